@@ -97,11 +97,14 @@ const config: PlaywrightTestConfig = {
   /* Folder for test artifacts such as screenshots, videos, traces, etc. */
   // outputDir: 'test-results/',
 
-  /* Run your local dev server before starting the tests */
-  // webServer: {
-  //   command: 'npm run start',
-  //   port: 3000,
-  // },
+  /* Run the fake modrinth api before starting the tests */
+  /* The app has to be started with MODRINTH_URL=http://127.0.0.1:4010/ to use it */
+  webServer: {
+    command: "cargo run --features fake-modrinth --bin fake-modrinth -- ../fixtures/modrinth --port 4010",
+    url: "http://127.0.0.1:4010/v3/collection/fixture1",
+    reuseExistingServer: true,
+    timeout: 5 * 60 * 1000,
+  },
 };
 
 export default config;
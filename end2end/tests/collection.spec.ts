@@ -0,0 +1,20 @@
+import { test, expect } from "@playwright/test";
+
+// runs against the fake modrinth api, see `fixtures/modrinth`
+
+test("collection page shows the best version", async ({ page }) => {
+  await page.goto("http://localhost:3000/collection/fixture1");
+
+  await expect(page).toHaveTitle("Fixture Pack - Mr Modpack");
+
+  await expect(page.locator(".collection-summary")).toContainText(
+    "Minecraft 1.20.1",
+  );
+  await expect(page.locator("th", { hasText: "Sodium" })).toBeVisible();
+});
+
+test("unknown collections show an error", async ({ page }) => {
+  await page.goto("http://localhost:3000/collection/nothere");
+
+  await expect(page.getByText("There was an error")).toBeVisible();
+});
@@ -0,0 +1,106 @@
+//! Working out which game versions the projects of a collection support.
+
+use std::collections::{HashMap, HashSet};
+
+use ferinth::structures::project::Project;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    modrinth::{Collection, ProjectKey},
+    SemanticVersion,
+};
+
+/// Everything needed to draw the compatibility matrix of a collection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectionData {
+    pub collection: Collection,
+    pub projects: Vec<(ProjectKey, Project)>,
+    /// Every game version supported by at least one project, most supported first
+    pub available_versions: Vec<(SemanticVersion, HashSet<ProjectKey>)>,
+    /// Whether exports that install the loader (server packs, Prism instances and mrpacks)
+    /// can be made, which needs loader versions that can be looked up
+    pub loader_exports: bool,
+}
+
+impl CollectionData {
+    /// Builds the matrix for the projects that support `loader`.
+    pub fn new(collection: Collection, projects: Vec<(ProjectKey, Project)>, loader: &str) -> Self {
+        let mut available_versions: HashMap<SemanticVersion, HashSet<ProjectKey>> = HashMap::new();
+
+        for (key, project) in projects.iter() {
+            if !project.loaders.iter().any(|l| l == loader) {
+                continue;
+            }
+
+            for version in project
+                .game_versions
+                .iter()
+                .filter_map(|v| v.parse::<SemanticVersion>().ok())
+            {
+                available_versions
+                    .entry(version)
+                    .and_modify(|projects| {
+                        projects.insert(key.clone());
+                    })
+                    .or_insert_with(|| {
+                        let mut p = HashSet::with_capacity(1);
+                        p.insert(key.clone());
+                        p
+                    });
+            }
+        }
+
+        CollectionData {
+            collection,
+            projects,
+            // newer versions win ties
+            available_versions: available_versions
+                .into_iter()
+                .sorted_by_key(|(version, projects)| (projects.len(), *version))
+                .rev()
+                .collect(),
+            loader_exports: true,
+        }
+    }
+
+    pub fn coverage(&self, projects: &HashSet<ProjectKey>) -> f64 {
+        (projects.len() as f64 / self.collection.projects.len() as f64) * 100.0
+    }
+
+    pub fn best_version(&self) -> Option<&(SemanticVersion, HashSet<ProjectKey>)> {
+        self.available_versions.first()
+    }
+}
+
+/// Finds a collection by id, checking uploaded packwiz packs before asking modrinth.
+#[cfg(feature = "ssr")]
+pub(crate) async fn resolve_collection(
+    api: &super::modrinth::ModrinthClient,
+    imports: &super::packwiz::PackwizImports,
+    id: &str,
+) -> super::modrinth::ApiResult<Collection> {
+    if let Some(collection) = imports.get(id).await {
+        return Ok(collection);
+    }
+    // imports that weren't saved are gone after a restart, and modrinth has never heard of them
+    if super::packwiz::PackwizImports::is_import(id) {
+        return Err(super::modrinth::ApiErr::ImportExpired);
+    }
+
+    api.get_collection(id).await
+}
+
+/// Looks up projects by key, fetching the ones that haven't been yet, like after a restart.
+#[cfg(feature = "ssr")]
+pub(crate) async fn lookup_projects(
+    api: &super::modrinth::ModrinthClient,
+    projects: &[ProjectKey],
+) -> super::modrinth::ApiResult<Vec<(ProjectKey, Project)>> {
+    let mut found = Vec::with_capacity(projects.len());
+    for key in projects {
+        found.push((key.clone(), api.project(key).await?));
+    }
+
+    Ok(found)
+}
@@ -0,0 +1,381 @@
+//! Jars kept on disk between exports, so popular mods aren't downloaded for every pack.
+//!
+//! Every jar is stored under its sha512, which modrinth lists for each file, so a cached
+//! jar is only ever used for the exact file it was downloaded as. Jars are hashed again
+//! before they're used, and thrown away if they don't match. Once the cache grows past its
+//! size limit, the jars that were used least recently are removed.
+
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use leptos::logging;
+use sha2::{Digest, Sha512};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::Mutex,
+};
+
+#[derive(Debug, Clone, Copy)]
+struct Blob {
+    size: u64,
+    last_used: SystemTime,
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    blobs: HashMap<String, Blob>,
+    total_size: u64,
+}
+
+#[derive(Debug)]
+pub struct JarCache {
+    dir: PathBuf,
+    max_size: u64,
+    index: Mutex<Index>,
+}
+
+/// Whether `hash` looks like a sha512, which also makes it safe to use as a file name.
+fn is_sha512(hash: &str) -> bool {
+    hash.len() == 128 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Hashes a file without reading it into memory.
+async fn hash_file(file: &mut File) -> io::Result<String> {
+    let mut hasher = Sha512::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+impl JarCache {
+    /// Opens the cache in `dir`, picking up the jars that are already there.
+    pub async fn open(dir: impl Into<PathBuf>, max_size: u64) -> io::Result<Self> {
+        let dir = dir.into();
+        tokio::fs::create_dir_all(&dir).await?;
+
+        let mut index = Index::default();
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_lowercase();
+            let metadata = entry.metadata().await?;
+
+            if !metadata.is_file() {
+                continue;
+            }
+            if !is_sha512(&name) {
+                // left over from a download that never finished
+                let _ = tokio::fs::remove_file(entry.path()).await;
+                continue;
+            }
+
+            index.total_size += metadata.len();
+            index.blobs.insert(
+                name,
+                Blob {
+                    size: metadata.len(),
+                    last_used: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                },
+            );
+        }
+
+        let cache = Self {
+            dir,
+            max_size,
+            index: Mutex::new(index),
+        };
+        cache.evict(&mut *cache.index.lock().await).await;
+
+        Ok(cache)
+    }
+
+    fn path(&self, sha512: &str) -> PathBuf {
+        self.dir.join(sha512)
+    }
+
+    /// Where to download a jar to before it's known to be good, see `insert`.
+    fn temp_path(&self) -> PathBuf {
+        self.dir
+            .join(format!("{}.part", uuid::Uuid::new_v4().simple()))
+    }
+
+    /// Starts writing a jar that's being downloaded into the cache.
+    pub(crate) async fn writer(&self) -> Option<CacheWriter<'_>> {
+        let path = self.temp_path();
+        match File::create(&path).await {
+            Ok(file) => Some(CacheWriter {
+                cache: self,
+                path,
+                file: Some(file),
+            }),
+            Err(err) => {
+                logging::warn!("couldn't write to the jar cache: {err}");
+                None
+            }
+        }
+    }
+
+    /// Opens the jar with `sha512`, if it's cached and still matches its hash.
+    pub(crate) async fn get(&self, sha512: &str) -> Option<File> {
+        let sha512 = sha512.to_lowercase();
+        if !is_sha512(&sha512) {
+            return None;
+        }
+
+        if !self.index.lock().await.blobs.contains_key(&sha512) {
+            return None;
+        }
+
+        // an evicted jar can still be read if it was opened first, so no need to hold the lock
+        let path = self.path(&sha512);
+        let verified = async {
+            let mut file = File::open(&path).await?;
+            let matches = hash_file(&mut file).await? == sha512;
+            file.rewind().await?;
+            io::Result::Ok(matches.then_some(file))
+        };
+        let verified = verified.await;
+
+        let mut index = self.index.lock().await;
+        match verified {
+            Ok(Some(file)) => {
+                let now = SystemTime::now();
+                if let Some(blob) = index.blobs.get_mut(&sha512) {
+                    blob.last_used = now;
+                }
+                // keeps the order across restarts
+                if let Ok(file) = file.try_clone().await {
+                    let _ = file.into_std().await.set_modified(now);
+                }
+                Some(file)
+            }
+            result => {
+                if let Err(err) = result {
+                    logging::warn!("couldn't read cached jar {sha512}: {err}");
+                } else {
+                    logging::warn!("cached jar {sha512} is corrupted, removing it");
+                }
+                if let Some(blob) = index.blobs.remove(&sha512) {
+                    index.total_size -= blob.size;
+                    let _ = tokio::fs::remove_file(path).await;
+                }
+                None
+            }
+        }
+    }
+
+    /// Moves a jar that was downloaded to `temp` (and checked against `sha512`) into the
+    /// cache, making room for it if needed.
+    pub(crate) async fn insert(&self, temp: &Path, sha512: &str) -> io::Result<()> {
+        let sha512 = sha512.to_lowercase();
+        let size = tokio::fs::metadata(temp).await?.len();
+
+        if !is_sha512(&sha512) || size > self.max_size {
+            return tokio::fs::remove_file(temp).await;
+        }
+
+        let mut index = self.index.lock().await;
+        tokio::fs::rename(temp, self.path(&sha512)).await?;
+
+        let blob = Blob {
+            size,
+            last_used: SystemTime::now(),
+        };
+        if let Some(old) = index.blobs.insert(sha512, blob) {
+            index.total_size -= old.size;
+        }
+        index.total_size += size;
+
+        self.evict(&mut index).await;
+
+        Ok(())
+    }
+
+    /// Removes the least recently used jars until the cache fits in its size limit.
+    async fn evict(&self, index: &mut Index) {
+        while index.total_size > self.max_size {
+            let Some(oldest) = index
+                .blobs
+                .iter()
+                .min_by_key(|(_, blob)| blob.last_used)
+                .map(|(sha512, _)| sha512.clone())
+            else {
+                break;
+            };
+
+            let blob = index.blobs.remove(&oldest).unwrap();
+            index.total_size -= blob.size;
+            if let Err(err) = tokio::fs::remove_file(self.path(&oldest)).await {
+                logging::warn!("couldn't evict cached jar {oldest}: {err}");
+            }
+        }
+    }
+}
+
+/// A jar being downloaded into the cache, which only ends up in it once it's `finish`ed.
+#[derive(Debug)]
+pub(crate) struct CacheWriter<'a> {
+    cache: &'a JarCache,
+    path: PathBuf,
+    /// Gone once writing failed, or the jar was finished
+    file: Option<File>,
+}
+
+impl CacheWriter<'_> {
+    /// Writes the next part of the jar. A full disk just means it won't be cached.
+    pub(crate) async fn write(&mut self, chunk: &[u8]) {
+        if let Some(file) = &mut self.file {
+            if let Err(err) = file.write_all(chunk).await {
+                logging::warn!("couldn't write to the jar cache: {err}");
+                self.file = None;
+                let _ = tokio::fs::remove_file(&self.path).await;
+            }
+        }
+    }
+
+    /// Adds the jar to the cache if all of it was written and it was verified to match
+    /// `sha512`, otherwise throws it away.
+    pub(crate) async fn finish(mut self, sha512: Option<&str>) {
+        let Some(mut file) = self.file.take() else {
+            return;
+        };
+
+        let result = match (file.flush().await, sha512) {
+            (Ok(()), Some(sha512)) => self.cache.insert(&self.path, sha512).await,
+            (Ok(()), None) => tokio::fs::remove_file(&self.path).await,
+            (Err(err), _) => Err(err),
+        };
+        if let Err(err) = result {
+            logging::warn!("couldn't add a jar to the cache: {err}");
+            let _ = tokio::fs::remove_file(&self.path).await;
+        }
+    }
+}
+
+impl Drop for CacheWriter<'_> {
+    fn drop(&mut self) {
+        // the download failed halfway
+        if self.file.is_some() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use sha2::{Digest, Sha512};
+    use tokio::io::AsyncReadExt;
+
+    use super::JarCache;
+    use crate::test_setup::temp_path;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = temp_path(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// Puts `contents` into the cache the way a download would.
+    async fn insert(cache: &JarCache, contents: &[u8]) -> String {
+        let sha512 = format!("{:x}", Sha512::digest(contents));
+        let mut writer = cache.writer().await.unwrap();
+        for chunk in contents.chunks(3) {
+            writer.write(chunk).await;
+        }
+        writer.finish(Some(&sha512)).await;
+        sha512
+    }
+
+    async fn read(cache: &JarCache, sha512: &str) -> Option<Vec<u8>> {
+        let mut contents = Vec::new();
+        cache
+            .get(sha512)
+            .await?
+            .read_to_end(&mut contents)
+            .await
+            .unwrap();
+        Some(contents)
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let dir = temp_dir("cache-round-trip");
+        let cache = JarCache::open(&dir, 1024).await.unwrap();
+
+        let sha512 = insert(&cache, b"a jar").await;
+        assert_eq!(read(&cache, &sha512).await.unwrap(), b"a jar");
+        assert_eq!(read(&cache, &"0".repeat(128)).await, None);
+        assert_eq!(read(&cache, "../../etc/passwd").await, None);
+
+        // a download that failed isn't kept
+        let mut writer = cache.writer().await.unwrap();
+        writer.write(b"half a j").await;
+        drop(writer);
+        // or was checked and didn't match
+        let mut writer = cache.writer().await.unwrap();
+        writer.write(b"not a jar").await;
+        writer.finish(None).await;
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // still there after a restart, and anything left over from a crash is cleaned up
+        tokio::fs::write(cache.temp_path(), b"half a j")
+            .await
+            .unwrap();
+        let cache = JarCache::open(&dir, 1024).await.unwrap();
+        assert_eq!(read(&cache, &sha512).await.unwrap(), b"a jar");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used() {
+        let dir = temp_dir("cache-evict");
+        let cache = JarCache::open(&dir, 10).await.unwrap();
+
+        let a = insert(&cache, b"aaaa").await;
+        let b = insert(&cache, b"bbbb").await;
+        // using a makes b the oldest
+        assert!(read(&cache, &a).await.is_some());
+        let c = insert(&cache, b"cccc").await;
+
+        assert!(read(&cache, &a).await.is_some());
+        assert!(read(&cache, &b).await.is_none());
+        assert!(read(&cache, &c).await.is_some());
+
+        // too big to ever fit
+        let d = insert(&cache, b"ddddddddddddddd").await;
+        assert!(read(&cache, &d).await.is_none());
+        assert!(read(&cache, &a).await.is_some());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn drops_corrupted_jars() {
+        let dir = temp_dir("cache-corrupt");
+        let cache = JarCache::open(&dir, 1024).await.unwrap();
+
+        let sha512 = insert(&cache, b"a jar").await;
+        tokio::fs::write(dir.join(&sha512), b"a jaz").await.unwrap();
+
+        assert_eq!(read(&cache, &sha512).await, None);
+        assert!(!dir.join(&sha512).exists());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
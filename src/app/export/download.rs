@@ -0,0 +1,161 @@
+//! Serving finished archives to whoever has their export id.
+//!
+//! Archives live in the exporter's private folder, not the site root, and are only
+//! reachable through the random id of the export (the job id for REST exports). Range
+//! requests are supported, so interrupted downloads can be resumed.
+
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Path, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+
+use super::Exporter;
+
+/// `attachment` with the filename, escaped for old browsers and percent-encoded for new ones.
+fn content_disposition(filename: &str) -> HeaderValue {
+    let fallback = filename
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c,
+            ' ' | '.' | '-' | '_' | '(' | ')' | '+' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    let encoded = filename
+        .bytes()
+        .map(|b| match b {
+            b if b.is_ascii_alphanumeric() || b"-._~".contains(&b) => (b as char).to_string(),
+            b => format!("%{b:02X}"),
+        })
+        .collect::<String>();
+
+    HeaderValue::from_str(&format!(
+        "attachment; filename=\"{fallback}\"; filename*=UTF-8''{encoded}"
+    ))
+    .unwrap()
+}
+
+/// Serves the archive of the export with `id`, honouring range and conditional headers.
+pub(crate) async fn serve_artifact(exporter: &Exporter, id: &str, request: Request) -> Response {
+    let Some(artifact) = exporter.artifact(id).await else {
+        return (StatusCode::NOT_FOUND, "this download has expired").into_response();
+    };
+
+    let mut response = match ServeFile::new(&artifact.path).oneshot(request).await {
+        Ok(response) => response,
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    if response.status().is_success() {
+        let headers = response.headers_mut();
+        headers.insert(
+            header::CONTENT_DISPOSITION,
+            content_disposition(&artifact.filename),
+        );
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("private"));
+    }
+
+    response.map(Body::new)
+}
+
+async fn download(
+    State(exporter): State<Arc<Exporter>>,
+    Path(id): Path<String>,
+    request: Request,
+) -> Response {
+    serve_artifact(&exporter, &id, request).await
+}
+
+/// Routes for downloading archives by export id, to be nested under the exporter's
+/// `url_prefix`.
+pub fn router<S>(exporter: Arc<Exporter>) -> Router<S> {
+    Router::new()
+        .route("/:id", get(download))
+        .with_state(exporter)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    use super::{content_disposition, router};
+    use crate::{
+        app::export::{
+            test_setup::{request, setup},
+            ExportFormat,
+        },
+        test_setup::temp_path,
+    };
+
+    #[test]
+    fn hostile_filenames() {
+        assert_eq!(
+            content_disposition("Fixture Pack.zip"),
+            "attachment; filename=\"Fixture Pack.zip\"; filename*=UTF-8''Fixture%20Pack.zip"
+        );
+        assert_eq!(
+            content_disposition("\"a\"\r\n/../b.zip"),
+            "attachment; filename=\"_a____.._b.zip\"; filename*=UTF-8''%22a%22%0D%0A%2F..%2Fb.zip"
+        );
+        assert_eq!(
+            content_disposition("Ünïcode.mrpack"),
+            "attachment; filename=\"_n_code.mrpack\"; filename*=UTF-8''%C3%9Cn%C3%AFcode.mrpack"
+        );
+    }
+
+    #[tokio::test]
+    async fn download_with_range() {
+        let (_, exporter, projects) = setup("fixture1").await;
+        let output = temp_path("downloads");
+        let exporter = Arc::new(exporter.with_output(&output, "/downloads"));
+
+        let report = exporter
+            .export("some-id", request(projects, ExportFormat::Mrpack))
+            .await
+            .unwrap();
+        assert_eq!(report.url, "/downloads/some-id");
+
+        let app = router::<()>(exporter);
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/some-id")
+                    .header(header::RANGE, "bytes=0-1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"fixture-pack.mrpack\"; filename*=UTF-8''fixture-pack.mrpack"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        // every zip starts with "PK"
+        assert_eq!(&body[..], b"PK");
+
+        let response = app
+            .oneshot(Request::get("/other-id").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let _ = std::fs::remove_dir_all(output);
+    }
+}
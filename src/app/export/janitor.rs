@@ -0,0 +1,279 @@
+//! Keeping the exporter's output folder from filling up.
+//!
+//! Archives expire `cleanup_delay` after they were built, and the janitor sweeps the folder
+//! every so often to remove them. If the archives that are left take up more than the
+//! quota, the oldest ones go early. Anything else that looks like an archive or a jar
+//! download but isn't being built or downloaded, like what a crash or restart left behind,
+//! is removed too.
+//! The first sweep happens as soon as the janitor starts.
+
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use leptos::logging;
+
+use super::{Artifact, ExportFormat, Exporter};
+
+/// An archive (or a jar going into one) being written, which is removed again unless it's
+/// `finish`ed.
+#[derive(Debug)]
+pub(super) struct Build {
+    building: Arc<Mutex<HashSet<PathBuf>>>,
+    path: PathBuf,
+    finished: bool,
+}
+
+impl Build {
+    pub(super) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Keeps the archive, now that it's complete.
+    pub(super) fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for Build {
+    fn drop(&mut self) {
+        self.building.lock().unwrap().remove(&self.path);
+
+        // the export failed or was cancelled halfway
+        if !self.finished {
+            remove(&self.path);
+        }
+    }
+}
+
+/// Removes a file, which is fine if it's already gone.
+fn remove(path: &Path) {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            logging::warn!("couldn't remove {}: {err}", path.display());
+        }
+        _ => {}
+    }
+}
+
+/// Whether the janitor should look after a file, which only holds for archives and the
+/// jars that are downloaded for them.
+fn is_export_file(path: &Path) -> bool {
+    let extensions = [
+        ExportFormat::ClientPack.extension(),
+        ExportFormat::ServerPack.extension(),
+        ExportFormat::Prism.extension(),
+        ExportFormat::Mrpack.extension(),
+        super::pack::PART_EXTENSION,
+    ];
+
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extensions.contains(&extension))
+}
+
+impl Exporter {
+    /// Marks the archive at `path` as being written, so sweeps leave it alone.
+    pub(super) fn start_build(&self, path: PathBuf) -> Build {
+        self.building.lock().unwrap().insert(path.clone());
+
+        Build {
+            building: self.building.clone(),
+            path,
+            finished: false,
+        }
+    }
+
+    pub(super) fn is_expired(&self, artifact: &Artifact, now: SystemTime) -> bool {
+        now.duration_since(artifact.created)
+            .is_ok_and(|age| age >= self.cleanup_delay)
+    }
+
+    /// Removes expired archives, then the oldest ones until the rest fit in `max_size`,
+    /// then whatever was left behind. An open file can still be read after it's removed,
+    /// so downloads in progress still finish.
+    pub async fn sweep(&self) {
+        let now = SystemTime::now();
+        let mut removed = Vec::new();
+
+        {
+            let mut artifacts = self.artifacts.write().await;
+            artifacts.retain(|_, artifact| {
+                let expired = self.is_expired(artifact, now);
+                if expired {
+                    removed.push(artifact.path.clone());
+                }
+                !expired
+            });
+
+            let mut total_size = artifacts
+                .values()
+                .map(|artifact| artifact.size)
+                .sum::<u64>();
+            while total_size > self.max_size {
+                let Some(oldest) = artifacts
+                    .iter()
+                    .min_by_key(|(_, artifact)| artifact.created)
+                    .map(|(id, _)| id.clone())
+                else {
+                    break;
+                };
+
+                let artifact = artifacts.remove(&oldest).unwrap();
+                total_size -= artifact.size;
+                removed.push(artifact.path);
+            }
+        }
+
+        for path in &removed {
+            remove(path);
+        }
+
+        let files = match self.list_files().await {
+            Ok(files) => files,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return,
+            Err(err) => {
+                logging::warn!("couldn't sweep {}: {err}", self.output_folder.display());
+                return;
+            }
+        };
+
+        // taken after listing the folder, so builds that start meanwhile are known
+        let artifacts = self.artifacts.read().await;
+        let building = self.building.lock().unwrap().clone();
+        let known = artifacts
+            .values()
+            .map(|artifact| &artifact.path)
+            .chain(&building)
+            .collect::<HashSet<_>>();
+
+        for path in files {
+            if is_export_file(&path) && !known.contains(&path) {
+                remove(&path);
+            }
+        }
+    }
+
+    async fn list_files(&self) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.output_folder).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_file() {
+                files.push(entry.path());
+            }
+        }
+        Ok(files)
+    }
+
+    /// Sweeps the output folder now, and then every `interval`.
+    pub fn spawn_janitor(self: Arc<Self>, interval: Duration) {
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                self.sweep().await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use crate::{
+        app::{
+            export::{test_setup::exporter, ExportFormat, Exporter},
+            modrinth::ModrinthClient,
+        },
+        test_setup::temp_path,
+    };
+
+    fn setup(name: &str) -> (Exporter, PathBuf) {
+        let dir = temp_path(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let exporter = exporter(Arc::new(ModrinthClient::default()))
+            .with_output(&dir, "/downloads")
+            .with_cleanup_delay(Duration::from_secs(60));
+
+        (exporter, dir)
+    }
+
+    /// An archive of `size` bytes, built `age` ago.
+    async fn archive(exporter: &Exporter, id: &str, size: usize, age: u64) {
+        let path = exporter.artifact_path(id, ExportFormat::Mrpack);
+        std::fs::write(path, vec![0; size]).unwrap();
+
+        let created = SystemTime::now() - Duration::from_secs(age);
+        assert!(
+            exporter
+                .restore_artifact(id, ExportFormat::Mrpack, format!("{id}.mrpack"), created)
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn removes_expired_and_leftovers() {
+        let (exporter, dir) = setup("janitor-expired");
+
+        archive(&exporter, "fresh", 1, 10).await;
+        archive(&exporter, "expired", 1, 90).await;
+        // from before a restart, and files that aren't archives
+        std::fs::write(dir.join("crashed.zip"), b"PK").unwrap();
+        std::fs::write(dir.join("crashed.part"), b"jar").unwrap();
+        std::fs::write(dir.join("jobs.json"), b"[]").unwrap();
+        // and an archive that's still being written, with a jar that's being downloaded
+        let build = exporter.start_build(exporter.artifact_path("building", ExportFormat::Prism));
+        std::fs::write(dir.join("building.zip"), b"PK").unwrap();
+        let download = exporter.start_build(dir.join("downloading.part"));
+        std::fs::write(dir.join("downloading.part"), b"jar").unwrap();
+
+        // expired archives can't be downloaded, even before they're swept
+        assert!(exporter.artifact("expired").await.is_none());
+
+        exporter.sweep().await;
+        assert!(exporter.artifact("fresh").await.is_some());
+        assert!(dir.join("fresh.mrpack").exists());
+        assert!(!dir.join("expired.mrpack").exists());
+        assert!(!dir.join("crashed.zip").exists());
+        assert!(!dir.join("crashed.part").exists());
+        assert!(dir.join("jobs.json").exists());
+        assert!(dir.join("building.zip").exists());
+        assert!(dir.join("downloading.part").exists());
+        drop(download);
+
+        // a build that's dropped without finishing failed
+        drop(build);
+        assert!(!dir.join("building.zip").exists());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn keeps_to_the_quota() {
+        let (exporter, dir) = setup("janitor-quota");
+        let exporter = exporter.with_max_size(10);
+
+        archive(&exporter, "oldest", 4, 30).await;
+        archive(&exporter, "older", 4, 20).await;
+        archive(&exporter, "newest", 4, 10).await;
+
+        exporter.sweep().await;
+        assert!(exporter.artifact("oldest").await.is_none());
+        assert!(!dir.join("oldest.mrpack").exists());
+        assert!(exporter.artifact("older").await.is_some());
+        assert!(exporter.artifact("newest").await.is_some());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
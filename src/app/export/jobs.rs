@@ -0,0 +1,412 @@
+//! Exports run in the background, polled by id from the web UI and the REST api.
+//!
+//! Jobs wait in a queue until one of a fixed number of workers is free, so a burst of
+//! users can't build unlimited archives at once. Asking for an export that's already
+//! queued, running, or finished (and still downloadable) gives back that job instead of
+//! starting another one. Jobs are saved to a json file, so finished ones can still be
+//! downloaded after a restart. Ones that were still running are marked as failed.
+
+use std::{
+    collections::HashMap,
+    io,
+    panic::AssertUnwindSafe,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::FutureExt as _;
+use itertools::Itertools;
+use leptos::logging;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{
+    sync::{Mutex, RwLock, Semaphore},
+    task::AbortHandle,
+};
+
+use super::{ExportJob, ExportJobState, ExportRequest, Exporter};
+
+/// How long a finished job can still be polled.
+const JOB_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JobRecord {
+    job: ExportJob,
+    /// Identifies what the job exports, see `idempotency_key`
+    key: String,
+    /// What the archive is saved as
+    filename: String,
+}
+
+#[derive(Debug)]
+struct Shared {
+    exporter: Arc<Exporter>,
+    /// Where jobs are saved, they're only kept in memory without it
+    path: Option<PathBuf>,
+    jobs: RwLock<HashMap<String, JobRecord>>,
+    /// The tasks of jobs that haven't finished, to cancel them
+    tasks: Mutex<HashMap<String, AbortHandle>>,
+    workers: Semaphore,
+}
+
+#[derive(Debug)]
+pub struct ExportJobs(Arc<Shared>);
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Everything that decides what an export produces, hashed.
+fn idempotency_key(request: &ExportRequest) -> String {
+    let project_ids = request.projects.iter().sorted().join(",");
+    let pinned = request
+        .settings
+        .pinned
+        .iter()
+        .sorted()
+        .map(|(project, version)| format!("{project}={version}"))
+        .join(",");
+
+    let key = format!(
+        "{}|{}|{:?}|{}|{:?}|{pinned}|{project_ids}",
+        request.collection_id,
+        request.release_version.to_game_version(),
+        request.format,
+        request.settings.loader,
+        request.settings.channel,
+    );
+    format!("{:x}", Sha256::digest(key))
+}
+
+impl Shared {
+    /// Writes every job back to disk, in one go so a crash can't leave the file half written.
+    async fn persist(&self, jobs: &HashMap<String, JobRecord>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let result = async {
+            let contents = serde_json::to_vec(&jobs.values().collect::<Vec<_>>())?;
+            let tmp = path.with_extension("tmp");
+            tokio::fs::write(&tmp, contents).await?;
+            tokio::fs::rename(&tmp, path).await
+        };
+        if let Err(err) = result.await {
+            logging::error!("couldn't save export jobs to {}: {err}", path.display());
+        }
+    }
+
+    /// Moves a job along, unless it was cancelled or has already finished.
+    async fn set_state(&self, id: &str, state: ExportJobState) {
+        let mut jobs = self.jobs.write().await;
+        let Some(record) = jobs.get_mut(id) else {
+            return;
+        };
+        if record.job.is_done() {
+            return;
+        }
+
+        record.job.state = state;
+        self.persist(&jobs).await;
+    }
+
+    async fn run(self: Arc<Self>, id: String, request: ExportRequest) {
+        // waits in the queue until a worker is free
+        let _worker = self.workers.acquire().await.unwrap();
+        self.set_state(&id, ExportJobState::Running).await;
+
+        // the archive is downloaded with the job's id. a panic is caught here rather than
+        // in a task of its own, so cancelling the job still aborts the export
+        let export = AssertUnwindSafe(self.exporter.export(&id, request)).catch_unwind();
+        let state = match export.await {
+            Ok(Ok(report)) => ExportJobState::Finished { report },
+            Ok(Err(err)) => ExportJobState::Failed {
+                error: err.to_string(),
+            },
+            Err(_) => {
+                logging::error!("export {id} panicked");
+                ExportJobState::Failed {
+                    error: "the export crashed".to_string(),
+                }
+            }
+        };
+        self.set_state(&id, state).await;
+
+        self.tasks.lock().await.remove(&id);
+    }
+}
+
+impl ExportJobs {
+    /// Jobs that are only kept in memory, running `workers` exports at a time.
+    pub fn new(exporter: Arc<Exporter>, workers: usize) -> Self {
+        Self(Arc::new(Shared {
+            exporter,
+            path: None,
+            jobs: Default::default(),
+            tasks: Default::default(),
+            workers: Semaphore::new(workers),
+        }))
+    }
+
+    /// Jobs saved in the json file at `path`, picking up the ones from before a restart.
+    pub async fn open(
+        exporter: Arc<Exporter>,
+        workers: usize,
+        path: impl Into<PathBuf>,
+    ) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+
+        let records: Vec<JobRecord> = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+
+        let cutoff = now().saturating_sub(JOB_RETENTION.as_secs());
+        let mut jobs = HashMap::with_capacity(records.len());
+        for mut record in records {
+            if record.job.created < cutoff {
+                continue;
+            }
+
+            match &record.job.state {
+                ExportJobState::Pending | ExportJobState::Running => {
+                    record.job.state = ExportJobState::Failed {
+                        error: "the server restarted before the export finished".to_string(),
+                    };
+                }
+                ExportJobState::Finished { .. } => {
+                    let created = UNIX_EPOCH + Duration::from_secs(record.job.created);
+                    exporter
+                        .restore_artifact(
+                            &record.job.id,
+                            record.job.format,
+                            record.filename.clone(),
+                            created,
+                        )
+                        .await;
+                }
+                ExportJobState::Failed { .. } | ExportJobState::Cancelled => {}
+            }
+
+            jobs.insert(record.job.id.clone(), record);
+        }
+
+        let shared = Shared {
+            exporter,
+            path: Some(path),
+            jobs: RwLock::new(jobs),
+            tasks: Default::default(),
+            workers: Semaphore::new(workers),
+        };
+        shared.persist(&*shared.jobs.read().await).await;
+
+        Ok(Self(Arc::new(shared)))
+    }
+
+    /// Queues an export and returns its job, or the job of the same export if it's already
+    /// queued, running, or can still be downloaded.
+    pub(crate) async fn start(&self, request: ExportRequest) -> ExportJob {
+        let key = idempotency_key(&request);
+
+        let mut jobs = self.0.jobs.write().await;
+
+        let cutoff = now().saturating_sub(JOB_RETENTION.as_secs());
+        jobs.retain(|_, record| !record.job.is_done() || record.job.created >= cutoff);
+
+        for record in jobs.values().filter(|record| record.key == key) {
+            let reusable = match record.job.state {
+                ExportJobState::Pending | ExportJobState::Running => true,
+                ExportJobState::Finished { .. } => {
+                    self.0.exporter.artifact(&record.job.id).await.is_some()
+                }
+                ExportJobState::Failed { .. } | ExportJobState::Cancelled => false,
+            };
+            if reusable {
+                return record.job.clone();
+            }
+        }
+
+        let job = ExportJob {
+            id: uuid::Uuid::new_v4().to_string(),
+            collection: request.collection_id.clone(),
+            game_version: request.release_version.to_game_version(),
+            format: request.format,
+            created: now(),
+            state: ExportJobState::Pending,
+        };
+        jobs.insert(
+            job.id.clone(),
+            JobRecord {
+                job: job.clone(),
+                key,
+                filename: request.filename(),
+            },
+        );
+        self.0.persist(&jobs).await;
+
+        // the task is registered before the lock is released, so it can't finish first
+        let task = tokio::task::spawn(self.0.clone().run(job.id.clone(), request));
+        self.0
+            .tasks
+            .lock()
+            .await
+            .insert(job.id.clone(), task.abort_handle());
+
+        job
+    }
+
+    /// What the jobs export with, to resolve packs without building them.
+    pub(crate) fn exporter(&self) -> &Exporter {
+        &self.0.exporter
+    }
+
+    pub(crate) async fn get(&self, id: &str) -> Option<ExportJob> {
+        self.0
+            .jobs
+            .read()
+            .await
+            .get(id)
+            .map(|record| record.job.clone())
+    }
+
+    /// Stops a job that hasn't finished yet. Returns the job as it is afterwards, which is
+    /// unchanged if it had already finished.
+    pub(crate) async fn cancel(&self, id: &str) -> Option<ExportJob> {
+        let mut jobs = self.0.jobs.write().await;
+        let record = jobs.get_mut(id)?;
+        if record.job.is_done() {
+            return Some(record.job.clone());
+        }
+
+        // the aborted export removes whatever it had written
+        if let Some(task) = self.0.tasks.lock().await.remove(id) {
+            task.abort();
+        }
+        record.job.state = ExportJobState::Cancelled;
+        let job = record.job.clone();
+        self.0.persist(&jobs).await;
+
+        Some(job)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Arc, time::Duration};
+
+    use super::ExportJobs;
+    use crate::{
+        app::{
+            export::{
+                test_setup::{request, setup as fixture},
+                ExportFormat, ExportJob, ExportJobState, Exporter,
+            },
+            modrinth::ProjectKey,
+        },
+        test_setup::temp_path,
+    };
+
+    async fn setup(output: &str) -> (Arc<Exporter>, HashSet<ProjectKey>) {
+        let (_, exporter, projects) = fixture("fixture1").await;
+        let exporter = exporter.with_output(temp_path(output), "/downloads");
+        (Arc::new(exporter), projects)
+    }
+
+    async fn wait(jobs: &ExportJobs, id: &str) -> ExportJob {
+        loop {
+            let job = jobs.get(id).await.unwrap();
+            if job.is_done() {
+                return job;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn same_export_same_job() {
+        let (exporter, projects) = setup("jobs-idempotent").await;
+        let jobs = ExportJobs::new(exporter, 2);
+
+        let first = jobs
+            .start(request(projects.clone(), ExportFormat::Mrpack))
+            .await;
+        let again = jobs
+            .start(request(projects.clone(), ExportFormat::Mrpack))
+            .await;
+        assert_eq!(first.id, again.id);
+
+        let other = jobs
+            .start(request(projects.clone(), ExportFormat::Prism))
+            .await;
+        assert_ne!(first.id, other.id);
+
+        // and once it's finished
+        assert!(matches!(
+            wait(&jobs, &first.id).await.state,
+            ExportJobState::Finished { .. }
+        ));
+        let finished = jobs.start(request(projects, ExportFormat::Mrpack)).await;
+        assert_eq!(first.id, finished.id);
+
+        let _ = std::fs::remove_dir_all(temp_path("jobs-idempotent"));
+    }
+
+    #[tokio::test]
+    async fn cancel_queued() {
+        let (exporter, projects) = setup("jobs-cancel").await;
+        // no workers, so jobs stay queued
+        let jobs = ExportJobs::new(exporter, 0);
+
+        let job = jobs
+            .start(request(projects, ExportFormat::ClientPack))
+            .await;
+        assert_eq!(job.state, ExportJobState::Pending);
+
+        let cancelled = jobs.cancel(&job.id).await.unwrap();
+        assert_eq!(cancelled.state, ExportJobState::Cancelled);
+        assert_eq!(
+            jobs.get(&job.id).await.unwrap().state,
+            ExportJobState::Cancelled
+        );
+        assert!(jobs.cancel("nothere").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn survives_restarts() {
+        let path = temp_path("jobs.json");
+        let _ = std::fs::remove_file(&path);
+
+        let (exporter, projects) = setup("jobs-restart").await;
+        let jobs = ExportJobs::open(exporter.clone(), 1, &path).await.unwrap();
+        let finished = jobs
+            .start(request(projects.clone(), ExportFormat::Mrpack))
+            .await;
+        let finished = wait(&jobs, &finished.id).await;
+        drop(jobs);
+
+        // a job that was interrupted
+        let jobs = ExportJobs::open(exporter.clone(), 0, &path).await.unwrap();
+        let interrupted = jobs.start(request(projects, ExportFormat::Prism)).await;
+        drop(jobs);
+
+        let (restarted, _) = setup("jobs-restart").await;
+        let jobs = ExportJobs::open(restarted.clone(), 1, &path).await.unwrap();
+        assert_eq!(jobs.get(&finished.id).await.unwrap(), finished);
+        assert!(restarted.artifact(&finished.id).await.is_some());
+        assert!(matches!(
+            jobs.get(&interrupted.id).await.unwrap().state,
+            ExportJobState::Failed { .. }
+        ));
+
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_dir_all(temp_path("jobs-restart"));
+    }
+}
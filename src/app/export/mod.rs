@@ -0,0 +1,275 @@
+#[cfg(feature = "ssr")]
+mod cache;
+#[cfg(feature = "ssr")]
+pub mod download;
+#[cfg(feature = "ssr")]
+mod janitor;
+#[cfg(feature = "ssr")]
+mod jobs;
+#[cfg(feature = "ssr")]
+mod mrpack;
+#[cfg(feature = "ssr")]
+mod pack;
+#[cfg(feature = "ssr")]
+mod prism;
+#[cfg(feature = "ssr")]
+mod server;
+
+#[cfg(feature = "ssr")]
+pub use cache::JarCache;
+#[cfg(feature = "ssr")]
+pub use jobs::*;
+#[cfg(feature = "ssr")]
+pub(crate) use mrpack::*;
+#[cfg(feature = "ssr")]
+pub use pack::*;
+#[cfg(feature = "ssr")]
+pub(crate) use prism::*;
+#[cfg(feature = "ssr")]
+pub(crate) use server::*;
+
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    modrinth::{ChannelPolicy, ProjectKey},
+    workspace::CollectionSettings,
+};
+
+/// The kind of archive an export produces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ExportFormat {
+    /// Every client mod jar at the root of a zip
+    ClientPack,
+    /// The mods a dedicated server can run, plus a README on installing the loader
+    ServerPack,
+    /// A Prism Launcher / MultiMC instance that can be imported as is
+    Prism,
+    /// A Modrinth `.mrpack`, which links to the jars instead of including them
+    Mrpack,
+}
+
+impl ExportFormat {
+    /// The folder inside the archive that mod jars are written to.
+    pub fn mods_dir(&self) -> &'static str {
+        match self {
+            ExportFormat::ClientPack => "",
+            ExportFormat::ServerPack => "mods/",
+            ExportFormat::Prism => ".minecraft/mods/",
+            ExportFormat::Mrpack => "mods/",
+        }
+    }
+
+    /// The side mods have to support to be included,
+    /// `None` if the archive is installed on both.
+    pub fn side(&self) -> Option<PackSide> {
+        match self {
+            ExportFormat::ClientPack | ExportFormat::Prism => Some(PackSide::Client),
+            ExportFormat::ServerPack => Some(PackSide::Server),
+            ExportFormat::Mrpack => None,
+        }
+    }
+
+    /// Whether the archive installs the loader, which needs its version looked up.
+    pub fn needs_loader_version(&self) -> bool {
+        *self != ExportFormat::ClientPack
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Mrpack => "mrpack",
+            _ => "zip",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(ExportFormat::ClientPack),
+            "server" => Ok(ExportFormat::ServerPack),
+            "prism" => Ok(ExportFormat::Prism),
+            "mrpack" => Ok(ExportFormat::Mrpack),
+            other => Err(format!(
+                "unknown format {other}, expected client, server, prism or mrpack"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PackSide {
+    Client,
+    Server,
+}
+
+impl Display for PackSide {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PackSide::Client => write!(f, "client"),
+            PackSide::Server => write!(f, "server"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExclusionReason {
+    /// The project says it can't run on this side
+    Unsupported(PackSide),
+    /// There is no version of the project for the game version and loader
+    NoVersion(String),
+    /// There are versions, but none on a release channel the policy allows
+    Channel(ChannelPolicy),
+    /// The version that was picked has no files to download
+    NoFiles(String),
+}
+
+impl Display for ExclusionReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExclusionReason::Unsupported(side) => write!(f, "{side}_side is unsupported"),
+            ExclusionReason::NoVersion(game_version) => {
+                write!(f, "no version for {game_version}")
+            }
+            ExclusionReason::Channel(channel) => {
+                write!(f, "no version allowed by the {channel} channel policy")
+            }
+            ExclusionReason::NoFiles(version_number) => {
+                write!(f, "version {version_number} has no files")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExcludedMod {
+    pub project_id: String,
+    pub title: String,
+    pub reason: ExclusionReason,
+}
+
+/// What an export produced, and what it had to leave out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportReport {
+    pub url: String,
+    pub excluded: Vec<ExcludedMod>,
+    /// How many requests to modrinth failed and had to be sent again
+    #[serde(default)]
+    pub retries: u32,
+}
+
+/// A mod as it was shipped in an exported pack.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestMod {
+    pub project_id: String,
+    pub title: String,
+    pub version_id: String,
+    pub version_number: String,
+    pub filename: String,
+    pub sha1: String,
+    pub sha512: String,
+}
+
+/// What went into an exported pack, written into every archive as `mr-modpack.json`.
+/// Uploaded again, it's what the pack is checked for updates against.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackManifest {
+    pub collection: String,
+    pub collection_name: String,
+    pub game_version: String,
+    pub format: ExportFormat,
+    pub settings: CollectionSettings,
+    /// The projects that were picked for the export, their dependencies aren't listed
+    pub projects: Vec<ProjectKey>,
+    /// Everything that was shipped, dependencies included
+    pub mods: Vec<ManifestMod>,
+}
+
+impl PackManifest {
+    pub const FILENAME: &'static str = "mr-modpack.json";
+}
+
+/// Where an export job is at. Jobs wait in a queue until a worker is free.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum ExportJobState {
+    Pending,
+    Running,
+    Finished { report: ExportReport },
+    Failed { error: String },
+    Cancelled,
+}
+
+/// An export running in the background, which is polled by its id.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportJob {
+    pub id: String,
+    pub collection: String,
+    pub game_version: String,
+    pub format: ExportFormat,
+    /// Seconds since the unix epoch
+    pub created: u64,
+    #[serde(flatten)]
+    pub state: ExportJobState,
+}
+
+impl ExportJob {
+    pub fn is_done(&self) -> bool {
+        !matches!(
+            self.state,
+            ExportJobState::Pending | ExportJobState::Running
+        )
+    }
+}
+
+/// What the tests of exports, and of what's built on them, start from.
+#[cfg(all(test, feature = "ssr"))]
+pub(crate) mod test_setup {
+    use std::{collections::HashSet, sync::Arc};
+
+    use super::{ExportFormat, ExportRequest, Exporter};
+    use crate::{
+        app::{
+            loader_meta::FixtureLoaderMetadata,
+            modrinth::{ModrinthClient, ProjectKey},
+        },
+        test_setup::fake_api,
+    };
+
+    /// An exporter with the loader versions from the fixtures.
+    pub(crate) fn exporter(api: Arc<ModrinthClient>) -> Exporter {
+        let loader_meta =
+            Arc::new(FixtureLoaderMetadata::from_file("fixtures/loader-meta.json").unwrap());
+        Exporter::new(api, loader_meta)
+    }
+
+    /// A client for a fake api serving the fixtures, an exporter using it, and the projects
+    /// of `collection`.
+    pub(crate) async fn setup(
+        collection: &str,
+    ) -> (Arc<ModrinthClient>, Exporter, HashSet<ProjectKey>) {
+        let api = Arc::new(fake_api().await);
+        let collection = api.get_collection(collection).await.unwrap();
+
+        (
+            api.clone(),
+            exporter(api),
+            collection.projects.into_iter().collect(),
+        )
+    }
+
+    /// An export of the fixture pack for 1.20.1, with the default settings.
+    pub(crate) fn request(projects: HashSet<ProjectKey>, format: ExportFormat) -> ExportRequest {
+        ExportRequest {
+            collection_id: "fixture1".to_string(),
+            collection_name: "Fixture Pack".to_string(),
+            release_version: "1.20.1".parse().unwrap(),
+            settings: Default::default(),
+            projects,
+            format,
+        }
+    }
+}
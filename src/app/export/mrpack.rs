@@ -0,0 +1,110 @@
+//! The index that makes a zip a Modrinth modpack (`.mrpack`).
+//!
+//! See <https://support.modrinth.com/en/articles/8802351-modrinth-modpack-format-mrpack>.
+
+use std::collections::BTreeMap;
+
+use ferinth::structures::project::ProjectSupportRange;
+use serde::Serialize;
+
+use super::ResolvedMod;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ModrinthIndex<'a> {
+    format_version: u32,
+    game: &'static str,
+    version_id: &'a str,
+    name: &'a str,
+    files: Vec<IndexFile<'a>>,
+    dependencies: BTreeMap<&'a str, &'a str>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexFile<'a> {
+    path: String,
+    hashes: IndexHashes<'a>,
+    env: IndexEnv,
+    downloads: Vec<&'a str>,
+    file_size: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct IndexHashes<'a> {
+    sha1: &'a str,
+    sha512: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct IndexEnv {
+    client: &'static str,
+    server: &'static str,
+}
+
+fn env(support: &ProjectSupportRange) -> &'static str {
+    match support {
+        ProjectSupportRange::Optional => "optional",
+        ProjectSupportRange::Unsupported => "unsupported",
+        // launchers skip anything that isn't required by default,
+        // so a mod that doesn't say gets installed to be safe
+        ProjectSupportRange::Required | ProjectSupportRange::Unknown => "required",
+    }
+}
+
+/// The id a loader goes by in the `dependencies` of an index.
+fn loader_dependency(loader: &str) -> &str {
+    match loader {
+        "fabric" => "fabric-loader",
+        "quilt" => "quilt-loader",
+        other => other,
+    }
+}
+
+/// The version of the pack itself, which is when it was exported like `2024.01.31-1200`,
+/// since a collection has no versions of its own.
+pub(crate) fn pack_version(time: std::time::SystemTime) -> String {
+    ferinth::structures::UtcTime::from(time)
+        .format("%Y.%m.%d-%H%M")
+        .to_string()
+}
+
+pub(crate) fn modrinth_index_json(
+    name: &str,
+    version_id: &str,
+    game_version: &str,
+    loader: &str,
+    loader_version: &str,
+    mods: &[ResolvedMod],
+) -> String {
+    let files = mods
+        .iter()
+        .map(|m| IndexFile {
+            path: format!("mods/{}", m.file.filename),
+            hashes: IndexHashes {
+                sha1: &m.file.hashes.sha1,
+                sha512: &m.file.hashes.sha512,
+            },
+            env: IndexEnv {
+                client: env(&m.project.client_side),
+                server: env(&m.project.server_side),
+            },
+            downloads: vec![m.file.url.as_str()],
+            file_size: m.file.size,
+        })
+        .collect();
+
+    let index = ModrinthIndex {
+        format_version: 1,
+        game: "minecraft",
+        version_id,
+        name,
+        files,
+        dependencies: BTreeMap::from([
+            ("minecraft", game_version),
+            (loader_dependency(loader), loader_version),
+        ]),
+    };
+
+    serde_json::to_string_pretty(&index).unwrap()
+}
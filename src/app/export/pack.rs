@@ -0,0 +1,1009 @@
+//! Building the pack archives themselves, for export jobs and the command line.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use async_zip::{base::write::ZipFileWriter, Compression, ZipEntryBuilder};
+use ferinth::structures::{
+    project::{Project, ProjectSupportRange},
+    version::{DependencyType, Version, VersionFile},
+};
+use futures::{AsyncWrite, AsyncWriteExt as _};
+use itertools::Itertools;
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    sync::RwLock,
+};
+
+use super::{
+    ExcludedMod, ExclusionReason, ExportFormat, ExportReport, JarCache, ManifestMod, PackManifest,
+    PackSide,
+};
+use crate::app::{
+    graph::{DependencyGraph, GraphEdge},
+    loader_meta::{LoaderMetaErr, LoaderMetadata},
+    modrinth::{count_retries, ApiErr, ModrinthClient, ProjectKey},
+    workspace::CollectionSettings,
+    SemanticVersion,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ExportErr {
+    #[error("{0}")]
+    Api(ApiErr),
+    #[error("zip error: {0}")]
+    Zip(async_zip::error::ZipError),
+    #[error("io error: {0}")]
+    Io(std::io::Error),
+    #[error("{0}")]
+    LoaderMeta(LoaderMetaErr),
+    #[error("{0} doesn't match its hash, it may have been corrupted")]
+    Integrity(String),
+    #[error("{0} versions can't be looked up, so only client packs can be exported for it")]
+    NoLoaderMeta(String),
+}
+
+pub(crate) type ExportResult<T> = Result<T, ExportErr>;
+
+/// What jars being downloaded for an archive end in, until they're in it.
+pub(super) const PART_EXTENSION: &str = "part";
+
+/// Everything that decides what ends up in an exported archive.
+#[derive(Debug, Clone)]
+pub(crate) struct ExportRequest {
+    pub collection_id: String,
+    pub collection_name: String,
+    pub release_version: SemanticVersion,
+    pub settings: CollectionSettings,
+    pub projects: HashSet<ProjectKey>,
+    pub format: ExportFormat,
+}
+
+impl ExportRequest {
+    /// What the archive is saved as when it's downloaded.
+    pub(crate) fn filename(&self) -> String {
+        format!(
+            "{}.{}",
+            slug(&self.collection_name),
+            self.format.extension()
+        )
+    }
+
+    /// The export that made the pack described by `manifest`, which picks the newest versions
+    /// when it's run again. `None` if the manifest's game version can't be read.
+    pub(crate) fn from_manifest(manifest: &PackManifest) -> Option<Self> {
+        Some(Self {
+            collection_id: manifest.collection.clone(),
+            collection_name: manifest.collection_name.clone(),
+            release_version: manifest.game_version.parse().ok()?,
+            settings: manifest.settings.clone(),
+            projects: manifest.projects.iter().cloned().collect(),
+            format: manifest.format,
+        })
+    }
+
+    /// Describes the pack this request resolved to.
+    pub(crate) fn manifest(&self, resolution: &Resolution) -> PackManifest {
+        PackManifest {
+            collection: self.collection_id.clone(),
+            collection_name: self.collection_name.clone(),
+            game_version: self.release_version.to_game_version(),
+            format: self.format,
+            settings: self.settings.clone(),
+            projects: self.projects.iter().cloned().sorted().collect(),
+            mods: resolution
+                .mods
+                .iter()
+                .map(ResolvedMod::manifest_mod)
+                .sorted_by(|a, b| a.project_id.cmp(&b.project_id))
+                .collect(),
+        }
+    }
+}
+
+/// Names that windows won't create a file under, whatever the extension.
+const RESERVED_NAMES: &[&str] = &[
+    "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8",
+    "com9", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// Turns a collection name into something that's safe to use as a file name anywhere:
+/// lowercase letters and digits, with everything else collapsed into single dashes.
+pub(crate) fn slug(name: &str) -> String {
+    let slug = name
+        .chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .dedup_by(|a, b| *a == '-' && *b == '-')
+        .take(64)
+        .collect::<String>();
+    let slug = slug.trim_matches('-');
+
+    if slug.is_empty() {
+        "modpack".to_string()
+    } else if RESERVED_NAMES.contains(&slug) {
+        format!("{slug}-pack")
+    } else {
+        slug.to_string()
+    }
+}
+
+/// A mod picked for an export, along with the file of the version that was picked.
+#[derive(Debug, Clone)]
+pub(crate) struct ResolvedMod {
+    pub project: Project,
+    pub version: Version,
+    pub file: VersionFile,
+}
+
+impl ResolvedMod {
+    pub(crate) fn manifest_mod(&self) -> ManifestMod {
+        ManifestMod {
+            project_id: self.project.id.clone(),
+            title: self.project.title.clone(),
+            version_id: self.version.id.clone(),
+            version_number: self.version.version_number.clone(),
+            filename: self.file.filename.clone(),
+            sha1: self.file.hashes.sha1.clone(),
+            sha512: self.file.hashes.sha512.clone(),
+        }
+    }
+}
+
+/// Every mod that goes into an export once dependencies have been followed.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Resolution {
+    pub mods: Vec<ResolvedMod>,
+    pub excluded: Vec<ExcludedMod>,
+    /// Every project that was looked at and how they depend on each other
+    pub graph: DependencyGraph,
+    /// How many requests failed and had to be sent again
+    pub retries: u32,
+}
+
+/// A finished archive waiting to be downloaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Artifact {
+    pub path: PathBuf,
+    /// What the browser should save it as
+    pub filename: String,
+    /// When it was built, it expires `cleanup_delay` after
+    pub created: SystemTime,
+    pub size: u64,
+}
+
+/// Builds exports, and keeps the ones made for the web in a private folder until they're
+/// downloaded from `url_prefix`, see `download::router`.
+#[derive(Debug)]
+pub struct Exporter {
+    api: Arc<ModrinthClient>,
+    loader_meta: Arc<dyn LoaderMetadata>,
+    pub(super) output_folder: PathBuf,
+    url_prefix: String,
+    pub(super) cleanup_delay: Duration,
+    /// How much space finished archives can take up together, in bytes
+    pub(super) max_size: u64,
+    /// Finished archives, by export id
+    pub(super) artifacts: RwLock<HashMap<String, Artifact>>,
+    /// Archives that are still being written, see `Build`
+    pub(super) building: Arc<std::sync::Mutex<HashSet<PathBuf>>>,
+    cache: Option<Arc<JarCache>>,
+}
+
+impl Exporter {
+    pub fn new(api: Arc<ModrinthClient>, loader_meta: Arc<dyn LoaderMetadata>) -> Self {
+        Self {
+            api,
+            loader_meta,
+            output_folder: std::env::temp_dir().join("mr-modpack-exports"),
+            url_prefix: "/downloads".to_string(),
+            cleanup_delay: Duration::from_secs(2 * 60),
+            max_size: u64::MAX,
+            artifacts: Default::default(),
+            building: Default::default(),
+            cache: None,
+        }
+    }
+
+    /// Where `export` writes archives to, and the url `download::router` is nested under.
+    pub fn with_output(mut self, output_folder: impl Into<PathBuf>, url_prefix: &str) -> Self {
+        self.output_folder = output_folder.into();
+        self.url_prefix = url_prefix.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// How long archives made by `export` can be downloaded for. Downloads that have started
+    /// by then still finish.
+    pub fn with_cleanup_delay(mut self, cleanup_delay: Duration) -> Self {
+        self.cleanup_delay = cleanup_delay;
+        self
+    }
+
+    /// How many bytes the archives made by `export` can take up together before the oldest
+    /// ones are removed early. There's no limit by default.
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Checks that `format` can be exported for `loader`, before it's queued and fails later.
+    pub(crate) fn check_loader(&self, loader: &str, format: ExportFormat) -> ExportResult<()> {
+        if format.needs_loader_version() && !self.loader_meta.supports(loader) {
+            return Err(ExportErr::NoLoaderMeta(loader.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Whether exports that install `loader` can be made, see [`Exporter::check_loader`].
+    pub(crate) fn supports_loader(&self, loader: &str) -> bool {
+        self.loader_meta.supports(loader)
+    }
+
+    /// Keeps downloaded jars in `cache`, and takes them from there when they're needed again.
+    pub fn with_cache(mut self, cache: Arc<JarCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Picks a version of every requested project and of their required dependencies,
+    /// without downloading anything.
+    pub(crate) async fn resolve(&self, request: &ExportRequest) -> ExportResult<Resolution> {
+        let ExportRequest {
+            release_version,
+            settings,
+            projects,
+            format,
+            ..
+        } = request;
+        let format = *format;
+        let api = &self.api;
+
+        let game_version = release_version.to_game_version();
+        let game_versions: &[&str] = &[&game_version];
+        let loader = settings.loader.as_str();
+        let loaders: &[&str] = &[loader];
+
+        let mut downloaded = HashSet::new();
+        let mut excluded: Vec<ExcludedMod> = Vec::new();
+        let mut mods = Vec::new();
+        let mut graph = DependencyGraph::default();
+
+        let mut todo = projects.iter().map(|p| (p.clone(), 0)).collect_vec();
+
+        while let Some((project, ident)) = todo.pop() {
+            let project = api.project(&project).await.map_err(ExportErr::Api)?;
+            let node = graph.node_mut(&project.id, &project.title);
+            // a mod of the collection can also be a dependency of another
+            node.top_level |= ident == 0;
+
+            if downloaded.contains(&project.id) {
+                continue;
+            }
+
+            if excluded.iter().any(|e| e.project_id == project.id) {
+                continue;
+            }
+
+            if let Some(side) = format.side() {
+                let support = match side {
+                    PackSide::Client => &project.client_side,
+                    PackSide::Server => &project.server_side,
+                };
+                if *support == ProjectSupportRange::Unsupported {
+                    excluded.push(ExcludedMod {
+                        project_id: project.id.clone(),
+                        title: project.title.clone(),
+                        reason: ExclusionReason::Unsupported(side),
+                    });
+                    continue;
+                }
+            }
+
+            // a pinned version is used as long as it's actually for this game version and loader
+            let pinned = match settings.pinned.get(&project.id) {
+                Some(version_id) => {
+                    Some(api.get_version(version_id).await.map_err(ExportErr::Api)?).filter(|v| {
+                        v.game_versions.contains(&game_version)
+                            && v.loaders.iter().any(|l| l == loader)
+                    })
+                }
+                None => None,
+            };
+
+            let versions = match pinned {
+                Some(pinned) => vec![pinned],
+                None => {
+                    let versions = api
+                        .get_project_versions(&project.slug, loaders, game_versions)
+                        .await
+                        .map_err(ExportErr::Api)?;
+
+                    if versions.is_empty() {
+                        excluded.push(ExcludedMod {
+                            project_id: project.id.clone(),
+                            title: project.title.clone(),
+                            reason: ExclusionReason::NoVersion(game_version.clone()),
+                        });
+                        continue;
+                    }
+
+                    let versions = versions
+                        .into_iter()
+                        .filter(|v| settings.channel.allows(&v.version_type))
+                        .collect_vec();
+
+                    if versions.is_empty() {
+                        excluded.push(ExcludedMod {
+                            project_id: project.id.clone(),
+                            title: project.title.clone(),
+                            reason: ExclusionReason::Channel(settings.channel),
+                        });
+                        continue;
+                    }
+
+                    versions
+                }
+            };
+
+            let latest_version = versions
+                .into_iter()
+                .max_by_key(|v| v.date_published)
+                .unwrap();
+
+            let Some(primary_file) = latest_version
+                .files
+                .iter()
+                .find(|f| f.primary)
+                .or(latest_version.files.first())
+            else {
+                excluded.push(ExcludedMod {
+                    project_id: project.id.clone(),
+                    title: project.title.clone(),
+                    reason: ExclusionReason::NoFiles(latest_version.version_number.clone()),
+                });
+                continue;
+            };
+
+            let file = primary_file.clone();
+
+            downloaded.insert(project.id.clone());
+            let node = graph.node_mut(&project.id, &project.title);
+            node.version_id = Some(latest_version.id.clone());
+            node.version_number = Some(latest_version.version_number.clone());
+
+            for dep in &latest_version.dependencies {
+                // dependencies on a version alone can't be followed
+                let Some(project_id) = dep.project_id.clone() else {
+                    continue;
+                };
+
+                graph.edges.push(GraphEdge {
+                    from: project.id.clone(),
+                    to: project_id.clone(),
+                    dependency_type: dep.dependency_type.clone(),
+                    version_id: dep.version_id.clone(),
+                });
+                if dep.dependency_type != DependencyType::Required {
+                    // not worth a request just for the tree, which shows the id until the
+                    // project is resolved for another reason
+                    graph.node_mut(&project_id, "");
+                    continue;
+                }
+
+                if downloaded.contains(&project_id) {
+                    continue;
+                }
+
+                let project = api.get_project(&project_id).await.map_err(ExportErr::Api)?;
+
+                todo.push((project, ident + 1));
+            }
+
+            mods.push(ResolvedMod {
+                project,
+                version: latest_version,
+                file,
+            });
+        }
+
+        for e in &excluded {
+            graph.node_mut(&e.project_id, &e.title).excluded = Some(e.reason.clone());
+        }
+
+        Ok(Resolution {
+            mods,
+            excluded,
+            graph,
+            retries: 0,
+        })
+    }
+
+    /// Writes the archive for `request` to `path`.
+    pub(crate) async fn write_archive(
+        &self,
+        request: &ExportRequest,
+        path: &Path,
+    ) -> ExportResult<Resolution> {
+        // jars are downloaded there before they go into the archive
+        tokio::fs::create_dir_all(&self.output_folder)
+            .await
+            .map_err(ExportErr::Io)?;
+        let (resolution, retries) = count_retries(self.build_archive(request, path)).await;
+
+        Ok(Resolution {
+            retries,
+            ..resolution?
+        })
+    }
+
+    async fn build_archive(
+        &self,
+        request: &ExportRequest,
+        path: &Path,
+    ) -> ExportResult<Resolution> {
+        let ExportRequest {
+            collection_name,
+            release_version,
+            settings,
+            format,
+            ..
+        } = request;
+        let format = *format;
+
+        let game_version = release_version.to_game_version();
+        let loader = settings.loader.as_str();
+
+        let loader_version = if !format.needs_loader_version() {
+            None
+        } else {
+            Some(
+                self.loader_meta
+                    .loader_version(loader, &game_version)
+                    .await
+                    .map_err(ExportErr::LoaderMeta)?,
+            )
+        };
+
+        let resolution = self.resolve(request).await?;
+
+        let mut file = tokio::fs::File::create(path).await.map_err(ExportErr::Io)?;
+        let mut zip = ZipFileWriter::with_tokio(&mut file);
+
+        match (format, &loader_version) {
+            (ExportFormat::Prism, Some(loader_version)) => {
+                let builder = ZipEntryBuilder::new("instance.cfg".into(), Compression::Deflate);
+                zip.write_entry_whole(builder, super::instance_cfg(collection_name).as_bytes())
+                    .await
+                    .map_err(ExportErr::Zip)?;
+
+                let builder = ZipEntryBuilder::new("mmc-pack.json".into(), Compression::Deflate);
+                let mmc_pack = super::mmc_pack_json(&game_version, loader, loader_version);
+                zip.write_entry_whole(builder, mmc_pack.as_bytes())
+                    .await
+                    .map_err(ExportErr::Zip)?;
+            }
+            (ExportFormat::Mrpack, Some(loader_version)) => {
+                let builder =
+                    ZipEntryBuilder::new("modrinth.index.json".into(), Compression::Deflate);
+                let index = super::modrinth_index_json(
+                    collection_name,
+                    &super::pack_version(std::time::SystemTime::now()),
+                    &game_version,
+                    loader,
+                    loader_version,
+                    &resolution.mods,
+                );
+                zip.write_entry_whole(builder, index.as_bytes())
+                    .await
+                    .map_err(ExportErr::Zip)?;
+            }
+            _ => {}
+        }
+
+        // an mrpack only links to the jars, the launcher downloads them
+        if format != ExportFormat::Mrpack {
+            // todo: do multiple downloads simultaneously
+            for m in &resolution.mods {
+                let name = format!("{}{}", format.mods_dir(), m.file.filename);
+                self.write_jar(&mut zip, name, &m.file).await?;
+            }
+        }
+
+        if let (ExportFormat::ServerPack, Some(loader_version)) = (format, &loader_version) {
+            let builder = ZipEntryBuilder::new("README.md".into(), Compression::Deflate);
+            let readme = super::server_readme(
+                collection_name,
+                &game_version,
+                loader,
+                loader_version,
+                &resolution.excluded,
+            );
+            zip.write_entry_whole(builder, readme.as_bytes())
+                .await
+                .map_err(ExportErr::Zip)?;
+        }
+
+        let builder = ZipEntryBuilder::new(PackManifest::FILENAME.into(), Compression::Deflate);
+        let manifest = serde_json::to_string_pretty(&request.manifest(&resolution))
+            .expect("manifests to serialize");
+        zip.write_entry_whole(builder, manifest.as_bytes())
+            .await
+            .map_err(ExportErr::Zip)?;
+
+        zip.close().await.map_err(ExportErr::Zip)?;
+        // tokio finishes writes in the background, so the archive could still be incomplete
+        file.flush().await.map_err(ExportErr::Io)?;
+
+        Ok(resolution)
+    }
+
+    /// Streams a jar into the archive, from the cache if it's there or from modrinth
+    /// otherwise, checking it against its sha512 hash on the way.
+    async fn write_jar<W: AsyncWrite + Unpin>(
+        &self,
+        zip: &mut ZipFileWriter<W>,
+        name: String,
+        file: &VersionFile,
+    ) -> ExportResult<()> {
+        let builder = ZipEntryBuilder::new(name.into(), Compression::Deflate);
+
+        // cached jars have already been checked
+        let cached = match &self.cache {
+            Some(cache) => cache.get(&file.hashes.sha512).await,
+            None => None,
+        };
+        if let Some(mut cached) = cached {
+            let mut entry = zip
+                .write_entry_stream(builder)
+                .await
+                .map_err(ExportErr::Zip)?;
+            let mut buffer = vec![0; 64 * 1024];
+
+            loop {
+                let read = cached.read(&mut buffer).await.map_err(ExportErr::Io)?;
+                if read == 0 {
+                    break;
+                }
+                entry
+                    .write_all(&buffer[..read])
+                    .await
+                    .map_err(ExportErr::Io)?;
+            }
+
+            return entry.close().await.map_err(ExportErr::Zip);
+        }
+
+        // downloaded to a file first, since a download that's retried halfway would
+        // otherwise end up in the archive twice. It's removed again once it's dropped,
+        // and by the janitor if a crash gets in the way.
+        let temp = self.start_build(self.output_folder.join(format!(
+            "{}.{PART_EXTENSION}",
+            uuid::Uuid::new_v4().simple()
+        )));
+        let sha512 = self
+            .api
+            .download_file(file.url.clone(), temp.path())
+            .await
+            .map_err(ExportErr::Api)?;
+
+        let verified = sha512.eq_ignore_ascii_case(&file.hashes.sha512);
+        if !verified && !file.hashes.sha512.is_empty() {
+            return Err(ExportErr::Integrity(file.filename.clone()));
+        }
+
+        let mut downloaded = tokio::fs::File::open(temp.path())
+            .await
+            .map_err(ExportErr::Io)?;
+        let mut entry = zip
+            .write_entry_stream(builder)
+            .await
+            .map_err(ExportErr::Zip)?;
+        let mut cache_writer = match &self.cache {
+            Some(cache) => cache.writer().await,
+            None => None,
+        };
+        let mut buffer = vec![0; 64 * 1024];
+
+        loop {
+            let read = downloaded.read(&mut buffer).await.map_err(ExportErr::Io)?;
+            if read == 0 {
+                break;
+            }
+            entry
+                .write_all(&buffer[..read])
+                .await
+                .map_err(ExportErr::Io)?;
+            if let Some(cache_writer) = &mut cache_writer {
+                cache_writer.write(&buffer[..read]).await;
+            }
+        }
+        entry.close().await.map_err(ExportErr::Zip)?;
+
+        if let Some(cache_writer) = cache_writer {
+            cache_writer.finish(verified.then_some(&sha512)).await;
+        }
+
+        Ok(())
+    }
+
+    /// Where the archive of the export with `id` is written.
+    pub(crate) fn artifact_path(&self, id: &str, format: ExportFormat) -> PathBuf {
+        self.output_folder
+            .join(format!("{id}.{}", format.extension()))
+    }
+
+    /// Makes an archive downloadable until `cleanup_delay` after it was created. It's
+    /// removed by the next `sweep` after that.
+    async fn add_artifact(&self, id: &str, artifact: Artifact) {
+        self.artifacts
+            .write()
+            .await
+            .insert(id.to_string(), artifact);
+    }
+
+    /// Picks up the archive of an export from before a restart, if it's still there.
+    pub(crate) async fn restore_artifact(
+        &self,
+        id: &str,
+        format: ExportFormat,
+        filename: String,
+        created: SystemTime,
+    ) -> bool {
+        let path = self.artifact_path(id, format);
+        let Ok(metadata) = tokio::fs::metadata(&path).await else {
+            return false;
+        };
+
+        let artifact = Artifact {
+            path,
+            filename,
+            created,
+            size: metadata.len(),
+        };
+        self.add_artifact(id, artifact).await;
+        true
+    }
+
+    /// Writes the archive into the output folder, where it can be downloaded by `id` for
+    /// a while.
+    pub(crate) async fn export(
+        &self,
+        id: &str,
+        request: ExportRequest,
+    ) -> ExportResult<ExportReport> {
+        tokio::fs::create_dir_all(&self.output_folder)
+            .await
+            .map_err(ExportErr::Io)?;
+
+        let path = self.artifact_path(id, request.format);
+        // removes what was written if the export fails or is cancelled
+        let build = self.start_build(path.clone());
+        let resolution = self.write_archive(&request, &path).await?;
+
+        let artifact = Artifact {
+            size: tokio::fs::metadata(&path)
+                .await
+                .map_err(ExportErr::Io)?
+                .len(),
+            path,
+            filename: request.filename(),
+            created: SystemTime::now(),
+        };
+        self.add_artifact(id, artifact).await;
+        build.finish();
+
+        Ok(ExportReport {
+            url: format!("{}/{id}", self.url_prefix),
+            excluded: resolution.excluded,
+            retries: resolution.retries,
+        })
+    }
+
+    /// The finished archive of the export with `id`, if it's still around.
+    pub(crate) async fn artifact(&self, id: &str) -> Option<Artifact> {
+        let artifacts = self.artifacts.read().await;
+        let artifact = artifacts.get(id)?;
+
+        (!self.is_expired(artifact, SystemTime::now())).then(|| artifact.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, path::Path, sync::Arc};
+
+    use async_zip::base::write::ZipFileWriter;
+    use ferinth::structures::version::DependencyType;
+    use futures::AsyncReadExt;
+
+    use super::{slug, ExportErr, ExportRequest, JarCache};
+    use crate::{
+        app::{
+            export::{
+                pack_version,
+                test_setup::{self, setup},
+                ExclusionReason, ExportFormat, PackManifest, PackSide,
+            },
+            modrinth::{ApiErr, ChannelPolicy, ProjectKey},
+        },
+        test_setup::temp_path,
+    };
+
+    fn request(
+        projects: HashSet<ProjectKey>,
+        format: ExportFormat,
+        channel: ChannelPolicy,
+    ) -> ExportRequest {
+        let mut request = test_setup::request(projects, format);
+        request.settings.channel = channel;
+        request
+    }
+
+    /// Reads every entry of a written archive, keyed by name.
+    async fn read_archive(path: &Path) -> Vec<(String, String)> {
+        use async_zip::base::read::mem::ZipFileReader;
+
+        let zip = ZipFileReader::new(std::fs::read(path).unwrap())
+            .await
+            .unwrap();
+
+        let mut entries = Vec::new();
+        for idx in 0..zip.file().entries().len() {
+            let name = zip.file().entries()[idx]
+                .filename()
+                .as_str()
+                .unwrap()
+                .to_string();
+            let mut contents = String::new();
+            let mut reader = zip.reader_with_entry(idx).await.unwrap();
+            reader.read_to_string(&mut contents).await.unwrap();
+            entries.push((name, contents));
+        }
+        entries.sort();
+        entries
+    }
+
+    #[tokio::test]
+    async fn client_pack_follows_dependencies() {
+        let (_, exporter, projects) = setup("fixture1").await;
+        let path = temp_path("client.zip");
+
+        let resolution = exporter
+            .write_archive(
+                &request(projects, ExportFormat::ClientPack, ChannelPolicy::Release),
+                &path,
+            )
+            .await
+            .unwrap();
+
+        // fabric api isn't in the collection, it's a required dependency of mod menu
+        let entries = read_archive(&path).await;
+        let names = entries.iter().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "fabric-api-0.92.1+1.20.1.jar",
+                "iris-1.7.0+mc1.20.1.jar",
+                "modmenu-7.2.2.jar",
+                "mr-modpack.json",
+                "sodium-fabric-0.5.8+mc1.20.1.jar",
+            ]
+        );
+
+        // the manifest lists dependencies too, but not what was excluded
+        let manifest: PackManifest = serde_json::from_str(&entries[3].1).unwrap();
+        assert_eq!(manifest.collection, "fixture1");
+        assert_eq!(manifest.game_version, "1.20.1");
+        assert_eq!(manifest.projects.len(), 4);
+        let versions = manifest
+            .mods
+            .iter()
+            .map(|m| m.version_id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(versions, ["sodi1201", "fapi1201", "iris1201", "mmen1201"]);
+        assert_eq!(manifest.mods[0].sha1.len(), 40);
+
+        // mod menu pulls in fabric api, and could use iris
+        let graph = &resolution.graph;
+        let edges = graph
+            .edges_from("mOgUt4GM")
+            .map(|edge| (edge.to.as_str(), edge.dependency_type.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            edges,
+            [
+                ("P7dR8mSH", DependencyType::Required),
+                ("YL57xq9U", DependencyType::Optional)
+            ]
+        );
+        let fabric_api = graph.node("P7dR8mSH").unwrap();
+        assert!(!fabric_api.top_level);
+        assert_eq!(fabric_api.version_id.as_deref(), Some("fapi1201"));
+        assert_eq!(graph.pulled_in_by("P7dR8mSH")[0].title, "Mod Menu");
+        assert!(graph.cycles().is_empty() && graph.conflicts().is_empty());
+
+        // lithium only has a beta for 1.20.1
+        assert!(graph.node("gvQqBUqZ").unwrap().excluded.is_some());
+        assert_eq!(resolution.excluded.len(), 1);
+        assert_eq!(resolution.excluded[0].project_id, "gvQqBUqZ");
+        assert_eq!(
+            resolution.excluded[0].reason,
+            ExclusionReason::Channel(ChannelPolicy::Release)
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn jars_come_from_the_cache() {
+        let (_, exporter, projects) = setup("fixture1").await;
+        let cache_dir = temp_path("jar-cache");
+        let cache = JarCache::open(&cache_dir, 1024 * 1024).await.unwrap();
+        let exporter = exporter.with_cache(Arc::new(cache));
+        let request = request(projects, ExportFormat::ClientPack, ChannelPolicy::Release);
+
+        let first = temp_path("uncached.zip");
+        exporter.write_archive(&request, &first).await.unwrap();
+        assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 4);
+
+        let second = temp_path("cached.zip");
+        exporter.write_archive(&request, &second).await.unwrap();
+        assert_eq!(read_archive(&first).await, read_archive(&second).await);
+
+        let _ = std::fs::remove_file(first);
+        let _ = std::fs::remove_file(second);
+        let _ = std::fs::remove_dir_all(cache_dir);
+    }
+
+    #[tokio::test]
+    async fn server_pack_excludes_client_mods() {
+        let (_, exporter, projects) = setup("fixture1").await;
+        let path = temp_path("server.zip");
+
+        let resolution = exporter
+            .write_archive(
+                &request(projects, ExportFormat::ServerPack, ChannelPolicy::Beta),
+                &path,
+            )
+            .await
+            .unwrap();
+
+        let entries = read_archive(&path).await;
+        let names = entries.iter().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "README.md",
+                "mods/lithium-fabric-mc1.20.1-0.11.2.jar",
+                "mr-modpack.json"
+            ]
+        );
+        assert!(entries[0].1.contains("-loader 0.15.11"));
+
+        let mut excluded = resolution
+            .excluded
+            .iter()
+            .map(|e| (e.project_id.as_str(), &e.reason))
+            .collect::<Vec<_>>();
+        excluded.sort_by_key(|(id, _)| *id);
+        let unsupported = ExclusionReason::Unsupported(PackSide::Server);
+        assert_eq!(
+            excluded,
+            [
+                ("AANobbMI", &unsupported),
+                ("YL57xq9U", &unsupported),
+                ("mOgUt4GM", &unsupported),
+            ]
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn mrpack_links_jars() {
+        let (_, exporter, projects) = setup("fixture1").await;
+        let path = temp_path("pack.mrpack");
+
+        exporter
+            .write_archive(
+                &request(projects, ExportFormat::Mrpack, ChannelPolicy::Release),
+                &path,
+            )
+            .await
+            .unwrap();
+
+        let entries = read_archive(&path).await;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, "modrinth.index.json");
+        assert_eq!(entries[1].0, "mr-modpack.json");
+
+        let index: serde_json::Value = serde_json::from_str(&entries[0].1).unwrap();
+        assert_eq!(index["dependencies"]["minecraft"], "1.20.1");
+        assert_eq!(index["dependencies"]["fabric-loader"], "0.15.11");
+        // the pack's own version, not the game's
+        assert_ne!(index["versionId"], "1.20.1");
+        let exported = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1706702400);
+        assert_eq!(pack_version(exported), "2024.01.31-1200");
+
+        let sodium = index["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|f| f["path"] == "mods/sodium-fabric-0.5.8+mc1.20.1.jar")
+            .unwrap();
+        assert_eq!(sodium["env"]["client"], "required");
+        assert_eq!(sodium["env"]["server"], "unsupported");
+        assert_eq!(sodium["hashes"]["sha1"].as_str().unwrap().len(), 40);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_download() {
+        let (_, exporter, projects) = setup("broken01").await;
+        let path = temp_path("broken.zip");
+
+        let result = exporter
+            .write_archive(
+                &request(projects, ExportFormat::ClientPack, ChannelPolicy::Release),
+                &path,
+            )
+            .await;
+        assert!(matches!(result, Err(ExportErr::Api(ApiErr::Status(404)))));
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn version_without_files() {
+        let (_, exporter, _) = setup("fixture1").await;
+
+        let projects = HashSet::from([ProjectKey("no-files".to_string())]);
+        let resolution = exporter
+            .resolve(&request(
+                projects,
+                ExportFormat::ClientPack,
+                ChannelPolicy::Release,
+            ))
+            .await
+            .unwrap();
+
+        assert!(resolution.mods.is_empty());
+        assert_eq!(
+            resolution.excluded[0].reason,
+            ExclusionReason::NoFiles("1.0.0".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn corrupt_jar() {
+        let (_, exporter, _) = setup("fixture1").await;
+
+        let mut version = exporter.api.get_version("sodi1201").await.unwrap();
+        let mut file = version.files.remove(0);
+        file.hashes.sha512 = "0".repeat(128);
+
+        let mut zip = ZipFileWriter::new(Vec::new());
+        let result = exporter
+            .write_jar(&mut zip, file.filename.clone(), &file)
+            .await;
+        assert!(matches!(result, Err(ExportErr::Integrity(name)) if name == file.filename));
+    }
+
+    #[test]
+    fn hostile_names() {
+        assert_eq!(slug("Fixture Pack"), "fixture-pack");
+        assert_eq!(slug("../../etc/passwd"), "etc-passwd");
+        assert_eq!(slug("a/b\\c:d*e?f\"g<h>i|j"), "a-b-c-d-e-f-g-h-i-j");
+        assert_eq!(slug("\r\nname=evil\0"), "name-evil");
+        assert_eq!(slug("Ünïcode Mods"), "ünïcode-mods");
+        assert_eq!(slug("..."), "modpack");
+        assert_eq!(slug(""), "modpack");
+        assert_eq!(slug("CON"), "con-pack");
+        assert_eq!(slug(&"a".repeat(1000)).len(), 64);
+    }
+}
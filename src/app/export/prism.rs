@@ -0,0 +1,69 @@
+//! The files that make a zip importable as a Prism Launcher / MultiMC instance.
+
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MmcPack {
+    components: Vec<MmcComponent>,
+    format_version: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct MmcComponent {
+    uid: &'static str,
+    version: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    important: bool,
+}
+
+impl MmcComponent {
+    fn new(uid: &'static str, version: &str) -> Self {
+        Self {
+            uid,
+            version: version.to_string(),
+            important: false,
+        }
+    }
+}
+
+pub(crate) fn instance_cfg(name: &str) -> String {
+    // the cfg is line based, so a name can't be allowed to start a new key
+    let name = name.replace(['\r', '\n'], " ");
+
+    format!("[General]\nConfigVersion=1.2\nInstanceType=OneSix\niconKey=default\nname={name}\n")
+}
+
+/// Builds `mmc-pack.json`, which tells the launcher which Minecraft and loader to install.
+pub(crate) fn mmc_pack_json(game_version: &str, loader: &str, loader_version: &str) -> String {
+    let mut components = vec![MmcComponent {
+        important: true,
+        ..MmcComponent::new("net.minecraft", game_version)
+    }];
+
+    match loader {
+        "fabric" => {
+            components.push(MmcComponent::new("net.fabricmc.intermediary", game_version));
+            components.push(MmcComponent::new(
+                "net.fabricmc.fabric-loader",
+                loader_version,
+            ));
+        }
+        "quilt" => {
+            components.push(MmcComponent::new("net.fabricmc.intermediary", game_version));
+            components.push(MmcComponent::new(
+                "org.quiltmc.quilt-loader",
+                loader_version,
+            ));
+        }
+        "forge" => components.push(MmcComponent::new("net.minecraftforge", loader_version)),
+        "neoforge" => components.push(MmcComponent::new("net.neoforged", loader_version)),
+        _ => {}
+    }
+
+    serde_json::to_string_pretty(&MmcPack {
+        components,
+        format_version: 1,
+    })
+    .unwrap()
+}
@@ -0,0 +1,66 @@
+//! The README that goes along with a server pack.
+
+use super::ExcludedMod;
+
+fn install_command(game_version: &str, loader: &str, loader_version: &str) -> String {
+    match loader {
+        "fabric" => format!(
+            "java -jar fabric-installer.jar server -mcversion {game_version} -loader {loader_version} -downloadMinecraft"
+        ),
+        "quilt" => format!(
+            "java -jar quilt-installer.jar install server {game_version} {loader_version} --download-server"
+        ),
+        "forge" => format!(
+            "java -jar forge-{game_version}-{loader_version}-installer.jar --installServer"
+        ),
+        "neoforge" => format!("java -jar neoforge-{loader_version}-installer.jar --installServer"),
+        other => format!("# install {other} {loader_version} for Minecraft {game_version}"),
+    }
+}
+
+fn installer_url(loader: &str) -> &'static str {
+    match loader {
+        "fabric" => "https://fabricmc.net/use/installer/",
+        "quilt" => "https://quiltmc.org/en/install/server/",
+        "forge" => "https://files.minecraftforge.net/",
+        "neoforge" => "https://neoforged.net/",
+        _ => "the loader's website",
+    }
+}
+
+pub(crate) fn server_readme(
+    name: &str,
+    game_version: &str,
+    loader: &str,
+    loader_version: &str,
+    excluded: &[ExcludedMod],
+) -> String {
+    let mut readme = format!(
+        "# {name} (server pack)\n\
+        \n\
+        Minecraft {game_version} with {loader} {loader_version}.\n\
+        \n\
+        ## Installing\n\
+        \n\
+        1. Download the {loader} installer from {}\n\
+        2. Install the server into an empty folder:\n\
+        \n\
+        ```shell\n\
+        {}\n\
+        ```\n\
+        \n\
+        3. Copy the `mods` folder from this pack next to the server jar and start the server.\n",
+        installer_url(loader),
+        install_command(game_version, loader, loader_version),
+    );
+
+    if !excluded.is_empty() {
+        readme.push_str("\n## Excluded mods\n\n");
+
+        for excluded in excluded {
+            readme.push_str(&format!("- {}: {}\n", excluded.title, excluded.reason));
+        }
+    }
+
+    readme
+}
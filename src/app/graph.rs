@@ -0,0 +1,599 @@
+//! The dependencies between the mods of an export, as the exporter followed them.
+//!
+//! Every project the exporter looked at is a node, with the version it picked if it made it
+//! into the pack. Every dependency of a picked version is an edge, whether it was followed
+//! or not. The tree shows which of the collection's mods pulled in each library, with
+//! required cycles and conflicting dependencies highlighted.
+
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use ferinth::structures::version::DependencyType;
+use leptos::*;
+use serde::{Deserialize, Serialize};
+use server_fn::codec::Json;
+
+use super::{export::ExclusionReason, workspace::CollectionSettings, SemanticVersion};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphNode {
+    pub project_id: String,
+    /// Empty for dependencies that were never resolved
+    pub title: String,
+    /// The version that was picked, `None` if the project isn't in the pack
+    pub version_id: Option<String>,
+    pub version_number: Option<String>,
+    /// Whether the project was picked for the export, rather than pulled in by another
+    pub top_level: bool,
+    /// Why the project was left out, if the exporter tried to include it
+    pub excluded: Option<ExclusionReason>,
+}
+
+/// A dependency of the version picked for `from` on the project `to`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphEdge {
+    pub from: String,
+    pub to: String,
+    pub dependency_type: DependencyType,
+    /// The version of `to` it asks for, if it asks for a specific one
+    pub version_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Conflict {
+    /// Both projects are in the pack, but `from` says it doesn't work with `to`
+    Incompatible { from: String, to: String },
+    /// `from` asks for a version of `to` other than the one that was picked
+    VersionMismatch {
+        from: String,
+        to: String,
+        wanted: String,
+        picked: String,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DependencyGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+impl GraphNode {
+    /// The title, or the id of a project whose title isn't known.
+    pub fn name(&self) -> &str {
+        if self.title.is_empty() {
+            &self.project_id
+        } else {
+            &self.title
+        }
+    }
+}
+
+impl DependencyGraph {
+    pub fn node(&self, project_id: &str) -> Option<&GraphNode> {
+        self.nodes.iter().find(|node| node.project_id == project_id)
+    }
+
+    /// The node of a project, added with `title` if it isn't there yet.
+    /// A node added without a title gets it once one is known.
+    pub fn node_mut(&mut self, project_id: &str, title: &str) -> &mut GraphNode {
+        let idx = match self
+            .nodes
+            .iter()
+            .position(|node| node.project_id == project_id)
+        {
+            Some(idx) => {
+                if self.nodes[idx].title.is_empty() {
+                    self.nodes[idx].title = title.to_string();
+                }
+                idx
+            }
+            None => {
+                self.nodes.push(GraphNode {
+                    project_id: project_id.to_string(),
+                    title: title.to_string(),
+                    version_id: None,
+                    version_number: None,
+                    top_level: false,
+                    excluded: None,
+                });
+                self.nodes.len() - 1
+            }
+        };
+        &mut self.nodes[idx]
+    }
+
+    pub fn edges_from<'a>(&'a self, project_id: &'a str) -> impl Iterator<Item = &'a GraphEdge> {
+        self.edges
+            .iter()
+            .filter(move |edge| edge.from == project_id)
+    }
+
+    fn required_from<'a>(&'a self, project_id: &'a str) -> impl Iterator<Item = &'a str> {
+        self.edges_from(project_id)
+            .filter(|edge| edge.dependency_type == DependencyType::Required)
+            .map(|edge| edge.to.as_str())
+    }
+
+    /// The top level projects that pull `project_id` in through required dependencies.
+    pub fn pulled_in_by(&self, project_id: &str) -> Vec<&GraphNode> {
+        self.nodes
+            .iter()
+            .filter(|node| node.top_level && node.project_id != project_id)
+            .filter(|node| {
+                let mut seen = HashSet::from([node.project_id.as_str()]);
+                let mut todo = vec![node.project_id.as_str()];
+                while let Some(id) = todo.pop() {
+                    for to in self.required_from(id) {
+                        if to == project_id {
+                            return true;
+                        }
+                        if seen.insert(to) {
+                            todo.push(to);
+                        }
+                    }
+                }
+                false
+            })
+            .collect()
+    }
+
+    /// Groups of projects that require each other in a loop, by project id.
+    pub fn cycles(&self) -> Vec<Vec<String>> {
+        // tarjan's strongly connected components
+        struct Search<'a> {
+            graph: &'a DependencyGraph,
+            next: usize,
+            index: HashMap<&'a str, usize>,
+            low: HashMap<&'a str, usize>,
+            stack: Vec<&'a str>,
+            on_stack: HashSet<&'a str>,
+            cycles: Vec<Vec<String>>,
+        }
+
+        impl<'a> Search<'a> {
+            fn visit(&mut self, id: &'a str) {
+                self.index.insert(id, self.next);
+                self.low.insert(id, self.next);
+                self.next += 1;
+                self.stack.push(id);
+                self.on_stack.insert(id);
+
+                for to in self.graph.required_from(id) {
+                    if !self.index.contains_key(to) {
+                        self.visit(to);
+                        let low = self.low[id].min(self.low[to]);
+                        self.low.insert(id, low);
+                    } else if self.on_stack.contains(to) {
+                        let low = self.low[id].min(self.index[to]);
+                        self.low.insert(id, low);
+                    }
+                }
+
+                if self.low[id] == self.index[id] {
+                    let mut component = Vec::new();
+                    while let Some(member) = self.stack.pop() {
+                        self.on_stack.remove(member);
+                        component.push(member.to_string());
+                        if member == id {
+                            break;
+                        }
+                    }
+
+                    let requires_itself = self.graph.required_from(id).any(|to| to == id);
+                    if component.len() > 1 || requires_itself {
+                        component.sort();
+                        self.cycles.push(component);
+                    }
+                }
+            }
+        }
+
+        let mut search = Search {
+            graph: self,
+            next: 0,
+            index: HashMap::new(),
+            low: HashMap::new(),
+            stack: Vec::new(),
+            on_stack: HashSet::new(),
+            cycles: Vec::new(),
+        };
+        for node in &self.nodes {
+            if !search.index.contains_key(node.project_id.as_str()) {
+                search.visit(&node.project_id);
+            }
+        }
+
+        search.cycles.sort();
+        search.cycles
+    }
+
+    /// Dependencies the pack doesn't satisfy.
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        self.edges
+            .iter()
+            .filter_map(|edge| {
+                let picked = self.node(&edge.to)?.version_id.as_ref()?;
+                match edge.dependency_type {
+                    DependencyType::Incompatible
+                        if edge.version_id.as_ref().map_or(true, |v| v == picked) =>
+                    {
+                        Some(Conflict::Incompatible {
+                            from: edge.from.clone(),
+                            to: edge.to.clone(),
+                        })
+                    }
+                    DependencyType::Required | DependencyType::Optional => {
+                        let wanted = edge.version_id.as_ref().filter(|v| *v != picked)?;
+                        Some(Conflict::VersionMismatch {
+                            from: edge.from.clone(),
+                            to: edge.to.clone(),
+                            wanted: wanted.clone(),
+                            picked: picked.clone(),
+                        })
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Resolves every mod of a collection for `release_version`, like an export of both sides
+/// would, and returns how they depend on each other.
+#[server(input = Json)]
+pub async fn get_dependency_graph(
+    collection_id: String,
+    release_version: SemanticVersion,
+    settings: CollectionSettings,
+) -> Result<DependencyGraph, ServerFnError> {
+    use std::sync::Arc;
+
+    use super::export::{ExportFormat, ExportRequest};
+
+    let api: Arc<super::modrinth::ModrinthClient> = use_context().unwrap();
+    let imports: Arc<super::packwiz::PackwizImports> = use_context().unwrap();
+    let jobs: Arc<super::export::ExportJobs> = use_context().unwrap();
+
+    if !super::SUPPORTED_LOADERS.contains(&settings.loader.as_str()) {
+        return Err(ServerFnError::new(format!(
+            "{} isn't a supported loader",
+            settings.loader
+        )));
+    }
+
+    let collection = super::coverage::resolve_collection(&api, &imports, &collection_id)
+        .await
+        .map_err(ServerFnError::new)?;
+
+    let request = ExportRequest {
+        collection_id: collection.id,
+        collection_name: collection.name,
+        release_version,
+        settings,
+        projects: collection.projects.into_iter().collect(),
+        format: ExportFormat::Mrpack,
+    };
+    let resolution =
+        jobs.exporter().resolve(&request).await.map_err(|err| {
+            ServerFnError::new(format!("couldn't resolve the dependencies: {err}"))
+        })?;
+
+    Ok(resolution.graph)
+}
+
+fn dependency_type_name(dependency_type: &DependencyType) -> &'static str {
+    match dependency_type {
+        DependencyType::Required => "required",
+        DependencyType::Optional => "optional",
+        DependencyType::Incompatible => "incompatible",
+        DependencyType::Embedded => "embedded",
+    }
+}
+
+/// What's shown about a node on its line of the tree.
+fn node_label(graph: &DependencyGraph, project_id: &str) -> String {
+    match graph.node(project_id) {
+        Some(node) => match (&node.version_number, &node.excluded) {
+            (Some(version_number), _) => format!("{} {version_number}", node.name()),
+            (None, Some(reason)) => format!("{} (left out, {reason})", node.name()),
+            (None, None) => format!("{} (not in the pack)", node.name()),
+        },
+        None => project_id.to_string(),
+    }
+}
+
+/// What a graph looks like in the tree, worked out once.
+struct TreeContext {
+    graph: DependencyGraph,
+    in_cycle: HashSet<String>,
+    /// Edges that conflict, by `from` and `to`
+    conflicting: HashSet<(String, String)>,
+}
+
+/// A project and everything it depends on, down to where it would loop back on itself.
+fn dependency_tree(tree: &Rc<TreeContext>, project_id: &str, path: &[String]) -> View {
+    let path = path
+        .iter()
+        .cloned()
+        .chain([project_id.to_string()])
+        .collect::<Vec<_>>();
+
+    let children = tree
+        .graph
+        .edges_from(project_id)
+        .map(|edge| {
+            let conflict = tree
+                .conflicting
+                .contains(&(edge.from.clone(), edge.to.clone()));
+            let cycle = path.contains(&edge.to);
+            let label = format!(
+                "{} ({})",
+                node_label(&tree.graph, &edge.to),
+                dependency_type_name(&edge.dependency_type)
+            );
+
+            let child = if cycle {
+                view! { <span class="cycle">{label}" ↻"</span> }.into_view()
+            } else if tree.graph.edges_from(&edge.to).next().is_some() {
+                view! {
+                    <details>
+                        <summary>{label}</summary>
+                        {dependency_tree(tree, &edge.to, &path)}
+                    </details>
+                }
+                .into_view()
+            } else {
+                view! { <span>{label}</span> }.into_view()
+            };
+
+            view! {
+                <li
+                    class=dependency_type_name(&edge.dependency_type)
+                    class:conflict=conflict
+                    class:in-cycle=tree.in_cycle.contains(&edge.to)
+                >
+                    {child}
+                </li>
+            }
+        })
+        .collect_view();
+
+    view! { <ul>{children}</ul> }.into_view()
+}
+
+/// The mods of a collection as a tree of what they depend on, with the libraries they pull
+/// in and any cycles and conflicts listed underneath.
+#[component]
+pub fn DependencyTree(
+    collection_id: String,
+    settings: Signal<CollectionSettings>,
+    /// The game versions to pick from, the first is shown at first
+    versions: Vec<SemanticVersion>,
+) -> impl IntoView {
+    let version = create_rw_signal(versions.first().copied());
+
+    let graph = create_local_resource(
+        move || (version.get(), settings.get()),
+        move |(version, settings)| {
+            let collection_id = collection_id.clone();
+            async move {
+                match version {
+                    Some(version) => {
+                        Some(get_dependency_graph(collection_id, version, settings).await)
+                    }
+                    None => None,
+                }
+            }
+        },
+    );
+
+    view! {
+        <div class="dependency-tree">
+            <label>
+                "Game version "
+                <select on:change=move |ev| version.set(event_target_value(&ev).parse().ok())>
+                    {versions.iter().map(|v| view! {
+                        <option value=v.to_game_version() selected=Some(*v) == version.get_untracked()>
+                            {v.to_game_version()}
+                        </option>
+                    }).collect_view()}
+                </select>
+            </label>
+
+            <Suspense fallback=|| view! { <p>"Loading..."</p> }>
+                {move || graph.get().map(|graph| match graph {
+                    None => view! { <p>"No mod supports any game version"</p> }.into_view(),
+                    Some(Err(err)) => view! { <p class="error">{err.to_string()}</p> }.into_view(),
+                    Some(Ok(graph)) => {
+                        let cycles = graph.cycles();
+                        let conflicts = graph.conflicts();
+                        let libraries = graph
+                            .nodes
+                            .iter()
+                            .filter(|node| !node.top_level && node.version_id.is_some())
+                            .map(|node| {
+                                let pulled_in_by = graph
+                                    .pulled_in_by(&node.project_id)
+                                    .into_iter()
+                                    .map(|by| by.name())
+                                    .collect::<Vec<_>>()
+                                    .join(", ");
+                                format!("{}: pulled in by {pulled_in_by}", node_label(&graph, &node.project_id))
+                            })
+                            .collect::<Vec<_>>();
+                        let cycle_lines = cycles
+                            .iter()
+                            .map(|cycle| {
+                                cycle
+                                    .iter()
+                                    .map(|id| graph.node(id).map_or(id.as_str(), |node| node.name()))
+                                    .collect::<Vec<_>>()
+                                    .join(" ⇄ ")
+                            })
+                            .collect::<Vec<_>>();
+                        let conflict_lines = conflicts
+                            .iter()
+                            .map(|conflict| {
+                                let title = |id: &str| graph.node(id).map_or(id.to_string(), |node| node.name().to_string());
+                                match conflict {
+                                    Conflict::Incompatible { from, to } => {
+                                        format!("{} is incompatible with {}", title(from), title(to))
+                                    }
+                                    Conflict::VersionMismatch { from, to, wanted, picked } => format!(
+                                        "{} wants version {wanted} of {}, but {picked} was picked",
+                                        title(from),
+                                        title(to),
+                                    ),
+                                }
+                            })
+                            .collect::<Vec<_>>();
+
+                        let mut top_level = graph
+                            .nodes
+                            .iter()
+                            .filter(|node| node.top_level)
+                            .map(|node| node.project_id.clone())
+                            .collect::<Vec<_>>();
+                        top_level.sort_by_key(|id| node_label(&graph, id));
+
+                        let tree = Rc::new(TreeContext {
+                            in_cycle: cycles.into_iter().flatten().collect(),
+                            conflicting: conflicts
+                                .iter()
+                                .map(|conflict| match conflict {
+                                    Conflict::Incompatible { from, to }
+                                    | Conflict::VersionMismatch { from, to, .. } => (from.clone(), to.clone()),
+                                })
+                                .collect(),
+                            graph,
+                        });
+
+                        view! {
+                            <ul>
+                                {top_level.into_iter().map(|id| {
+                                    let label = node_label(&tree.graph, &id);
+                                    let has_dependencies = tree.graph.edges_from(&id).next().is_some();
+                                    view! {
+                                        <li class:in-cycle=tree.in_cycle.contains(&id)>
+                                            {if has_dependencies {
+                                                view! {
+                                                    <details>
+                                                        <summary>{label}</summary>
+                                                        {dependency_tree(&tree, &id, &[])}
+                                                    </details>
+                                                }.into_view()
+                                            } else {
+                                                view! { <span>{label}</span> }.into_view()
+                                            }}
+                                        </li>
+                                    }
+                                }).collect_view()}
+                            </ul>
+
+                            <h3>"Libraries"</h3>
+                            {if libraries.is_empty() {
+                                view! { <p>"Nothing"</p> }.into_view()
+                            } else {
+                                view! {
+                                    <ul>{libraries.into_iter().map(|line| view! { <li>{line}</li> }).collect_view()}</ul>
+                                }.into_view()
+                            }}
+
+                            {(!cycle_lines.is_empty()).then(|| view! {
+                                <h3>"Cycles"</h3>
+                                <ul>
+                                    {cycle_lines.into_iter().map(|line| view! { <li class="in-cycle">{line}</li> }).collect_view()}
+                                </ul>
+                            })}
+                            {(!conflict_lines.is_empty()).then(|| view! {
+                                <h3>"Conflicts"</h3>
+                                <ul>
+                                    {conflict_lines.into_iter().map(|line| view! { <li class="conflict">{line}</li> }).collect_view()}
+                                </ul>
+                            })}
+                        }.into_view()
+                    }
+                })}
+            </Suspense>
+        </div>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ferinth::structures::version::DependencyType;
+
+    use super::{Conflict, DependencyGraph, GraphEdge};
+
+    fn edge(from: &str, to: &str, dependency_type: DependencyType) -> GraphEdge {
+        GraphEdge {
+            from: from.to_string(),
+            to: to.to_string(),
+            dependency_type,
+            version_id: None,
+        }
+    }
+
+    fn graph() -> DependencyGraph {
+        let mut graph = DependencyGraph::default();
+        for (id, top_level) in [("a", true), ("b", true), ("lib", false), ("core", false)] {
+            let node = graph.node_mut(id, &id.to_uppercase());
+            node.version_id = Some(format!("{id}-1"));
+            node.top_level = top_level;
+        }
+        graph.node_mut("extra", "Extra");
+
+        graph.edges = vec![
+            edge("a", "lib", DependencyType::Required),
+            edge("lib", "core", DependencyType::Required),
+            edge("core", "lib", DependencyType::Required),
+            edge("b", "core", DependencyType::Optional),
+            edge("b", "a", DependencyType::Incompatible),
+            edge("b", "extra", DependencyType::Incompatible),
+            GraphEdge {
+                version_id: Some("lib-0".to_string()),
+                ..edge("b", "lib", DependencyType::Required)
+            },
+        ];
+        graph
+    }
+
+    #[test]
+    fn cycles_and_conflicts() {
+        let graph = graph();
+
+        assert_eq!(graph.cycles(), [["core", "lib"]]);
+
+        // extra isn't in the pack, so it can't conflict
+        assert_eq!(
+            graph.conflicts(),
+            [
+                Conflict::Incompatible {
+                    from: "b".to_string(),
+                    to: "a".to_string(),
+                },
+                Conflict::VersionMismatch {
+                    from: "b".to_string(),
+                    to: "lib".to_string(),
+                    wanted: "lib-0".to_string(),
+                    picked: "lib-1".to_string(),
+                },
+            ]
+        );
+
+        let pulled_in_by = |id| {
+            graph
+                .pulled_in_by(id)
+                .into_iter()
+                .map(|node| node.project_id.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(pulled_in_by("core"), ["a", "b"]);
+        // an optional dependency doesn't pull anything in
+        assert_eq!(pulled_in_by("a"), Vec::<&str>::new());
+    }
+}
@@ -0,0 +1,246 @@
+//! Snapshots of a collection's compatibility matrix over time, drawn as a trend chart.
+//!
+//! A snapshot is taken whenever the server works out a collection's matrix, for someone
+//! looking at it or for a watch, but only if the coverage changed or the last snapshot is
+//! older than the snapshot interval. Over weeks, this shows which game version the mods of
+//! a collection are converging on.
+
+#[cfg(feature = "ssr")]
+mod store;
+
+#[cfg(feature = "ssr")]
+pub use store::*;
+
+use std::collections::HashSet;
+
+use itertools::Itertools;
+use leptos::*;
+use serde::{Deserialize, Serialize};
+
+use super::{coverage::CollectionData, modrinth::ProjectKey, watch::format_time, SemanticVersion};
+
+/// The coverage of a collection at one point in time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoverageSnapshot {
+    /// Seconds since the unix epoch
+    pub time: u64,
+    /// How many projects the collection had
+    pub projects: usize,
+    /// The projects supporting each game version, like `CollectionData::available_versions`
+    pub versions: Vec<(SemanticVersion, HashSet<ProjectKey>)>,
+}
+
+impl CoverageSnapshot {
+    pub fn new(data: &CollectionData, time: u64) -> Self {
+        Self {
+            time,
+            projects: data.collection.projects.len(),
+            versions: data.available_versions.clone(),
+        }
+    }
+
+    pub fn supported(&self, game_version: SemanticVersion) -> Option<&HashSet<ProjectKey>> {
+        self.versions
+            .iter()
+            .find(|(version, _)| *version == game_version)
+            .map(|(_, projects)| projects)
+    }
+
+    pub fn is_complete(&self, game_version: SemanticVersion) -> bool {
+        self.supported(game_version)
+            .is_some_and(|projects| projects.len() >= self.projects)
+    }
+
+    /// The percentage of projects supporting `game_version`.
+    pub fn coverage(&self, game_version: SemanticVersion) -> f64 {
+        let supported = self.supported(game_version).map_or(0, HashSet::len);
+        if self.projects == 0 {
+            0.0
+        } else {
+            supported as f64 / self.projects as f64 * 100.0
+        }
+    }
+
+    /// Whether the matrix is the same, whenever it was taken.
+    pub fn same_coverage(&self, other: &CoverageSnapshot) -> bool {
+        self.projects == other.projects
+            && self.versions.len() == other.versions.len()
+            && self
+                .versions
+                .iter()
+                .all(|(version, projects)| other.supported(*version) == Some(projects))
+    }
+}
+
+/// The coverage of each game version over time, for the `lines` versions that are the most
+/// supported in the newest snapshot.
+pub fn trend(
+    history: &[CoverageSnapshot],
+    lines: usize,
+) -> Vec<(SemanticVersion, Vec<(u64, f64)>)> {
+    let Some(newest) = history.last() else {
+        return Vec::new();
+    };
+
+    newest
+        .versions
+        .iter()
+        // newer versions win ties
+        .sorted_by_key(|(version, projects)| (projects.len(), *version))
+        .rev()
+        .take(lines)
+        .map(|(version, _)| {
+            let points = history
+                .iter()
+                .map(|snapshot| (snapshot.time, snapshot.coverage(*version)))
+                .collect();
+            (*version, points)
+        })
+        .collect()
+}
+
+/// Every snapshot of a collection for `loader`, oldest first.
+#[server]
+pub async fn get_coverage_history(
+    collection_id: String,
+    loader: String,
+) -> Result<Vec<CoverageSnapshot>, ServerFnError> {
+    let history: std::sync::Arc<HistoryStore> = use_context().unwrap();
+
+    Ok(history.history(&collection_id, &loader).await)
+}
+
+const CHART_WIDTH: f64 = 600.0;
+const CHART_HEIGHT: f64 = 200.0;
+const CHART_COLORS: [&str; 6] = [
+    "#1bd96a", "#4f9cff", "#ffa347", "#ff496e", "#c78aff", "#e8e8e8",
+];
+
+/// A line per game version, showing how much of the collection supported it over time.
+#[component]
+pub fn CoverageChart(collection_id: String, loader: String) -> impl IntoView {
+    let history = create_local_resource(
+        || (),
+        move |_| get_coverage_history(collection_id.clone(), loader.clone()),
+    );
+
+    view! {
+        <div class="coverage-chart">
+            <Suspense fallback=|| view! { <p>"Loading..."</p> }>
+                {move || history.get().map(|history| match history {
+                    Err(err) => view! { <p class="error">{err.to_string()}</p> }.into_view(),
+                    Ok(history) if history.is_empty() => view! {
+                        <p>"No coverage has been recorded yet"</p>
+                    }.into_view(),
+                    Ok(history) => {
+                        let start = history.first().unwrap().time;
+                        let end = history.last().unwrap().time;
+                        let x = move |time: u64| {
+                            if end == start {
+                                CHART_WIDTH
+                            } else {
+                                (time - start) as f64 / (end - start) as f64 * CHART_WIDTH
+                            }
+                        };
+                        let y = |coverage: f64| CHART_HEIGHT - coverage / 100.0 * CHART_HEIGHT;
+                        let lines = trend(&history, CHART_COLORS.len());
+
+                        view! {
+                            <svg
+                                viewBox=format!("-40 -10 {} {}", CHART_WIDTH + 50.0, CHART_HEIGHT + 30.0)
+                                role="img"
+                            >
+                                <line class="axis" x1="0" y1="0" x2="0" y2=CHART_HEIGHT/>
+                                <line class="axis" x1="0" y1=CHART_HEIGHT x2=CHART_WIDTH y2=CHART_HEIGHT/>
+                                <text x="-5" y="5" text-anchor="end">"100%"</text>
+                                <text x="-5" y=CHART_HEIGHT text-anchor="end">"0%"</text>
+                                <text x="0" y=CHART_HEIGHT + 18.0>{format_time(start)}</text>
+                                <text x=CHART_WIDTH y=CHART_HEIGHT + 18.0 text-anchor="end">
+                                    {format_time(end)}
+                                </text>
+                                {lines.iter().zip(CHART_COLORS).map(|((_, points), color)| {
+                                    let points = points
+                                        .iter()
+                                        .map(|(time, coverage)| format!("{:.1},{:.1}", x(*time), y(*coverage)))
+                                        .join(" ");
+                                    view! {
+                                        <polyline points=points fill="none" stroke=color stroke-width="2"/>
+                                    }
+                                }).collect_view()}
+                            </svg>
+                            <ul class="legend">
+                                {lines.into_iter().zip(CHART_COLORS).map(|((version, points), color)| {
+                                    let latest = points.last().map_or(0.0, |(_, coverage)| *coverage);
+                                    view! {
+                                        <li>
+                                            <span class="swatch" style=format!("background-color: {color}")></span>
+                                            {format!("{} ({latest:.1}%)", version.to_game_version())}
+                                        </li>
+                                    }
+                                }).collect_view()}
+                            </ul>
+                        }.into_view()
+                    }
+                })}
+            </Suspense>
+        </div>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{trend, CoverageSnapshot};
+    use crate::app::{modrinth::ProjectKey, SemanticVersion};
+
+    fn snapshot(time: u64, versions: &[(&str, &[&str])]) -> CoverageSnapshot {
+        CoverageSnapshot {
+            time,
+            projects: 4,
+            versions: versions
+                .iter()
+                .map(|(version, ids)| {
+                    (
+                        version.parse().unwrap(),
+                        ids.iter()
+                            .map(|id| ProjectKey(id.to_string()))
+                            .collect::<HashSet<_>>(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn lines_per_version() {
+        let history = [
+            snapshot(10, &[("1.20.1", &["a", "b", "c"]), ("1.20.4", &["a"])]),
+            snapshot(
+                20,
+                &[
+                    ("1.20.1", &["a", "b", "c"]),
+                    ("1.20.4", &["a", "b", "c", "d"]),
+                ],
+            ),
+        ];
+        let v = |version: &str| version.parse::<SemanticVersion>().unwrap();
+
+        // most supported at the end first
+        assert_eq!(
+            trend(&history, 6),
+            [
+                (v("1.20.4"), vec![(10, 25.0), (20, 100.0)]),
+                (v("1.20.1"), vec![(10, 75.0), (20, 75.0)]),
+            ]
+        );
+        assert_eq!(trend(&history, 1).len(), 1);
+        assert!(trend(&[], 6).is_empty());
+
+        assert!(history[0].same_coverage(&snapshot(
+            30,
+            &[("1.20.4", &["a"]), ("1.20.1", &["c", "b", "a"])]
+        )));
+        assert!(!history[0].same_coverage(&history[1]));
+    }
+}
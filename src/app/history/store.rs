@@ -0,0 +1,281 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use leptos::logging;
+use tokio::sync::RwLock;
+
+use super::CoverageSnapshot;
+
+/// How many snapshots are kept per collection and loader, the oldest go first.
+const MAX_SNAPSHOTS: usize = 256;
+/// How many collections (per loader) have a history, the one updated longest ago goes first
+/// unless it's watched.
+const MAX_COLLECTIONS: usize = 256;
+/// How often new snapshots are saved, rather than rewriting the file for every one.
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Snapshots keyed by collection and loader, kept in a single json file.
+#[derive(Debug)]
+pub struct HistoryStore {
+    /// Where snapshots are saved, they're only kept in memory without it
+    path: Option<PathBuf>,
+    /// Unchanged coverage is only recorded again after this long
+    interval: Duration,
+    snapshots: RwLock<HashMap<String, Vec<CoverageSnapshot>>>,
+    /// Histories that are never evicted, since anyone can look at a collection but only
+    /// watched ones are polled on purpose. Set again by the `WatchStore` on every start
+    watched: RwLock<HashSet<String>>,
+    /// Whether there are snapshots that haven't been saved yet
+    dirty: AtomicBool,
+}
+
+fn key(collection: &str, loader: &str) -> String {
+    format!("{collection}/{loader}")
+}
+
+impl HistoryStore {
+    /// A history that's only kept in memory.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            path: None,
+            interval,
+            snapshots: Default::default(),
+            watched: Default::default(),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Loads the history from `path`, starting empty if the file doesn't exist yet.
+    pub async fn open(path: impl Into<PathBuf>, interval: Duration) -> io::Result<Self> {
+        let path = path.into();
+
+        let snapshots = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            path: Some(path),
+            interval,
+            snapshots: RwLock::new(snapshots),
+            watched: Default::default(),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    /// Writes every snapshot back to disk if any were recorded since the last save, in one go
+    /// so a crash can't leave the file half written. Only serializing happens under the lock.
+    pub async fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return;
+        }
+
+        let result = async {
+            let contents = serde_json::to_vec(&*self.snapshots.read().await)?;
+
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            let tmp = path.with_extension("tmp");
+            tokio::fs::write(&tmp, contents).await?;
+            tokio::fs::rename(&tmp, path).await
+        };
+        if let Err(err) = result.await {
+            logging::error!(
+                "couldn't save coverage history to {}: {err}",
+                path.display()
+            );
+            self.dirty.store(true, Ordering::Release);
+        }
+    }
+
+    /// Saves new snapshots every [`SAVE_INTERVAL`], so at most that much is lost in a crash.
+    pub fn spawn_saver(self: Arc<Self>) {
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(SAVE_INTERVAL);
+            loop {
+                interval.tick().await;
+                self.save().await;
+            }
+        });
+    }
+
+    /// Adds a snapshot, unless the last one has the same coverage and isn't due for another.
+    /// Returns whether it was added.
+    pub(crate) async fn record(
+        &self,
+        collection: &str,
+        loader: &str,
+        snapshot: CoverageSnapshot,
+    ) -> bool {
+        let key = key(collection, loader);
+        let mut snapshots = self.snapshots.write().await;
+
+        if let Some(last) = snapshots.get(&key).and_then(|history| history.last()) {
+            let due = snapshot.time.saturating_sub(last.time) >= self.interval.as_secs();
+            if !due && last.same_coverage(&snapshot) {
+                return false;
+            }
+        }
+
+        if !snapshots.contains_key(&key) && snapshots.len() >= MAX_COLLECTIONS {
+            let watched = self.watched.read().await;
+            let stale = snapshots
+                .iter()
+                .filter(|(key, _)| !watched.contains(*key))
+                .min_by_key(|(_, history)| history.last().map_or(0, |snapshot| snapshot.time))
+                .map(|(key, _)| key.clone());
+            if let Some(stale) = stale {
+                snapshots.remove(&stale);
+            }
+        }
+
+        let history = snapshots.entry(key).or_default();
+        history.push(snapshot);
+        if history.len() > MAX_SNAPSHOTS {
+            let extra = history.len() - MAX_SNAPSHOTS;
+            history.drain(..extra);
+        }
+
+        self.dirty.store(true, Ordering::Release);
+        true
+    }
+
+    /// Keeps the history of a watched collection from being evicted, or lets it be again.
+    pub(crate) async fn set_watched(&self, collection: &str, loader: &str, watched: bool) {
+        let key = key(collection, loader);
+        let mut keys = self.watched.write().await;
+        if watched {
+            keys.insert(key);
+        } else {
+            keys.remove(&key);
+        }
+    }
+
+    /// Every snapshot of a collection for `loader`, oldest first.
+    pub(crate) async fn history(&self, collection: &str, loader: &str) -> Vec<CoverageSnapshot> {
+        self.snapshots
+            .read()
+            .await
+            .get(&key(collection, loader))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, time::Duration};
+
+    use super::{HistoryStore, MAX_COLLECTIONS};
+    use crate::{
+        app::{history::CoverageSnapshot, modrinth::ProjectKey},
+        test_setup::temp_path,
+    };
+
+    fn snapshot(time: u64, supported: &[&str]) -> CoverageSnapshot {
+        CoverageSnapshot {
+            time,
+            projects: 2,
+            versions: vec![(
+                "1.20.4".parse().unwrap(),
+                supported
+                    .iter()
+                    .map(|id| ProjectKey(id.to_string()))
+                    .collect::<HashSet<_>>(),
+            )],
+        }
+    }
+
+    #[tokio::test]
+    async fn only_changes_or_when_due() {
+        let path = temp_path("history/history.json");
+        let _ = std::fs::remove_file(&path);
+
+        let day = Duration::from_secs(24 * 60 * 60);
+        let history = HistoryStore::open(&path, day).await.unwrap();
+
+        assert!(
+            history
+                .record("fixture1", "fabric", snapshot(0, &["a"]))
+                .await
+        );
+        // nothing changed an hour later
+        assert!(
+            !history
+                .record("fixture1", "fabric", snapshot(3600, &["a"]))
+                .await
+        );
+        // but it did two hours later
+        assert!(
+            history
+                .record("fixture1", "fabric", snapshot(7200, &["a", "b"]))
+                .await
+        );
+        // and a day after that, it's recorded either way
+        assert!(
+            history
+                .record("fixture1", "fabric", snapshot(7200 + 86400, &["a", "b"]))
+                .await
+        );
+        // loaders have their own history
+        assert!(
+            history
+                .record("fixture1", "quilt", snapshot(3600, &["a"]))
+                .await
+        );
+
+        let times = |snapshots: Vec<CoverageSnapshot>| {
+            snapshots
+                .iter()
+                .map(|snapshot| snapshot.time)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            times(history.history("fixture1", "fabric").await),
+            [0, 7200, 93600]
+        );
+
+        history.save().await;
+        let reopened = HistoryStore::open(&path, day).await.unwrap();
+        assert_eq!(times(reopened.history("fixture1", "quilt").await), [3600]);
+        assert!(reopened.history("fixture2", "fabric").await.is_empty());
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn keeps_watched_collections() {
+        let history = HistoryStore::new(Duration::from_secs(60 * 60));
+
+        history.set_watched("watched1", "fabric", true).await;
+        history
+            .record("watched1", "fabric", snapshot(0, &["a"]))
+            .await;
+        history
+            .record("viewed01", "fabric", snapshot(1, &["a"]))
+            .await;
+        // someone looking at a lot of collections
+        for n in 0..MAX_COLLECTIONS {
+            history
+                .record(&format!("other{n}"), "fabric", snapshot(2, &["a"]))
+                .await;
+        }
+
+        assert_eq!(history.history("watched1", "fabric").await.len(), 1);
+        assert!(history.history("viewed01", "fabric").await.is_empty());
+    }
+}
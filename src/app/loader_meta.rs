@@ -0,0 +1,189 @@
+//! Sources for mod loader versions, used when an export needs to name a specific loader
+//! build (like a Prism instance).
+//!
+//! The source is picked at startup and provided as an `Arc<dyn LoaderMetadata>` context,
+//! so it can point at the official meta servers, a local mirror, or a fixture file.
+
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use futures::future::BoxFuture;
+use reqwest::Client;
+use serde::Deserialize;
+
+use crate::config::LoaderMetaConfig;
+
+pub const FABRIC_META_ENDPOINT: &str = "https://meta.fabricmc.net/";
+
+#[derive(Debug, thiserror::Error)]
+pub enum LoaderMetaErr {
+    #[error("reqwest error: {0}")]
+    Reqwest(reqwest::Error),
+    #[error("json parse error: {0}")]
+    Json(serde_json::Error),
+    #[error("io error: {0}")]
+    Io(std::io::Error),
+    #[error("loader meta returned error code {0}")]
+    Status(u16),
+    #[error("{loader} isn't supported by this loader meta source")]
+    UnsupportedLoader { loader: String },
+    #[error("no {loader} version found for {game_version}")]
+    NotFound {
+        loader: String,
+        game_version: String,
+    },
+}
+
+pub type LoaderMetaResult<T> = Result<T, LoaderMetaErr>;
+
+pub trait LoaderMetadata: std::fmt::Debug + Send + Sync {
+    /// Finds the loader version that should be installed for `game_version`.
+    fn loader_version<'a>(
+        &'a self,
+        loader: &'a str,
+        game_version: &'a str,
+    ) -> BoxFuture<'a, LoaderMetaResult<String>>;
+
+    /// Whether versions of `loader` can be looked up at all.
+    fn supports(&self, loader: &str) -> bool;
+}
+
+/// Picks the source from the config: a fixture file (for offline use),
+/// otherwise a fabric meta server, which can be a local mirror.
+pub fn from_config(config: &LoaderMetaConfig) -> LoaderMetaResult<Arc<dyn LoaderMetadata>> {
+    match &config.fixture {
+        Some(path) => Ok(Arc::new(FixtureLoaderMetadata::from_file(path)?)),
+        None => Ok(Arc::new(FabricMeta::new(&config.fabric_endpoint))),
+    }
+}
+
+/// Queries a Fabric meta server (or a mirror of one) for the latest stable loader.
+#[derive(Debug)]
+pub struct FabricMeta {
+    client: Client,
+    endpoint: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct FabricLoaderEntry {
+    loader: FabricLoader,
+}
+
+#[derive(Debug, Deserialize)]
+struct FabricLoader {
+    version: String,
+    stable: bool,
+}
+
+impl Default for FabricMeta {
+    fn default() -> Self {
+        Self::new(FABRIC_META_ENDPOINT)
+    }
+}
+
+impl FabricMeta {
+    pub fn new(endpoint: &str) -> Self {
+        let mut endpoint = endpoint.to_string();
+        if !endpoint.ends_with('/') {
+            endpoint.push('/');
+        }
+
+        Self {
+            client: Client::new(),
+            endpoint,
+        }
+    }
+}
+
+impl LoaderMetadata for FabricMeta {
+    fn supports(&self, loader: &str) -> bool {
+        loader == "fabric"
+    }
+
+    fn loader_version<'a>(
+        &'a self,
+        loader: &'a str,
+        game_version: &'a str,
+    ) -> BoxFuture<'a, LoaderMetaResult<String>> {
+        Box::pin(async move {
+            if !self.supports(loader) {
+                return Err(LoaderMetaErr::UnsupportedLoader {
+                    loader: loader.to_string(),
+                });
+            }
+
+            let response = self
+                .client
+                .get(format!(
+                    "{}v2/versions/loader/{game_version}",
+                    self.endpoint
+                ))
+                .send()
+                .await
+                .map_err(LoaderMetaErr::Reqwest)?;
+
+            if !response.status().is_success() {
+                return Err(LoaderMetaErr::Status(response.status().as_u16()));
+            }
+
+            let body = response.text().await.map_err(LoaderMetaErr::Reqwest)?;
+            let entries: Vec<FabricLoaderEntry> =
+                serde_json::from_str(&body).map_err(LoaderMetaErr::Json)?;
+
+            // the meta server lists the newest loaders first
+            entries
+                .into_iter()
+                .find(|entry| entry.loader.stable)
+                .map(|entry| entry.loader.version)
+                .ok_or_else(|| LoaderMetaErr::NotFound {
+                    loader: loader.to_string(),
+                    game_version: game_version.to_string(),
+                })
+        })
+    }
+}
+
+/// Fixed loader versions read from a json file shaped like
+/// `{ "fabric": { "1.20.1": "0.15.11", "*": "0.15.11" } }`,
+/// where `*` is used for any game version that isn't listed.
+#[derive(Debug, Default, Deserialize)]
+#[serde(transparent)]
+pub struct FixtureLoaderMetadata {
+    versions: HashMap<String, HashMap<String, String>>,
+}
+
+impl FixtureLoaderMetadata {
+    pub fn from_file(path: impl AsRef<Path>) -> LoaderMetaResult<Self> {
+        let contents = std::fs::read_to_string(path).map_err(LoaderMetaErr::Io)?;
+        serde_json::from_str(&contents).map_err(LoaderMetaErr::Json)
+    }
+}
+
+impl LoaderMetadata for FixtureLoaderMetadata {
+    fn supports(&self, loader: &str) -> bool {
+        self.versions.contains_key(loader)
+    }
+
+    fn loader_version<'a>(
+        &'a self,
+        loader: &'a str,
+        game_version: &'a str,
+    ) -> BoxFuture<'a, LoaderMetaResult<String>> {
+        Box::pin(async move {
+            let versions =
+                self.versions
+                    .get(loader)
+                    .ok_or_else(|| LoaderMetaErr::UnsupportedLoader {
+                        loader: loader.to_string(),
+                    })?;
+
+            versions
+                .get(game_version)
+                .or_else(|| versions.get("*"))
+                .cloned()
+                .ok_or_else(|| LoaderMetaErr::NotFound {
+                    loader: loader.to_string(),
+                    game_version: game_version.to_string(),
+                })
+        })
+    }
+}
@@ -1,32 +1,60 @@
+pub mod coverage;
+pub mod export;
+pub mod graph;
+pub mod history;
+#[cfg(feature = "ssr")]
+pub mod loader_meta;
 pub mod modrinth;
-
+#[cfg(feature = "ssr")]
+pub mod packwiz;
+pub mod updates;
+pub mod upgrade;
+pub mod versions;
+pub mod watch;
+pub mod workspace;
+
+#[cfg(feature = "ssr")]
+use std::sync::Arc;
 use std::{
     collections::{HashMap, HashSet},
     fmt::Display,
-    path::Path,
     rc::Rc,
     str::{
         pattern::{Pattern, Searcher},
         FromStr,
     },
-    sync::Arc,
-    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use crate::error_template::{AppError, ErrorTemplate};
-use ferinth::structures::{project::Project, version::DependencyType, ID};
 use itertools::Itertools;
 use leptos::{
-    html::{Iframe, Input},
+    html::Input,
     leptos_dom::logging::{console_error, console_log},
     *,
 };
 use leptos_meta::*;
 use leptos_router::*;
-use leptos_use::{use_cookie, utils::JsonCodec};
+use leptos_use::{
+    use_cookie,
+    utils::{FromToStringCodec, JsonCodec},
+};
 use serde::{Deserialize, Serialize};
-
-use self::modrinth::{Collection, ProjectKey};
+use server_fn::codec::{Json, MultipartData, MultipartFormData};
+use wasm_bindgen::JsCast;
+use web_sys::{FormData, HtmlFormElement};
+
+use self::{
+    coverage::CollectionData,
+    export::{ExportFormat, ExportJob, ExportJobState},
+    graph::DependencyTree,
+    history::CoverageChart,
+    modrinth::{ChannelPolicy, ProjectKey},
+    updates::UpdateChecker,
+    upgrade::UpgradePanel,
+    versions::VersionPanel,
+    watch::{NotificationFeed, WatchPanel},
+    workspace::{CollectionSettings, Workspace, WorkspacePanel},
+};
 
 #[component]
 pub fn App() -> impl IntoView {
@@ -53,6 +81,13 @@ pub fn App() -> impl IntoView {
             <main>
                 <Routes>
                     <Route path="" view=HomePage/>
+                    // these render in async mode so the meta tags make it into the <head>
+                    <Route path="/collection/:id" view=CollectionPage ssr=SsrMode::Async/>
+                    <Route
+                        path="/collection/:id/:game_version/:loader"
+                        view=CollectionPage
+                        ssr=SsrMode::Async
+                    />
                 </Routes>
             </main>
         </Router>
@@ -66,10 +101,64 @@ fn HomePage() -> impl IntoView {
 
     let (collections, set_collections) =
         use_cookie::<Vec<String>, JsonCodec>("modrinth_collections");
+    let (collection_settings, set_collection_settings) = use_cookie::<
+        HashMap<String, CollectionSettings>,
+        JsonCodec,
+    >("modrinth_collection_settings");
+    let (workspace_token, set_workspace_token) =
+        use_cookie::<String, FromToStringCodec>("modrinth_workspace");
+
+    let workspace = create_rw_signal(Workspace::from_cookie(
+        collections.get_untracked().unwrap_or_default(),
+        collection_settings.get_untracked().unwrap_or_default(),
+    ));
+
+    // when the workspace is saved on the server, that copy wins over the cookie
+    let remote_workspace = create_local_resource(
+        move || workspace_token.get(),
+        |token| async move {
+            match token {
+                Some(token) => workspace::load_workspace(token).await.map(Some),
+                None => Ok(None),
+            }
+        },
+    );
+    create_effect(move |_| match remote_workspace.get() {
+        Some(Ok(Some(remote))) => workspace.set(remote),
+        Some(Err(err)) => console_error(&format!("couldn't load workspace: {err}")),
+        _ => {}
+    });
 
-    if collections.get_untracked().is_none() {
-        set_collections(Default::default());
-    }
+    create_effect(move |first_run: Option<()>| {
+        let current = workspace.get();
+        set_collections.set(Some(current.to_cookie()));
+        set_collection_settings.set(Some(current.settings_cookie()));
+
+        if first_run.is_none() {
+            return;
+        }
+        if let Some(token) = workspace_token.get_untracked() {
+            spawn_local(async move {
+                if let Err(err) = workspace::save_workspace(token, current).await {
+                    console_error(&format!("couldn't save workspace: {err}"));
+                }
+            });
+        }
+    });
+
+    let add_collection = move |val: String| {
+        console_log("adding collection");
+        workspace.update(|workspace| workspace.add(val));
+    };
+
+    let import_pack = create_action(move |data: &FormData| {
+        let data = data.clone();
+        async move {
+            let id = import_packwiz(data.into()).await?;
+            add_collection(id);
+            Ok::<_, ServerFnError>(())
+        }
+    });
 
     view! {
         <h1>"Mr Modpack"</h1>
@@ -85,33 +174,48 @@ fn HomePage() -> impl IntoView {
                 return;
             }
 
-            set_collections.update(|collections| {
-                if let Some(collections) = collections {
-                    if collections.iter().all(|c| *c != val) {
-                        console_log("pushing collection");
-                        collections.push(val);
-                    }
-                } else {
-                    console_log("setting collection");
-                    *collections = Some(vec![val]);
-                }
-            });
+            add_collection(val);
 
             input.set_value("");
         }>
             <input type="text" class="search" placeholder="Type a Modrinth Collection ID" node_ref=input/>
         </form>
 
+        <form class="packwiz-import" on:submit=move |ev| {
+            ev.prevent_default();
+
+            let form = ev.target().unwrap().unchecked_into::<HtmlFormElement>();
+            let data = FormData::new_with_form(&form).unwrap();
+
+            import_pack.dispatch(data);
+        }>
+            <label>
+                "Or import a zipped packwiz pack: "
+                <input type="file" name="pack" accept=".zip"/>
+            </label>
+            <button class="margin-all" type="submit">
+                {move || if import_pack.pending().get() { "Importing..." } else { "Import" }}
+            </button>
+            {move || match import_pack.value().get() {
+                Some(Err(err)) => Some(view! { <p class="error">{err.to_string()}</p> }),
+                _ => None,
+            }}
+        </form>
+        <UpdateChecker/>
+
+        <WorkspacePanel workspace token=workspace_token set_token=set_workspace_token/>
+        <NotificationFeed/>
+
         <div id="content">
             <For
                 // a function that returns the items we're iterating over; a signal is fine
-                each=move || collections.get().unwrap_or_default().into_iter().rev()
+                each=move || workspace.with(|w| w.collections.iter().map(|c| c.id.clone()).collect_vec())
                 // a unique key for each item
                 key=|id| id.clone()
                 // renders each item to a view
                 let:id
             >
-                <Collection id set_collections/>
+                <Collection id workspace/>
             </For>
         </div>
     }
@@ -185,73 +289,131 @@ impl FromStr for SemanticVersion {
     }
 }
 
+impl SemanticVersion {
+    /// Formats the version the way Minecraft names its releases,
+    /// which leaves off a zero patch (`1.20` rather than `1.20.0`).
+    pub fn to_game_version(&self) -> String {
+        if self.patch == 0 {
+            format!("{}.{}", self.major, self.minor)
+        } else {
+            self.to_string()
+        }
+    }
+}
+
 impl Display for SemanticVersion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
     }
 }
 
+pub(crate) const DEFAULT_LOADER: &str = "fabric";
+pub(crate) const SUPPORTED_LOADERS: &[&str] = &["fabric", "quilt", "forge", "neoforge"];
+/// The most a single uploaded file can be, since uploads are read into memory.
+/// Big enough for an exported pack with its jars.
+#[cfg(feature = "ssr")]
+pub(crate) const MAX_UPLOAD: usize = 256 * 1024 * 1024;
+
+/// Works out the matrix of a collection, adding it to the coverage history.
+#[server]
+async fn load_collection(id: String, loader: String) -> Result<CollectionData, ServerFnError> {
+    let api: Arc<modrinth::ModrinthClient> = use_context().unwrap();
+    let imports: Arc<packwiz::PackwizImports> = use_context().unwrap();
+    let history: Arc<history::HistoryStore> = use_context().unwrap();
+    let jobs: Arc<export::ExportJobs> = use_context().unwrap();
+
+    if !SUPPORTED_LOADERS.contains(&loader.as_str()) {
+        return Err(ServerFnError::new(format!(
+            "{loader} isn't a supported loader"
+        )));
+    }
+
+    let collection = coverage::resolve_collection(&api, &imports, &id)
+        .await
+        .map_err(ServerFnError::new)?;
+    let projects = coverage::lookup_projects(&api, &collection.projects)
+        .await
+        .map_err(|err| ServerFnError::new(format!("couldn't look up the projects: {err}")))?;
+
+    let mut data = CollectionData::new(collection, projects, &loader);
+    data.loader_exports = jobs.exporter().supports_loader(&loader);
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    history
+        .record(
+            &data.collection.id,
+            &loader,
+            history::CoverageSnapshot::new(&data, time),
+        )
+        .await;
+
+    Ok(data)
+}
+
 #[component]
-fn Collection(id: String, set_collections: WriteSignal<Option<Vec<String>>>) -> impl IntoView {
+fn Collection(id: String, workspace: RwSignal<Workspace>) -> impl IntoView {
+    let settings = {
+        let id = id.clone();
+        create_memo(move |_| workspace.with(|w| w.settings(&id)))
+    };
+
     let cloned_id = id.clone();
     let collection = create_local_resource(
-        move || cloned_id.clone(),
-        move |id| async move {
-            let collection = get_collection(id).await?;
-
-            let projects = get_projects(collection.projects.clone()).await?;
-
-            let mut available_versions: HashMap<SemanticVersion, HashSet<ProjectKey>> =
-                HashMap::new();
-
-            for (key, project) in projects.iter() {
-                for version in project
-                    .game_versions
-                    .iter()
-                    .filter_map(|v| v.parse::<SemanticVersion>().ok())
-                {
-                    available_versions
-                        .entry(version)
-                        .and_modify(|projects| {
-                            projects.insert(*key);
-                        })
-                        .or_insert_with(|| {
-                            let mut p = HashSet::with_capacity(1);
-                            p.insert(*key);
-                            p
-                        });
-                }
+        move || (cloned_id.clone(), settings.with(|s| s.loader.clone())),
+        |(id, loader)| load_collection(id, loader),
+    );
+
+    let stored_id = store_value(id.clone());
+    let update_settings = move |f: &dyn Fn(&mut CollectionSettings)| {
+        workspace.update(|w| {
+            if let Some(settings) = w.settings_mut(&stored_id.get_value()) {
+                f(settings)
             }
+        })
+    };
+    let move_by =
+        move |offset: isize| workspace.update(|w| w.move_by(&stored_id.get_value(), offset));
+    let on_pin = Callback::new(move |(project, version): (String, Option<String>)| {
+        update_settings(&|s| match &version {
+            Some(version) => {
+                s.pinned.insert(project.clone(), version.clone());
+            }
+            None => {
+                s.pinned.remove(&project);
+            }
+        })
+    });
 
-            Ok::<_, ServerFnError>((
-                collection,
-                projects,
-                available_versions
-                    .into_iter()
-                    .sorted_by_key(|(_, projects)| projects.len())
-                    .rev()
-                    .collect::<Vec<_>>(),
-            ))
-        },
-    );
+    let comparing = create_rw_signal(false);
+    let charting = create_rw_signal(false);
+    let graphing = create_rw_signal(false);
 
     let close: Rc<dyn Fn()> = Rc::new(move || {
         let cloned_id = id.clone();
-        set_collections.update(move |collections| {
-            if let Some(collections) = collections {
-                collections.remove(
-                    collections
-                        .iter()
-                        .find_position(|c| *c == &cloned_id)
-                        .unwrap()
-                        .0,
-                );
-            }
-        });
+        workspace.update(move |w| w.remove(&cloned_id));
     });
     // this is easier than having to deal with Fn vs FnOnce hell
     let (close, _) = create_signal(close);
 
+    let loader_options = move || {
+        let loader = settings.with(|s| s.loader.clone());
+        SUPPORTED_LOADERS
+            .iter()
+            .map(|l| view! { <option value=*l selected=*l == loader>{*l}</option> })
+            .collect_view()
+    };
+    let channel_options = move || {
+        let channel = settings.with(|s| s.channel);
+        ChannelPolicy::ALL
+            .iter()
+            .map(
+                |c| view! { <option value=c.as_str() selected=*c == channel>{c.as_str()}</option> },
+            )
+            .collect_view()
+    };
+
     view! {
         <Suspense
             fallback=|| view! {
@@ -264,97 +426,575 @@ fn Collection(id: String, set_collections: WriteSignal<Option<Vec<String>>>) ->
                 fallback=|_| {view! { "There was an error" }}
             >
                 {move || {
-                    collection.get().map(move |c| c.map(move |(collection, projects, available_versions)| {
-                    let collection_name = collection.name.clone();
+                    collection.get().map(move |c| c.map(move |data| {
+                        let id = data.collection.id.clone();
+                        let watch_panel = {
+                            let id = id.clone();
+                            let projects = data
+                                .projects
+                                .iter()
+                                .map(|(key, project)| (key.clone(), project.title.clone()))
+                                .collect_vec();
+                            let versions = data
+                                .available_versions
+                                .iter()
+                                .map(|(version, _)| *version)
+                                .collect_vec();
+                            view! {
+                                <WatchPanel
+                                    collection_id=id
+                                    loader=settings.with_untracked(|s| s.loader.clone())
+                                    projects
+                                    versions
+                                />
+                            }
+                        };
+                        let chart = {
+                            let id = id.clone();
+                            move || charting.get().then(|| view! {
+                                <CoverageChart
+                                    collection_id=id.clone()
+                                    loader=settings.with_untracked(|s| s.loader.clone())
+                                />
+                            })
+                        };
+                        let upgrade_panel = {
+                            let id = id.clone();
+                            let versions = data
+                                .available_versions
+                                .iter()
+                                .map(|(version, _)| *version)
+                                .sorted()
+                                .rev()
+                                .collect_vec();
+                            let from = data.best_version().map(|(version, _)| *version);
+                            move || comparing.get().then(|| view! {
+                                <UpgradePanel
+                                    collection_id=id.clone()
+                                    loader=settings.with_untracked(|s| s.loader.clone())
+                                    versions=versions.clone()
+                                    from
+                                />
+                            })
+                        };
+
+                        let dependency_tree = {
+                            let id = id.clone();
+                            let versions = data
+                                .available_versions
+                                .iter()
+                                .map(|(version, _)| *version)
+                                .collect_vec();
+                            move || graphing.get().then(|| view! {
+                                <DependencyTree
+                                    collection_id=id.clone()
+                                    settings=settings.into()
+                                    versions=versions.clone()
+                                />
+                            })
+                        };
+
+                        view! {
+                        <h2>{data.collection.name.clone()}</h2>
+                        <p class="collection-id">
+                            <A href=format!("/collection/{id}")>{id.clone()}</A>
+                        </p>
+                        <p class="collection-description">{data.collection.description.clone()}</p>
+
+                        <div class="collection-settings">
+                            <label>
+                                "Loader "
+                                <select on:change=move |ev| {
+                                    let loader = event_target_value(&ev);
+                                    update_settings(&|s| s.loader.clone_from(&loader));
+                                }>
+                                    {loader_options}
+                                </select>
+                            </label>
+                            <label>
+                                "Versions "
+                                <select on:change=move |ev| {
+                                    if let Ok(channel) = event_target_value(&ev).parse() {
+                                        update_settings(&|s| s.channel = channel);
+                                    }
+                                }>
+                                    {channel_options}
+                                </select>
+                            </label>
+                            <button class="margin-all" on:click=move |_| move_by(-1)>"Move up"</button>
+                            <button class="margin-all" on:click=move |_| move_by(1)>"Move down"</button>
+                            <button class="margin-all" on:click=move |_| comparing.update(|c| *c = !*c)>
+                                {move || if comparing.get() { "Stop comparing" } else { "Compare versions" }}
+                            </button>
+                            <button class="margin-all" on:click=move |_| charting.update(|c| *c = !*c)>
+                                {move || if charting.get() { "Hide history" } else { "Coverage history" }}
+                            </button>
+                            <button class="margin-all" on:click=move |_| graphing.update(|g| *g = !*g)>
+                                {move || if graphing.get() { "Hide dependencies" } else { "Dependencies" }}
+                            </button>
+                        </div>
+                        {watch_panel}
+                        {chart}
+                        {upgrade_panel}
+                        {dependency_tree}
+
+                        <Spoiler close={close.get_untracked()}>
+                            <CollectionMatrix data settings on_pin=Some(on_pin)/>
+                        </Spoiler>
+                    }}))
+                }}
+            </ErrorBoundary>
+        </Suspense>
+    }
+}
 
-                    view! {
-                    <h2>{collection.name}</h2>
-                    <p class="collection-id">{collection.id}</p>
-
-                    <Spoiler close={close.get_untracked()}>
-                    <div class="collection-table">
-                    <table>
-                        <tbody>
-                            <tr>
-                                <th>
-                                    "Mod"
-                                </th>
-                                {available_versions.clone().into_iter().map(|(version, projects)| {
-                                    let collection_name = collection_name.clone();
-                                    let projects_2 = projects.clone();
-                                    let download_loading = create_rw_signal(false);
-                                    view! {
-                                    <td>
-                                        <span class="version">
-                                            {version.to_string()}
-                                        </span>
-                                        <span class="percentage">
-                                            {format!("{:.1}", (projects.len() as f64 / collection.projects.len() as f64) * 100.0)}
-                                            "%"
-                                        </span>
-                                        <button
-                                            class={move || if download_loading.get() {
-                                                "download downloading"
-                                            } else {
-                                                "download"
-                                            }}
-                                            on:click=move |ev| {
-                                                ev.prevent_default();
-
-                                                if download_loading.get_untracked() {
-                                                    return;
-                                                }
-
-                                                let collection_name = collection_name.clone();
-                                                let projects_2 = projects_2.clone();
-                                                download_loading.set(true);
-
-                                                spawn_local(async move {
-                                                    let zip = download_zip(collection_name.clone(), version, projects_2.clone()).await.unwrap();
-
-                                                    download_loading.set(false);
-
-                                                    window().open_with_url(&zip).unwrap();
-                                                });
-                                            }
-                                        >
-                                            {move || if download_loading.get() {
-                                                "Downloading..."
-                                            } else {
-                                                "Download all"
-                                            }}
-                                        </button>
-
-                                    </td>
-                                }}).collect_view()}
-                            </tr>
-
-                            {projects.into_iter().map(|(key, project)| view! {
-                                <tr>
-                                    <th><a href={format!("https://modrinth.com/mod/{}", project.slug)} target="_blank">
-                                        {project.title}
-                                    </a></th>
-                                    {available_versions.iter().map(|(_, projects)| view! {
-                                        <td>
-                                            {if projects.contains(&key) {
-                                                "✅"
-                                            } else {
-                                                "❌"
-                                            }}
-                                        </td>
-                                    }).collect_view()}
-                                </tr>
-                            }).collect_view()}
-                        </tbody>
-                    </table>
-                    </div>
-                    </Spoiler>
-                }}))}}
+/// A single collection, addressable by url so it can be shared.
+///
+/// The loader and game version can be given either in the path
+/// (`/collection/:id/:game_version/:loader`) or as `?loader=` and `?version=` query parameters,
+/// which take precedence. `?channel=` picks the release channels downloads may use,
+/// and `?min=` hides game versions below that percentage of coverage.
+#[component]
+fn CollectionPage() -> impl IntoView {
+    let params = use_params_map();
+    let query = use_query_map();
+
+    let id = move || params.with(|p| p.get("id").cloned().unwrap_or_default());
+    let loader = move || {
+        query
+            .with(|q| q.get("loader").cloned())
+            .or_else(|| params.with(|p| p.get("loader").cloned()))
+            .unwrap_or_else(|| DEFAULT_LOADER.to_string())
+    };
+    let selected = move || {
+        query
+            .with(|q| q.get("version").cloned())
+            .or_else(|| params.with(|p| p.get("game_version").cloned()))
+            .and_then(|v| v.parse::<SemanticVersion>().ok())
+    };
+    let channel = move || {
+        query
+            .with(|q| {
+                q.get("channel")
+                    .and_then(|c| c.parse::<ChannelPolicy>().ok())
+            })
+            .unwrap_or_default()
+    };
+    let min_coverage = move || {
+        query
+            .with(|q| q.get("min").and_then(|min| min.parse::<f64>().ok()))
+            .unwrap_or(0.0)
+    };
+
+    let collection = create_resource(
+        move || (id(), loader()),
+        |(id, loader)| load_collection(id, loader),
+    );
+
+    view! {
+        <h1><A href="/">"Mr Modpack"</A></h1>
+
+        <Suspense
+            fallback=|| view! {
+                <p>
+                    "Loading..."
+                </p>
+            }
+        >
+            <ErrorBoundary
+                fallback=|_| {view! { "There was an error" }}
+            >
+                {move || {
+                    collection.get().map(move |c| c.map(move |data| {
+                        let loader = loader();
+                        let channel = channel();
+                        let selected = selected();
+                        let min_coverage = min_coverage();
+
+                        // the chosen version if there is one, otherwise the best one
+                        let summary = match selected
+                            .and_then(|selected| {
+                                data.available_versions.iter().find(|(v, _)| *v == selected)
+                            })
+                            .or_else(|| data.best_version())
+                        {
+                            Some((version, projects)) => format!(
+                                "{:.1}% of {} mods work on Minecraft {} with {loader}",
+                                data.coverage(projects),
+                                data.collection.projects.len(),
+                                version.to_game_version(),
+                            ),
+                            None => format!("None of the mods support {loader}"),
+                        };
+
+                        let loader_options = SUPPORTED_LOADERS
+                            .iter()
+                            .map(|l| view! {
+                                <option value=*l selected=*l == loader>{*l}</option>
+                            })
+                            .collect_view();
+                        let channel_options = ChannelPolicy::ALL
+                            .iter()
+                            .map(|c| view! {
+                                <option value=c.as_str() selected=*c == channel>{c.as_str()}</option>
+                            })
+                            .collect_view();
+
+                        let settings = CollectionSettings {
+                            loader,
+                            channel,
+                            ..Default::default()
+                        };
+
+                        view! {
+                            <div id="content">
+                                <Title text=format!("{} - Mr Modpack", data.collection.name)/>
+                                <Meta name="description" content=summary.clone()/>
+                                <Meta property="og:site_name" content="Mr Modpack"/>
+                                <Meta property="og:type" content="website"/>
+                                <Meta property="og:title" content=data.collection.name.clone()/>
+                                <Meta property="og:description" content=summary.clone()/>
+
+                                <h2>{data.collection.name.clone()}</h2>
+                                <p class="collection-id">{data.collection.id.clone()}</p>
+                                <p class="collection-description">{data.collection.description.clone()}</p>
+                                <p class="collection-summary">{summary}</p>
+
+                                <Form method="GET" action="" class="collection-filters">
+                                    <label>
+                                        "Loader "
+                                        <select name="loader">
+                                            {loader_options}
+                                        </select>
+                                    </label>
+                                    <label>
+                                        "Versions "
+                                        <select name="channel">
+                                            {channel_options}
+                                        </select>
+                                    </label>
+                                    <label>
+                                        "Minimum coverage "
+                                        <input type="number" name="min" min="0" max="100" value=min_coverage/>
+                                        "%"
+                                    </label>
+                                    <button class="margin-all" type="submit">"Apply"</button>
+                                </Form>
+
+                                <CollectionMatrix data settings selected min_coverage/>
+                            </div>
+                        }
+                    }))
+                }}
             </ErrorBoundary>
         </Suspense>
     }
 }
 
+/// Which project (and its title) is drilled into, on which game version.
+type Drill = (ProjectKey, String, SemanticVersion);
+
+#[component]
+fn CollectionMatrix(
+    data: CollectionData,
+    #[prop(into)] settings: MaybeSignal<CollectionSettings>,
+    #[prop(default = None)] selected: Option<SemanticVersion>,
+    #[prop(optional)] min_coverage: f64,
+    /// Pins a version of a project (by id) for exports, or unpins it with `None`.
+    /// Versions can only be looked at without it.
+    #[prop(default = None)]
+    on_pin: Option<Callback<(String, Option<String>)>>,
+) -> impl IntoView {
+    let available_versions = data
+        .available_versions
+        .iter()
+        .filter(|(_, projects)| data.coverage(projects) >= min_coverage)
+        .cloned()
+        .collect_vec();
+    let no_loader_exports = !data.loader_exports;
+
+    let settings = Signal::derive(move || settings.get());
+    let drill = create_rw_signal(None::<Drill>);
+    // a mod's row opens the chosen version, or the best one
+    let row_version = selected.or_else(|| available_versions.first().map(|(version, _)| *version));
+
+    view! {
+        <div class="collection-table">
+        <table>
+            <tbody>
+                <tr>
+                    <th>
+                        "Mod"
+                    </th>
+                    {available_versions.clone().into_iter().map(|(version, projects)| {
+                        let collection_id = data.collection.id.clone();
+                        view! {
+                        <td class:selected=selected == Some(version)>
+                            <A class="version" href=move || settings.with(|settings| format!(
+                                "/collection/{}/{}/{}?channel={}",
+                                collection_id,
+                                version.to_game_version(),
+                                settings.loader,
+                                settings.channel,
+                            ))>
+                                {version.to_string()}
+                            </A>
+                            <span class="percentage">
+                                {format!("{:.1}", data.coverage(&projects))}
+                                "%"
+                            </span>
+                            <DownloadButton
+                                collection_id=data.collection.id.clone()
+                                version
+                                settings
+                                projects=projects.clone()
+                                format=ExportFormat::ClientPack
+                                label="Client pack"
+                            />
+                            <DownloadButton
+                                collection_id=data.collection.id.clone()
+                                version
+                                settings
+                                projects=projects.clone()
+                                format=ExportFormat::ServerPack
+                                label="Server pack"
+                                disabled=no_loader_exports
+                            />
+                            <DownloadButton
+                                collection_id=data.collection.id.clone()
+                                version
+                                settings
+                                projects=projects.clone()
+                                format=ExportFormat::Prism
+                                label="Prism instance"
+                                disabled=no_loader_exports
+                            />
+                            <DownloadButton
+                                collection_id=data.collection.id.clone()
+                                version
+                                settings
+                                projects
+                                format=ExportFormat::Mrpack
+                                label="Modrinth pack"
+                                disabled=no_loader_exports
+                            />
+                        </td>
+                    }}).collect_view()}
+                </tr>
+
+                {data.projects.into_iter().map(|(key, project)| {
+                    let is_pinned = {
+                        let id = project.id.clone();
+                        move || settings.with(|settings| settings.pinned.contains_key(&id))
+                    };
+                    let open_row = {
+                        let key = key.clone();
+                        let title = project.title.clone();
+                        move |_| {
+                            if let Some(version) = row_version {
+                                drill.set(Some((key.clone(), title.clone(), version)));
+                            }
+                        }
+                    };
+
+                    view! {
+                    <tr>
+                        <th>
+                            <button class="project" on:click=open_row>
+                                {project.title.clone()}
+                                {move || is_pinned().then_some(" 📌")}
+                            </button>
+                            <a href={format!("https://modrinth.com/mod/{}", project.slug)} target="_blank">
+                                "↗"
+                            </a>
+                        </th>
+                        {available_versions.iter().map(|(version, projects)| {
+                            let version = *version;
+                            let key = key.clone();
+                            let title = project.title.clone();
+                            let supported = projects.contains(&key);
+                            view! {
+                            <td
+                                class="drill"
+                                class:selected=selected == Some(version)
+                                on:click=move |_| drill.set(Some((key.clone(), title.clone(), version)))
+                            >
+                                {if supported {
+                                    "✅"
+                                } else {
+                                    "❌"
+                                }}
+                            </td>
+                        }}).collect_view()}
+                    </tr>
+                }}).collect_view()}
+            </tbody>
+        </table>
+        </div>
+        {move || drill.get().map(|(project, title, game_version)| {
+            let project_id = project.0.clone();
+            let pinned = {
+                let project_id = project_id.clone();
+                Signal::derive(move || settings.with(|settings| settings.pinned.get(&project_id).cloned()))
+            };
+            let on_pin = on_pin.map(|on_pin| {
+                Callback::new(move |version| on_pin((project_id.clone(), version)))
+            });
+
+            view! {
+                <VersionPanel
+                    project
+                    title
+                    loader=settings.with(|settings| settings.loader.clone())
+                    game_version
+                    pinned
+                    on_pin
+                    on_close=Callback::new(move |_| drill.set(None))
+                />
+            }
+        })}
+    }
+}
+
+/// Waits in the browser without blocking it.
+async fn sleep(duration: std::time::Duration) {
+    let (done, wait) = futures::channel::oneshot::channel();
+    set_timeout(
+        move || {
+            let _ = done.send(());
+        },
+        duration,
+    );
+    let _ = wait.await;
+}
+
+#[component]
+fn DownloadButton(
+    collection_id: String,
+    version: SemanticVersion,
+    settings: Signal<CollectionSettings>,
+    projects: HashSet<ProjectKey>,
+    format: ExportFormat,
+    label: &'static str,
+    /// For formats that can't be exported with the collection's loader
+    #[prop(optional)]
+    disabled: bool,
+) -> impl IntoView {
+    let job = create_rw_signal(None::<ExportJob>);
+    let error = create_rw_signal(None::<String>);
+    let busy = move || job.with(|job| job.as_ref().is_some_and(|job| !job.is_done()));
+
+    view! {
+        <button
+            class={move || if busy() {
+                "download downloading"
+            } else {
+                "download"
+            }}
+            disabled=disabled
+            title=disabled.then_some("Loader versions can't be looked up for this loader, only client packs can be exported")
+            on:click=move |ev| {
+                ev.prevent_default();
+
+                if busy() {
+                    return;
+                }
+
+                let collection_id = collection_id.clone();
+                let settings = settings.get_untracked();
+                let projects = projects.clone();
+                error.set(None);
+
+                spawn_local(async move {
+                    let mut polled = start_export(
+                        collection_id,
+                        version,
+                        settings,
+                        projects,
+                        format,
+                    )
+                    .await;
+
+                    // the export runs in the background, so check on it until it's done
+                    loop {
+                        match polled {
+                            Ok(current) if current.is_done() => {
+                                if let ExportJobState::Finished { report } = &current.state {
+                                    window().open_with_url(&report.url).unwrap();
+                                }
+                                job.set(Some(current));
+                                break;
+                            }
+                            Ok(current) => {
+                                let id = current.id.clone();
+                                job.set(Some(current));
+                                sleep(std::time::Duration::from_secs(1)).await;
+                                // cancelled in the meantime
+                                if !busy() {
+                                    break;
+                                }
+                                polled = export_status(id).await;
+                            }
+                            Err(err) => {
+                                job.set(None);
+                                error.set(Some(err.to_string()));
+                                break;
+                            }
+                        }
+                    }
+                });
+            }
+        >
+            {move || match job.get().map(|job| job.state) {
+                Some(ExportJobState::Pending) => "Queued...",
+                Some(ExportJobState::Running) => "Building...",
+                _ => label,
+            }}
+        </button>
+        {move || busy().then(|| view! {
+            <button class="download" on:click=move |ev| {
+                ev.prevent_default();
+
+                let Some(id) = job.get_untracked().map(|job| job.id) else {
+                    return;
+                };
+                spawn_local(async move {
+                    match cancel_export(id).await {
+                        Ok(cancelled) => job.set(Some(cancelled)),
+                        Err(err) => error.set(Some(err.to_string())),
+                    }
+                });
+            }>
+                "Cancel"
+            </button>
+        })}
+        {move || error.get().map(|error| view! {
+            <p class="export-report">{format!("Export failed: {error}")}</p>
+        })}
+        {move || job.get().and_then(|job| match job.state {
+            ExportJobState::Failed { error } => Some(view! {
+                <p class="export-report">{format!("Export failed: {error}")}</p>
+            }.into_view()),
+            ExportJobState::Finished { report } => Some(view! {
+                {(!report.excluded.is_empty()).then(|| view! {
+                    <details class="export-report">
+                        <summary>{format!("{} excluded", report.excluded.len())}</summary>
+                        <ul>
+                            {report.excluded.into_iter().map(|e| view! {
+                                <li>{e.title}": "{e.reason.to_string()}</li>
+                            }).collect_view()}
+                        </ul>
+                    </details>
+                })}
+                {(report.retries > 0).then(|| view! {
+                    <p class="export-report">{format!("{} requests to Modrinth had to be retried", report.retries)}</p>
+                })}
+            }.into_view()),
+            _ => None,
+        })}
+    }
+}
+
 #[component]
 fn Spoiler(close: Rc<dyn Fn()>, children: Children) -> impl IntoView {
     let visible = create_rw_signal(true);
@@ -378,194 +1018,117 @@ fn Spoiler(close: Rc<dyn Fn()>, children: Children) -> impl IntoView {
     }
 }
 
-#[server]
-async fn get_projects(
-    projects: Vec<ProjectKey>,
-) -> Result<Vec<(ProjectKey, Project)>, ServerFnError> {
+/// Reads an uploaded packwiz zip and stores its mods as a collection, returning its id.
+#[server(input = MultipartFormData)]
+async fn import_packwiz(data: MultipartData) -> Result<String, ServerFnError> {
     let api: Arc<modrinth::ModrinthClient> = use_context().unwrap();
+    let imports: Arc<packwiz::PackwizImports> = use_context().unwrap();
 
-    let all_projects = api.global_projects.read().await;
+    let mut data = data.into_inner().unwrap();
 
-    let mut res = Vec::with_capacity(projects.len());
-
-    for project in projects {
-        res.push((project, all_projects[project.0].clone()));
+    let mut archive = Vec::new();
+    while let Some(mut field) = data.next_field().await? {
+        if field.name() != Some("pack") {
+            continue;
+        }
+        while let Some(chunk) = field.chunk().await? {
+            if archive.len() + chunk.len() > MAX_UPLOAD {
+                return Err(ServerFnError::new(format!(
+                    "the pack can't be bigger than {} MB",
+                    MAX_UPLOAD / (1024 * 1024)
+                )));
+            }
+            archive.extend_from_slice(&chunk);
+        }
     }
 
-    Ok(res)
-}
-
-#[server]
-async fn get_collection(collection_id: String) -> Result<Collection, ServerFnError> {
-    let api: Arc<modrinth::ModrinthClient> = use_context().unwrap();
+    if archive.is_empty() {
+        return Err(ServerFnError::new("no pack was uploaded"));
+    }
 
-    api.get_collection(&collection_id)
+    let pack = packwiz::read_pack(archive)
         .await
-        .map_err(ServerFnError::new)
-}
+        .map_err(ServerFnError::new)?;
 
-const LOADERS: &[&str] = &["fabric"];
+    imports.import(&api, pack).await.map_err(ServerFnError::new)
+}
 
-#[server]
-async fn download_zip(
-    collection_name: String,
+/// Queues an export, or finds the same one that's already queued or finished.
+#[server(input = Json)]
+async fn start_export(
+    collection_id: String,
     release_version: SemanticVersion,
+    settings: CollectionSettings,
     projects: HashSet<ProjectKey>,
-) -> Result<String, ServerFnError> {
-    use async_zip::{base::write::ZipFileWriter, Compression, ZipEntryBuilder};
-
+    format: ExportFormat,
+) -> Result<ExportJob, ServerFnError> {
     let api: Arc<modrinth::ModrinthClient> = use_context().unwrap();
+    let imports: Arc<packwiz::PackwizImports> = use_context().unwrap();
+    let jobs: Arc<export::ExportJobs> = use_context().unwrap();
+
+    if !SUPPORTED_LOADERS.contains(&settings.loader.as_str()) {
+        return Err(ServerFnError::new(format!(
+            "{} isn't a supported loader",
+            settings.loader
+        )));
+    }
+    jobs.exporter()
+        .check_loader(&settings.loader, format)
+        .map_err(ServerFnError::new)?;
 
-    let game_version = release_version.to_string();
-    let game_versions: &[&str] = &[&game_version];
-
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_millis();
-
-    let opts: LeptosOptions = use_context().unwrap();
-    let output_folder = AsRef::<Path>::as_ref(&opts.site_root).join("temp-download-all");
-    let _ = tokio::fs::create_dir(&output_folder).await;
-
-    let filename = output_folder.join(format!("{}-{now}.zip", collection_name));
-    let mut zip = tokio::fs::File::create(&filename).await.unwrap();
-    let mut zip = ZipFileWriter::with_tokio(&mut zip);
-
-    let mut downloaded = HashSet::new();
-
-    let mut todo = projects.into_iter().map(|p| (p, 0)).collect_vec();
-
-    // todo: do multiple downloads simultaneously
-    while let Some((project, ident)) = todo.pop() {
-        let global_projects = api.global_projects.read().await;
-        let project = &global_projects[project.0];
-
-        if downloaded.contains(&project.id) {
-            println!(
-                "|{}{} already downloaded",
-                "  ".repeat(ident + 1),
-                project.id
-            );
-            continue;
-        }
-
-        let versions = api
-            .get_project_versions(&project.slug, LOADERS, game_versions)
-            .await?;
-
-        if versions.is_empty() {
-            println!(
-                "|{}nothing found for {} ({})",
-                "  ".repeat(ident),
-                project.title,
-                game_versions[0]
-            );
-            continue;
-        }
-
-        println!(
-            "|{}==={} ({})===",
-            "  ".repeat(ident),
-            project.title,
-            game_versions[0]
-        );
-
-        let (latest_version, latest_semver) = versions
-            .into_iter()
-            .map(|v| {
-                let semver = v
-                    .version_number
-                    .replace(&game_version, "")
-                    .replace(
-                        &format!("{}.{}", release_version.major, release_version.minor),
-                        "",
-                    )
-                    .parse::<SemanticVersion>()
-                    .unwrap_or_else(|_| {
-                        console_error(&format!(
-                            "|{} wasn't parsable for {}!",
-                            v.version_number, project.title
-                        ));
-                        SemanticVersion {
-                            major: 0,
-                            minor: 0,
-                            patch: 0,
-                        }
-                    });
-
-                (v, semver)
-            })
-            .max_by_key(|(v, _)| v.date_published)
-            //.max_by_key(|(_, semver)| *semver)
-            .unwrap();
-
-        // todo: or_else(first_file)
-        let primary_file = latest_version
-            .files
-            .iter()
-            .find(|f| f.primary)
-            .unwrap_or_else(|| latest_version.files.first().unwrap());
-        println!(
-            "|{}{} (v{}) : {}",
-            "  ".repeat(ident + 1),
-            latest_version.name,
-            latest_semver,
-            primary_file.filename
-        );
-
-        let jar = api.download_file(primary_file.url.clone()).await.unwrap();
-
-        let mut dst = output_folder.to_path_buf();
-        dst.push(&primary_file.filename);
-
-        let builder =
-            ZipEntryBuilder::new(primary_file.filename.clone().into(), Compression::Deflate);
-        zip.write_entry_whole(builder, &jar).await.unwrap();
-
-        downloaded.insert(project.id.clone());
-
-        // do this before calling `get_project`
-        // otherwise causes deadlock
-        drop(global_projects);
-
-        for dep in latest_version.dependencies {
-            let project_id = dep.project_id.unwrap();
-
-            if dep.dependency_type != DependencyType::Required {
-                println!(
-                    "|{}- {} is not required",
-                    "  ".repeat(ident + 1),
-                    project_id
-                );
-                continue;
-            }
-
-            if downloaded.contains(&project_id) {
-                println!(
-                    "|{}- {} already downloaded",
-                    "  ".repeat(ident + 1),
-                    project_id
-                );
-                continue;
-            }
-
-            let project = api.get_project(&project_id).await?;
+    // the name ends up in the archive, so it's looked up rather than taken from the client
+    let collection = coverage::resolve_collection(&api, &imports, &collection_id)
+        .await
+        .map_err(ServerFnError::new)?;
+
+    // only projects of the collection can be exported under its name
+    if let Some(key) = projects
+        .iter()
+        .find(|key| !collection.projects.contains(key))
+    {
+        return Err(ServerFnError::new(format!(
+            "{key} isn't part of the collection"
+        )));
+    }
 
-            todo.push((project, ident + 1));
-        }
+    // collections can list slugs too, but every project is exported under its id
+    let mut keys = HashSet::with_capacity(projects.len());
+    for key in &projects {
+        let project = api
+            .project(key)
+            .await
+            .map_err(|err| ServerFnError::new(format!("couldn't find project {key}: {err}")))?;
+        keys.insert(ProjectKey(project.id));
     }
 
-    println!("finished download!");
+    Ok(jobs
+        .start(export::ExportRequest {
+            collection_id: collection.id,
+            collection_name: collection.name,
+            release_version,
+            settings,
+            projects: keys,
+            format,
+        })
+        .await)
+}
 
-    zip.close().await.unwrap();
+#[server]
+async fn export_status(id: String) -> Result<ExportJob, ServerFnError> {
+    let jobs: Arc<export::ExportJobs> = use_context().unwrap();
 
-    tokio::task::spawn(async move {
-        tokio::time::sleep(Duration::from_secs(2 * 60)).await;
-        tokio::fs::remove_file(filename).await.unwrap()
-    });
+    jobs.get(&id)
+        .await
+        .ok_or_else(|| ServerFnError::new("this export has expired"))
+}
 
-    Ok(format!("/temp-download-all/{}-{now}.zip", collection_name))
+#[server]
+async fn cancel_export(id: String) -> Result<ExportJob, ServerFnError> {
+    let jobs: Arc<export::ExportJobs> = use_context().unwrap();
+
+    jobs.cancel(&id)
+        .await
+        .ok_or_else(|| ServerFnError::new("this export has expired"))
 }
 
 #[cfg(test)]
@@ -1,17 +1,21 @@
-use std::time::Duration;
+use std::{collections::HashMap, future::Future, io, path::Path, time::Duration};
 
-use bytes::Bytes;
-use ferinth::{
-    structures::{project::Project, version::Version},
-    Ferinth,
-};
-use reqwest::{Client, ClientBuilder, IntoUrl};
-use serde::{Deserialize, Serialize};
-use tokio::{sync::RwLock, time::Interval};
+use ferinth::structures::{project::Project, version::Version};
+use leptos::logging;
+use reqwest::{Client, ClientBuilder, IntoUrl, Response};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use tokio::{io::AsyncWriteExt as _, sync::RwLock};
 
-use super::{Collection, ProjectID, ProjectKey, UserID};
+use super::{
+    rate_limit::RateLimiter,
+    retry::{self, RetryPolicy},
+    Collection, ProjectID, ProjectKey, UserID,
+};
+use crate::config::ModrinthConfig;
 
-const MODRINTH_ENDPOINT: &str = "https://api.modrinth.com/v3/";
+/// The root of the modrinth api, which has the `v2/` and `v3/` apis under it.
+pub const MODRINTH_ENDPOINT: &str = "https://api.modrinth.com/";
 
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum ApiErr {
@@ -19,14 +23,35 @@ pub(crate) enum ApiErr {
     Reqwest(reqwest::Error),
     #[error("json parse error: {0}")]
     Json(serde_json::Error),
-    #[error("ferinth error: {0}")]
-    Ferinth(ferinth::Error),
     #[error("not found")]
     NotFound,
+    #[error("api returned error code {0}")]
+    Status(u16),
+    #[error("this packwiz import has expired, upload the pack again")]
+    ImportExpired,
+    #[error("io error: {0}")]
+    Io(io::Error),
 }
 
 pub(crate) type ApiResult<T> = Result<T, ApiErr>;
 
+/// Ids and slugs are put into urls, so anything that could change which url is requested
+/// can't be one.
+fn check_id(id: &str) -> ApiResult<()> {
+    let valid = !id.is_empty()
+        && id.len() <= 64
+        && id != "."
+        && id != ".."
+        && !id.contains(['/', '\\', '?', '#', '%'])
+        && !id.chars().any(char::is_control);
+
+    if valid {
+        Ok(())
+    } else {
+        Err(ApiErr::NotFound)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct InnerCollection {
     id: String,
@@ -38,19 +63,18 @@ struct InnerCollection {
 
 #[derive(Debug)]
 pub struct ModrinthClient {
-    v2: Ferinth,
-    v3: Client,
-    request_window: RwLock<Interval>,
-    pub(crate) global_projects: RwLock<Vec<Project>>,
+    client: Client,
+    v2: String,
+    v3: String,
+    rate_limiter: RateLimiter,
+    retry: RetryPolicy,
+    /// Every project that was fetched, by id
+    pub(crate) projects: RwLock<HashMap<String, Project>>,
 }
 
 impl Default for ModrinthClient {
     fn default() -> Self {
-        Self::new(
-            env!("CARGO_PKG_NAME"),
-            Some(env!("CARGO_PKG_VERSION")),
-            Some("notaflyinggoose@gmail.com"),
-        )
+        Self::from_config(&ModrinthConfig::default())
     }
 }
 
@@ -69,38 +93,138 @@ impl ModrinthClient {
             user_agent.push(')');
         }
 
-        let mut request_window = tokio::time::interval(Duration::from_secs_f32(60.0 / 250.0));
-        request_window.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
-
         Self {
-            v2: Ferinth::new(name, version, contact, None).unwrap(),
-            v3: ClientBuilder::default()
+            client: ClientBuilder::default()
                 .user_agent(user_agent)
                 .build()
                 .unwrap(),
-            request_window: RwLock::new(request_window),
-            global_projects: Default::default(),
+            v2: format!("{MODRINTH_ENDPOINT}v2/"),
+            v3: format!("{MODRINTH_ENDPOINT}v3/"),
+            rate_limiter: RateLimiter::per_minute(250),
+            retry: RetryPolicy::default(),
+            projects: Default::default(),
         }
     }
 
-    async fn wait_for_window(&self) {
-        let mut lock = self.request_window.write().await;
-        lock.tick().await;
+    /// Points the client at another api root, like a mirror or a fake server for tests.
+    pub fn with_endpoint(mut self, endpoint: &str) -> Self {
+        let endpoint = endpoint.trim_end_matches('/');
+        self.v2 = format!("{endpoint}/v2/");
+        self.v3 = format!("{endpoint}/v3/");
+        self
     }
 
-    pub(crate) async fn download_file<U>(&self, url: U) -> ApiResult<Bytes>
+    /// Keeps to `requests_per_minute` on average, allowing bursts of up to that many.
+    pub fn with_rate_limit(mut self, requests_per_minute: u32) -> Self {
+        self.rate_limiter = RateLimiter::per_minute(requests_per_minute);
+        self
+    }
+
+    /// Changes how failed requests are retried.
+    pub(crate) fn with_retries(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn from_config(config: &ModrinthConfig) -> Self {
+        Self::new(
+            env!("CARGO_PKG_NAME"),
+            Some(env!("CARGO_PKG_VERSION")),
+            config.contact.as_deref().filter(|c| !c.is_empty()),
+        )
+        .with_endpoint(&config.endpoint)
+        .with_rate_limit(config.requests_per_minute)
+        .with_retries(RetryPolicy {
+            attempts: config.retry_attempts,
+            base_delay: Duration::from_millis(config.retry_delay_ms),
+            ..Default::default()
+        })
+    }
+
+    /// Runs `request` until it succeeds, fails in a way that won't go away, or runs out
+    /// of attempts.
+    async fn retrying<T, F, Fut>(&self, mut request: F) -> ApiResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = ApiResult<T>>,
+    {
+        let mut attempt = 1;
+
+        loop {
+            match request().await {
+                Err(err) if err.is_transient() && attempt < self.retry.attempts => {
+                    let delay = self.retry.delay(attempt);
+                    logging::warn!("request failed ({err}), retrying in {delay:?}");
+
+                    retry::record_retry();
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Sends a request once the rate limit allows it.
+    async fn send(&self, url: &str) -> ApiResult<Response> {
+        self.rate_limiter.acquire().await;
+        let response = self.client.get(url).send().await.map_err(ApiErr::Reqwest)?;
+        self.rate_limiter
+            .observe(response.status(), response.headers())
+            .await;
+
+        Ok(response)
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, url: String) -> ApiResult<T> {
+        self.retrying(|| async {
+            let response = self.send(&url).await?;
+
+            if !response.status().is_success() {
+                match response.status().as_u16() {
+                    404 => return Err(ApiErr::NotFound),
+                    other => return Err(ApiErr::Status(other)),
+                }
+            }
+
+            let body = response.text().await.map_err(ApiErr::Reqwest)?;
+
+            serde_json::from_str(&body).map_err(ApiErr::Json)
+        })
+        .await
+    }
+
+    /// Downloads a file to `path` a chunk at a time, so it never has to fit in memory,
+    /// returning its sha512. A download that breaks off halfway is started over, using up
+    /// the same attempts as a request that fails outright.
+    pub(crate) async fn download_file<U>(&self, url: U, path: &Path) -> ApiResult<String>
     where
         U: IntoUrl,
     {
-        self.wait_for_window().await;
-        self.v3
-            .get(url)
-            .send()
-            .await
-            .map_err(ApiErr::Reqwest)?
-            .bytes()
-            .await
-            .map_err(ApiErr::Reqwest)
+        let url = url.into_url().map_err(ApiErr::Reqwest)?;
+
+        self.retrying(|| async {
+            let mut response =
+                self.send(url.as_str())
+                    .await?
+                    .error_for_status()
+                    .map_err(|err| match err.status() {
+                        Some(status) => ApiErr::Status(status.as_u16()),
+                        None => ApiErr::Reqwest(err),
+                    })?;
+
+            // whatever an earlier attempt wrote is thrown away
+            let mut file = tokio::fs::File::create(path).await.map_err(ApiErr::Io)?;
+            let mut hasher = Sha512::new();
+            while let Some(chunk) = response.chunk().await.map_err(ApiErr::Reqwest)? {
+                hasher.update(&chunk);
+                file.write_all(&chunk).await.map_err(ApiErr::Io)?;
+            }
+            file.flush().await.map_err(ApiErr::Io)?;
+
+            Ok(format!("{:x}", hasher.finalize()))
+        })
+        .await
     }
 
     pub(crate) async fn get_project_versions(
@@ -109,48 +233,55 @@ impl ModrinthClient {
         loaders: &[&str],
         game_versions: &[&str],
     ) -> ApiResult<Vec<Version>> {
-        self.wait_for_window().await;
-        self.v2
-            .list_versions_filtered(id, Some(loaders), Some(game_versions), None)
-            .await
-            .map_err(ApiErr::Ferinth)
+        check_id(id)?;
+        // the filters are json arrays, passed as query parameters, and empty ones are left out
+        // so they match every version
+        let mut url = reqwest::Url::parse(&format!("{}project/{id}/version", self.v2)).unwrap();
+        for (name, filter) in [("loaders", loaders), ("game_versions", game_versions)] {
+            if !filter.is_empty() {
+                url.query_pairs_mut()
+                    .append_pair(name, &serde_json::to_string(filter).unwrap());
+            }
+        }
+
+        self.get_json(url.into()).await
     }
 
     pub(crate) async fn get_version(&self, id: &str) -> ApiResult<Version> {
-        self.wait_for_window().await;
-        self.v2.get_version(id).await.map_err(ApiErr::Ferinth)
+        check_id(id)?;
+        self.get_json(format!("{}version/{id}", self.v2)).await
     }
 
-    pub(crate) async fn get_project(&self, id: &str) -> ApiResult<ProjectKey> {
-        self.wait_for_window().await;
-        let project = self.v2.get_project(id).await.map_err(ApiErr::Ferinth)?;
-
-        let mut global_projects = self.global_projects.write().await;
+    async fn fetch_project(&self, id: &str) -> ApiResult<Project> {
+        check_id(id)?;
+        let project: Project = self.get_json(format!("{}project/{id}", self.v2)).await?;
 
-        global_projects.push(project);
+        self.projects
+            .write()
+            .await
+            .insert(project.id.clone(), project.clone());
 
-        Ok(ProjectKey(global_projects.len() - 1))
+        Ok(project)
     }
 
-    pub(crate) async fn get_collection(&self, id: &str) -> ApiResult<Collection> {
-        self.wait_for_window().await;
-        let response = self
-            .v3
-            .get(format!("{MODRINTH_ENDPOINT}collection/{}", id))
-            .send()
-            .await
-            .map_err(ApiErr::Reqwest)?;
+    /// Fetches a project by id or slug, returning the key it can be looked up with.
+    pub(crate) async fn get_project(&self, id: &str) -> ApiResult<ProjectKey> {
+        Ok(ProjectKey(self.fetch_project(id).await?.id))
+    }
 
-        if !response.status().is_success() {
-            match response.status().as_u16() {
-                404 => return Err(ApiErr::NotFound),
-                other => panic!("api returned error code {other}"),
-            }
+    /// The project with `key`, fetching it if it hasn't been yet. Keys come from the
+    /// browser, so they can be anything, and a slug finds the project too.
+    pub(crate) async fn project(&self, key: &ProjectKey) -> ApiResult<Project> {
+        if let Some(project) = self.projects.read().await.get(&key.0) {
+            return Ok(project.clone());
         }
 
-        let body = response.text().await.map_err(ApiErr::Reqwest)?;
+        self.fetch_project(&key.0).await
+    }
 
-        let pre: InnerCollection = serde_json::from_str(&body).map_err(ApiErr::Json)?;
+    pub(crate) async fn get_collection(&self, id: &str) -> ApiResult<Collection> {
+        check_id(id)?;
+        let pre: InnerCollection = self.get_json(format!("{}collection/{id}", self.v3)).await?;
 
         let mut projects = Vec::with_capacity(pre.projects.len());
 
@@ -169,3 +300,242 @@ impl ModrinthClient {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use axum::{
+        body::Body,
+        http::StatusCode,
+        response::{IntoResponse, Response},
+        routing::get,
+        Router,
+    };
+
+    use futures::StreamExt as _;
+    use sha2::{Digest, Sha512};
+
+    use super::{ApiErr, ModrinthClient, ProjectKey, RetryPolicy};
+    use crate::{
+        app::modrinth::count_retries,
+        test_setup::{fake_api, temp_path},
+    };
+
+    #[tokio::test]
+    async fn collection_projects() {
+        let api = fake_api().await;
+
+        let collection = api.get_collection("fixture1").await.unwrap();
+        assert_eq!(collection.name, "Fixture Pack");
+
+        let projects = api.projects.read().await;
+        let slugs = collection
+            .projects
+            .iter()
+            .map(|key| projects[&key.0].slug.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(slugs, ["sodium", "iris", "lithium", "modmenu"]);
+    }
+
+    #[tokio::test]
+    async fn project_keys() {
+        let api = fake_api().await;
+        let collection = api.get_collection("fixture1").await.unwrap();
+        let key = &collection.projects[0];
+
+        // a client that never saw the key, like after a restart, still finds the project
+        let restarted = fake_api().await;
+        assert_eq!(restarted.project(key).await.unwrap().slug, "sodium");
+        // by slug too
+        let by_slug = ProjectKey("sodium".to_string());
+        assert_eq!(restarted.project(&by_slug).await.unwrap().id, key.0);
+
+        for hostile in [
+            "nothere",
+            "",
+            "..",
+            "../collection/fixture1",
+            "a?b",
+            "a%2Fb",
+            "a\nb",
+        ] {
+            let key = ProjectKey(hostile.to_string());
+            assert!(
+                matches!(restarted.project(&key).await, Err(ApiErr::NotFound)),
+                "{hostile:?} was found"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn filtered_versions() {
+        let api = fake_api().await;
+
+        let versions = api
+            .get_project_versions("sodium", &["fabric"], &["1.20.4"])
+            .await
+            .unwrap();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].id, "sodi1204");
+
+        let versions = api
+            .get_project_versions("iris", &["forge"], &["1.20.1"])
+            .await
+            .unwrap();
+        assert!(versions.is_empty());
+
+        // no filter means every game version
+        let versions = api
+            .get_project_versions("sodium", &["fabric"], &[])
+            .await
+            .unwrap();
+        assert_eq!(versions.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn missing() {
+        let api = fake_api().await;
+
+        assert!(matches!(
+            api.get_collection("nothere").await,
+            Err(ApiErr::NotFound)
+        ));
+        assert!(matches!(
+            api.get_version("nothere").await,
+            Err(ApiErr::NotFound)
+        ));
+    }
+
+    /// A server that answers `/file.jar` with `statuses` in turn, then with a jar.
+    /// A status of 0 starts sending the jar, but is cut off halfway.
+    async fn flaky(statuses: &'static [u16]) -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let app = Router::new().route(
+            "/file.jar",
+            get({
+                let requests = requests.clone();
+                move || async move {
+                    match statuses.get(requests.fetch_add(1, Ordering::SeqCst)) {
+                        Some(0) => {
+                            let chunks: [Result<&str, std::io::Error>; 2] =
+                                [Ok("ja"), Err(std::io::ErrorKind::ConnectionReset.into())];
+                            // gives the first chunk time to be sent before the connection drops
+                            let chunks = futures::stream::iter(chunks).then(|chunk| async {
+                                tokio::time::sleep(Duration::from_millis(10)).await;
+                                chunk
+                            });
+                            Response::builder()
+                                .header("Content-Length", "3")
+                                .body(Body::from_stream(chunks))
+                                .unwrap()
+                        }
+                        // rate limited, with a reset that has already passed
+                        Some(429) => (
+                            StatusCode::TOO_MANY_REQUESTS,
+                            [("X-Ratelimit-Remaining", "0"), ("X-Ratelimit-Reset", "0")],
+                            "slow down",
+                        )
+                            .into_response(),
+                        Some(status) => (
+                            StatusCode::from_u16(*status).unwrap(),
+                            [
+                                ("X-Ratelimit-Remaining", "299"),
+                                ("X-Ratelimit-Reset", "60"),
+                            ],
+                            "oops",
+                        )
+                            .into_response(),
+                        None => (
+                            StatusCode::OK,
+                            [
+                                ("X-Ratelimit-Remaining", "299"),
+                                ("X-Ratelimit-Reset", "60"),
+                            ],
+                            "jar",
+                        )
+                            .into_response(),
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/file.jar", listener.local_addr().unwrap());
+        tokio::task::spawn(async move { axum::serve(listener, app).await });
+
+        (url, requests)
+    }
+
+    fn retrying_client() -> ModrinthClient {
+        ModrinthClient::default().with_retries(RetryPolicy {
+            attempts: 3,
+            base_delay: Duration::from_millis(1),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures() {
+        let (url, requests) = flaky(&[429, 503]).await;
+
+        let path = temp_path("retried.jar");
+
+        let (sha512, retries) = count_retries(retrying_client().download_file(url, &path)).await;
+        assert_eq!(sha512.unwrap(), format!("{:x}", Sha512::digest(b"jar")));
+        assert_eq!(std::fs::read(&path).unwrap(), b"jar");
+        assert_eq!(retries, 2);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn restarts_cut_off_downloads() {
+        let (url, requests) = flaky(&[0, 0]).await;
+        let path = temp_path("cut-off.jar");
+
+        let (sha512, retries) = count_retries(retrying_client().download_file(url, &path)).await;
+        assert_eq!(sha512.unwrap(), format!("{:x}", Sha512::digest(b"jar")));
+        // nothing from the attempts that were cut off is left in the file
+        assert_eq!(std::fs::read(&path).unwrap(), b"jar");
+        assert_eq!(retries, 2);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        // the same attempts are used up by downloads that keep getting cut off
+        let (url, requests) = flaky(&[0, 0, 0]).await;
+        assert!(matches!(
+            retrying_client().download_file(url, &path).await,
+            Err(ApiErr::Reqwest(_))
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn gives_up() {
+        let path = temp_path("given-up.jar");
+
+        // out of attempts
+        let (url, requests) = flaky(&[500, 502, 503]).await;
+        assert!(matches!(
+            retrying_client().download_file(url, &path).await,
+            Err(ApiErr::Status(503))
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        // not worth retrying
+        let (url, requests) = flaky(&[403]).await;
+        assert!(matches!(
+            retrying_client().download_file(url, &path).await,
+            Err(ApiErr::Status(403))
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,9 +1,18 @@
 #[cfg(feature = "ssr")]
 mod api;
+#[cfg(feature = "ssr")]
+mod rate_limit;
+#[cfg(feature = "ssr")]
+mod retry;
 
 #[cfg(feature = "ssr")]
 pub use api::*;
+#[cfg(feature = "ssr")]
+pub(crate) use retry::count_retries;
 
+use std::{fmt::Display, str::FromStr};
+
+use ferinth::structures::version::VersionType;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -18,11 +27,19 @@ impl AsRef<str> for ProjectID {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(transparent)]
-pub struct UserID(String);
+pub struct UserID(pub String);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// A project's modrinth id, which the browser sends back to refer to it. Ids never change,
+/// so keys stay valid across restarts, but they're checked like any other client input.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 #[serde(transparent)]
-pub struct ProjectKey(pub usize);
+pub struct ProjectKey(pub String);
+
+impl Display for ProjectKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Collection {
@@ -32,3 +49,59 @@ pub struct Collection {
     pub description: String,
     pub projects: Vec<ProjectKey>,
 }
+
+/// Which release channels a version may come from when picking one to download.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelPolicy {
+    /// Only full releases
+    #[default]
+    Release,
+    /// Releases and betas
+    Beta,
+    /// Anything, including alphas
+    Alpha,
+}
+
+impl ChannelPolicy {
+    pub const ALL: [ChannelPolicy; 3] = [
+        ChannelPolicy::Release,
+        ChannelPolicy::Beta,
+        ChannelPolicy::Alpha,
+    ];
+
+    pub fn allows(&self, version_type: &VersionType) -> bool {
+        match self {
+            ChannelPolicy::Release => *version_type == VersionType::Release,
+            ChannelPolicy::Beta => *version_type != VersionType::Alpha,
+            ChannelPolicy::Alpha => true,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChannelPolicy::Release => "release",
+            ChannelPolicy::Beta => "beta",
+            ChannelPolicy::Alpha => "alpha",
+        }
+    }
+}
+
+impl FromStr for ChannelPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "release" => Ok(ChannelPolicy::Release),
+            "beta" => Ok(ChannelPolicy::Beta),
+            "alpha" => Ok(ChannelPolicy::Alpha),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for ChannelPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -0,0 +1,209 @@
+//! A token bucket that keeps requests under modrinth's rate limit.
+//!
+//! The bucket starts full, so bursts go out straight away, and refills at the configured
+//! rate. Every response tells us how much of the server's budget is left
+//! (`X-Ratelimit-Remaining`) and when it resets (`X-Ratelimit-Reset`, in seconds), and the
+//! bucket never holds more than the server says we have. Once that reaches zero, or the
+//! server answers with a 429, nothing is sent until the reset.
+
+use std::time::Duration;
+
+use reqwest::{header::HeaderMap, StatusCode};
+use tokio::{sync::Mutex, time::Instant};
+
+/// How long to back off after a 429 that didn't say when the limit resets.
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+struct Bucket {
+    /// Can go negative, which means requests are queued up waiting for a token
+    tokens: f64,
+    /// When tokens were last added. Set in the future to pause until then.
+    refilled: Instant,
+}
+
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    bucket: Mutex<Bucket>,
+    capacity: f64,
+    /// Tokens added per second
+    rate: f64,
+}
+
+fn header<T: std::str::FromStr>(headers: &HeaderMap, name: &str) -> Option<T> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+impl RateLimiter {
+    /// Allows bursts of up to `requests_per_minute`, refilling evenly over the minute.
+    pub(crate) fn per_minute(requests_per_minute: u32) -> Self {
+        let capacity = requests_per_minute.max(1) as f64;
+
+        Self {
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                refilled: Instant::now(),
+            }),
+            capacity,
+            rate: capacity / 60.0,
+        }
+    }
+
+    fn refill(&self, bucket: &mut Bucket, now: Instant) {
+        if now <= bucket.refilled {
+            return;
+        }
+
+        let elapsed = (now - bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.capacity);
+        bucket.refilled = now;
+    }
+
+    /// Takes a token, waiting until one is available.
+    ///
+    /// The lock is only held to reserve a token, so callers wait side by side
+    /// and are let through in the order they arrived.
+    pub(crate) async fn acquire(&self) {
+        let wait = {
+            let mut bucket = self.bucket.lock().await;
+            let now = Instant::now();
+            self.refill(&mut bucket, now);
+
+            bucket.tokens -= 1.0;
+
+            let paused = bucket.refilled.saturating_duration_since(now);
+            let queued = Duration::from_secs_f64((-bucket.tokens).max(0.0) / self.rate);
+            paused + queued
+        };
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+
+        // the server may have asked us to back off while we were waiting
+        loop {
+            let paused_until = self.bucket.lock().await.refilled;
+            if paused_until <= Instant::now() {
+                break;
+            }
+            tokio::time::sleep_until(paused_until).await;
+        }
+    }
+
+    /// Stops sending requests for `duration`, and drops whatever tokens were left.
+    async fn pause(&self, duration: Duration) {
+        let mut bucket = self.bucket.lock().await;
+        let now = Instant::now();
+        self.refill(&mut bucket, now);
+
+        // one request can go as soon as the limit resets
+        bucket.tokens = bucket.tokens.min(0.0) + 1.0;
+        bucket.refilled = bucket.refilled.max(now + duration);
+    }
+
+    /// Adjusts to the rate limit headers of a response.
+    pub(crate) async fn observe(&self, status: StatusCode, headers: &HeaderMap) {
+        let remaining: Option<u32> = header(headers, "x-ratelimit-remaining");
+        let reset = header(headers, "x-ratelimit-reset").map(Duration::from_secs);
+
+        if status == StatusCode::TOO_MANY_REQUESTS || remaining == Some(0) {
+            self.pause(reset.unwrap_or(DEFAULT_BACKOFF)).await;
+        } else if let Some(remaining) = remaining {
+            let mut bucket = self.bucket.lock().await;
+            bucket.tokens = bucket.tokens.min(remaining as f64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::{header::HeaderMap, StatusCode};
+    use tokio::time::Instant;
+
+    use super::RateLimiter;
+
+    fn headers(remaining: u32, reset: u64) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Ratelimit-Remaining", remaining.into());
+        headers.insert("X-Ratelimit-Reset", reset.into());
+        headers
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bursts_then_refills() {
+        let limiter = RateLimiter::per_minute(60);
+        let start = Instant::now();
+
+        for _ in 0..60 {
+            limiter.acquire().await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // the bucket is empty, so the next ones go out at a second each
+        limiter.acquire().await;
+        limiter.acquire().await;
+        assert_eq!(start.elapsed().as_secs(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_callers_share_the_budget() {
+        let limiter = std::sync::Arc::new(RateLimiter::per_minute(60));
+        for _ in 0..58 {
+            limiter.acquire().await;
+        }
+        let start = Instant::now();
+
+        // two tokens left, the third and fourth caller wait a second each
+        let waits = futures::future::join_all((0..4).map(|_| {
+            let limiter = limiter.clone();
+            async move {
+                limiter.acquire().await;
+                start.elapsed().as_secs()
+            }
+        }))
+        .await;
+
+        assert_eq!(waits, [0, 0, 1, 2]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn follows_headers() {
+        let limiter = RateLimiter::per_minute(60);
+        let start = Instant::now();
+
+        // the server has less budget left than we thought
+        limiter.observe(StatusCode::OK, &headers(1, 30)).await;
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        limiter.acquire().await;
+        assert_eq!(start.elapsed().as_secs(), 1);
+
+        // and none at all, so wait for the reset
+        limiter.observe(StatusCode::OK, &headers(0, 30)).await;
+        limiter.acquire().await;
+        assert_eq!(start.elapsed().as_secs(), 31);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backs_off_on_429() {
+        let limiter = RateLimiter::per_minute(60);
+        let start = Instant::now();
+
+        limiter
+            .observe(StatusCode::TOO_MANY_REQUESTS, &headers(0, 10))
+            .await;
+        limiter.acquire().await;
+        assert_eq!(start.elapsed().as_secs(), 10);
+
+        // without a reset header there's a default backoff
+        let limiter = RateLimiter::per_minute(60);
+        let start = Instant::now();
+        limiter
+            .observe(StatusCode::TOO_MANY_REQUESTS, &HeaderMap::new())
+            .await;
+        limiter.acquire().await;
+        assert_eq!(start.elapsed().as_secs(), 5);
+    }
+}
@@ -0,0 +1,124 @@
+//! Trying requests again when they fail for reasons that are likely to go away.
+//!
+//! Every request the client makes is a GET, so sending one twice is always safe. Only
+//! failures that say nothing about the request itself are retried: connection problems,
+//! timeouts, bodies cut off halfway, 5xx and 429 responses. A 404 will still be a 404.
+
+use std::{cell::Cell, future::Future, time::Duration};
+
+use super::ApiErr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct RetryPolicy {
+    /// How many times a request is sent before giving up, including the first
+    pub attempts: u32,
+    /// The wait before the first retry, which doubles for every retry after it
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 4,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before sending the request for the `retry`th time (starting at 1).
+    ///
+    /// Somewhere between half and all of the exponential delay, so clients that failed
+    /// together don't all come back at once.
+    pub(crate) fn delay(&self, retry: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_delay);
+
+        exponential.mul_f64(0.5 + fastrand::f64() * 0.5)
+    }
+}
+
+impl ApiErr {
+    pub(crate) fn is_transient(&self) -> bool {
+        match self {
+            // a body that breaks off halfway is a decode error
+            ApiErr::Reqwest(err) => {
+                err.is_connect() || err.is_timeout() || err.is_body() || err.is_decode()
+            }
+            ApiErr::Status(status) => *status == 429 || (500..600).contains(status),
+            ApiErr::Json(_) | ApiErr::NotFound | ApiErr::ImportExpired | ApiErr::Io(_) => false,
+        }
+    }
+}
+
+tokio::task_local! {
+    static RETRIES: Cell<u32>;
+}
+
+/// Runs `future`, also returning how many requests it had to retry.
+pub(crate) async fn count_retries<F: Future>(future: F) -> (F::Output, u32) {
+    RETRIES
+        .scope(Cell::new(0), async move {
+            let output = future.await;
+            (output, RETRIES.with(Cell::get))
+        })
+        .await
+}
+
+/// Counts a retry towards the surrounding `count_retries`, if there is one.
+pub(super) fn record_retry() {
+    let _ = RETRIES.try_with(|retries| retries.set(retries.get() + 1));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{count_retries, record_retry, RetryPolicy};
+    use crate::app::modrinth::ApiErr;
+
+    #[test]
+    fn delays_grow_with_jitter() {
+        let policy = RetryPolicy {
+            attempts: 10,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+        };
+
+        for _ in 0..100 {
+            let first = policy.delay(1);
+            assert!(first >= Duration::from_millis(500) && first <= Duration::from_secs(1));
+
+            let third = policy.delay(3);
+            assert!(third >= Duration::from_secs(2) && third <= Duration::from_secs(4));
+
+            // capped
+            assert!(policy.delay(9) <= Duration::from_secs(5));
+        }
+    }
+
+    #[test]
+    fn transient() {
+        assert!(ApiErr::Status(503).is_transient());
+        assert!(ApiErr::Status(429).is_transient());
+        assert!(!ApiErr::Status(403).is_transient());
+        assert!(!ApiErr::NotFound.is_transient());
+    }
+
+    #[tokio::test]
+    async fn counts_per_scope() {
+        let ((), retries) = count_retries(async {
+            record_retry();
+            record_retry();
+        })
+        .await;
+        assert_eq!(retries, 2);
+
+        // outside of a scope it's ignored
+        record_retry();
+    }
+}
@@ -0,0 +1,598 @@
+//! Reading [packwiz](https://packwiz.infra.link/) packs back into a mod list.
+//!
+//! A pack is uploaded as a zip of its `pack.toml`/`index.toml` tree. Only the
+//! `[update.modrinth]` section of each metafile is used, everything else
+//! (download urls, hashes, overrides) is ignored.
+
+use std::{collections::HashMap, io, path::PathBuf};
+
+use futures::AsyncReadExt;
+use itertools::Itertools;
+use leptos::logging;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+
+use super::modrinth::{ApiErr, Collection, ModrinthClient, UserID};
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum PackwizErr {
+    #[error("zip error: {0}")]
+    Zip(async_zip::error::ZipError),
+    #[error("io error: {0}")]
+    Io(std::io::Error),
+    #[error("couldn't parse {0}: {1}")]
+    Toml(String, toml::de::Error),
+    #[error("no pack.toml found in the archive")]
+    MissingPack,
+    #[error("{0} is referenced by the pack but missing from the archive")]
+    MissingFile(String),
+    #[error("{0} is bigger than {} KiB", MAX_TOML_SIZE / 1024)]
+    FileTooBig(String),
+    #[error("the pack's toml files add up to more than {} MiB", MAX_TOML_TOTAL / (1024 * 1024))]
+    PackTooBig,
+    #[error("the archive has more than {MAX_ENTRIES} files")]
+    TooManyFiles,
+    #[error("{0}")]
+    Api(ApiErr),
+}
+
+pub(crate) type PackwizResult<T> = Result<T, PackwizErr>;
+
+/// The most a single toml file of a pack can be once unzipped.
+const MAX_TOML_SIZE: u64 = 1024 * 1024;
+/// The most all toml files of a pack can be together once unzipped.
+const MAX_TOML_TOTAL: u64 = 64 * 1024 * 1024;
+/// The most files an uploaded archive can have, toml or not.
+const MAX_ENTRIES: usize = 64 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct PackToml {
+    name: String,
+    author: Option<String>,
+    index: IndexRef,
+    #[serde(default)]
+    versions: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IndexRef {
+    file: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IndexToml {
+    #[serde(default)]
+    files: Vec<IndexFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IndexFile {
+    file: String,
+    #[serde(default)]
+    metafile: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct MetaToml {
+    name: String,
+    update: Option<UpdateSection>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateSection {
+    modrinth: Option<ModrinthUpdate>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ModrinthUpdate {
+    mod_id: String,
+    version: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PackwizPack {
+    pub name: String,
+    pub author: Option<String>,
+    pub minecraft: Option<String>,
+    pub mods: Vec<PackwizMod>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PackwizMod {
+    pub name: String,
+    /// `None` if the metafile has no `[update.modrinth]` section
+    pub modrinth: Option<(String, String)>,
+}
+
+/// Joins a path relative to `base_dir`, resolving `.` and `..` the way packwiz does.
+/// Paths inside the archive always use `/`.
+fn join(base_dir: &str, rel: &str) -> String {
+    let mut parts: Vec<&str> = base_dir.split('/').filter(|p| !p.is_empty()).collect();
+
+    for part in rel.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+
+    parts.join("/")
+}
+
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("")
+}
+
+fn parse_toml<T: serde::de::DeserializeOwned>(path: &str, contents: &str) -> PackwizResult<T> {
+    toml::from_str(contents).map_err(|err| PackwizErr::Toml(path.to_string(), err))
+}
+
+/// Parses a pack from the toml files it contains, keyed by their path inside the archive.
+pub(crate) fn parse_pack(files: &HashMap<String, String>) -> PackwizResult<PackwizPack> {
+    // the archive might have been made from a parent folder (like a github download),
+    // so use the shallowest pack.toml as the root
+    let pack_path = files
+        .keys()
+        .filter(|path| *path == "pack.toml" || path.ends_with("/pack.toml"))
+        .min_by_key(|path| path.matches('/').count())
+        .ok_or(PackwizErr::MissingPack)?;
+
+    let pack: PackToml = parse_toml(pack_path, &files[pack_path])?;
+
+    let index_path = join(parent(pack_path), &pack.index.file);
+    let index: IndexToml = parse_toml(
+        &index_path,
+        files
+            .get(&index_path)
+            .ok_or_else(|| PackwizErr::MissingFile(index_path.clone()))?,
+    )?;
+
+    let mut mods = Vec::new();
+
+    for file in index.files {
+        if !file.metafile && !file.file.ends_with(".pw.toml") {
+            continue;
+        }
+
+        let meta_path = join(parent(&index_path), &file.file);
+        let meta: MetaToml = parse_toml(
+            &meta_path,
+            files
+                .get(&meta_path)
+                .ok_or_else(|| PackwizErr::MissingFile(meta_path.clone()))?,
+        )?;
+
+        mods.push(PackwizMod {
+            name: meta.name,
+            modrinth: meta
+                .update
+                .and_then(|update| update.modrinth)
+                .map(|modrinth| (modrinth.mod_id, modrinth.version)),
+        });
+    }
+
+    Ok(PackwizPack {
+        name: pack.name,
+        author: pack.author,
+        minecraft: pack.versions.get("minecraft").cloned(),
+        mods,
+    })
+}
+
+/// Reads every toml file out of a zipped pack.
+///
+/// The sizes in the archive can't be trusted, so files are cut off while reading as well,
+/// keeping a small upload from unzipping into gigabytes.
+async fn read_toml_files(archive: Vec<u8>) -> PackwizResult<HashMap<String, String>> {
+    use async_zip::base::read::mem::ZipFileReader;
+
+    let zip = ZipFileReader::new(archive).await.map_err(PackwizErr::Zip)?;
+    if zip.file().entries().len() > MAX_ENTRIES {
+        return Err(PackwizErr::TooManyFiles);
+    }
+
+    let mut files = HashMap::new();
+    let mut total = 0;
+
+    for (idx, entry) in zip.file().entries().iter().enumerate() {
+        let Ok(path) = entry.filename().as_str() else {
+            continue;
+        };
+        if !path.ends_with(".toml") {
+            continue;
+        }
+        let path = join("", path);
+
+        if entry.uncompressed_size() > MAX_TOML_SIZE {
+            return Err(PackwizErr::FileTooBig(path));
+        }
+
+        let reader = zip.reader_with_entry(idx).await.map_err(PackwizErr::Zip)?;
+        let mut contents = String::new();
+        reader
+            .take(MAX_TOML_SIZE + 1)
+            .read_to_string(&mut contents)
+            .await
+            .map_err(PackwizErr::Io)?;
+
+        if contents.len() as u64 > MAX_TOML_SIZE {
+            return Err(PackwizErr::FileTooBig(path));
+        }
+        total += contents.len() as u64;
+        if total > MAX_TOML_TOTAL {
+            return Err(PackwizErr::PackTooBig);
+        }
+
+        files.insert(path, contents);
+    }
+
+    Ok(files)
+}
+
+pub(crate) async fn read_pack(archive: Vec<u8>) -> PackwizResult<PackwizPack> {
+    parse_pack(&read_toml_files(archive).await?)
+}
+
+/// Packs that have been uploaded, stored as collections so they can be shown in the same matrix.
+#[derive(Debug, Default)]
+pub struct PackwizImports {
+    /// Where imports are saved, they're only kept in memory without it
+    path: Option<PathBuf>,
+    collections: RwLock<HashMap<String, Collection>>,
+}
+
+impl PackwizImports {
+    const ID_PREFIX: &'static str = "packwiz-";
+
+    /// Loads the imports from `path`, starting empty if the file doesn't exist yet.
+    pub async fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+
+        let collections = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            path: Some(path),
+            collections: RwLock::new(collections),
+        })
+    }
+
+    /// Writes every import back to disk, in one go so a crash can't leave the file half written.
+    async fn persist(&self, collections: &HashMap<String, Collection>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let result = async {
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+
+            let contents = serde_json::to_vec(collections)?;
+            let tmp = path.with_extension("tmp");
+            tokio::fs::write(&tmp, contents).await?;
+            tokio::fs::rename(&tmp, path).await
+        };
+        if let Err(err) = result.await {
+            logging::error!("couldn't save packwiz imports to {}: {err}", path.display());
+        }
+    }
+
+    /// Whether `id` is one handed out by [`PackwizImports::import`], rather than a modrinth id.
+    pub(crate) fn is_import(id: &str) -> bool {
+        id.starts_with(Self::ID_PREFIX)
+    }
+
+    pub(crate) async fn get(&self, id: &str) -> Option<Collection> {
+        self.collections.read().await.get(id).cloned()
+    }
+
+    /// Hashes what the pack is made of, so importing it again gives the same id, also after
+    /// a restart or an upgrade. Mods are sorted so their order in the index doesn't matter.
+    fn content_hash(pack: &PackwizPack) -> String {
+        let mods = pack
+            .mods
+            .iter()
+            .map(|pack_mod| (&pack_mod.name, &pack_mod.modrinth))
+            .sorted()
+            .collect_vec();
+        let contents = serde_json::to_vec(&(&pack.name, mods)).unwrap();
+        format!("{:x}", Sha256::digest(contents))
+    }
+
+    /// Looks up every mod of the pack on modrinth and stores the result as a collection,
+    /// returning the id of that collection.
+    pub(crate) async fn import(
+        &self,
+        api: &ModrinthClient,
+        pack: PackwizPack,
+    ) -> PackwizResult<String> {
+        let id = format!("{}{}", Self::ID_PREFIX, &Self::content_hash(&pack)[..16]);
+
+        if self.collections.read().await.contains_key(&id) {
+            return Ok(id);
+        }
+
+        let mut projects = Vec::with_capacity(pack.mods.len());
+        let mut skipped = Vec::new();
+        // removed from modrinth since the pack was made, which shouldn't stop the rest
+        let mut unresolved = Vec::new();
+
+        for pack_mod in &pack.mods {
+            match &pack_mod.modrinth {
+                Some((project_id, _)) => match api.get_project(project_id).await {
+                    Ok(project) => projects.push(project),
+                    Err(ApiErr::NotFound) => unresolved.push(pack_mod.name.as_str()),
+                    Err(err) => return Err(PackwizErr::Api(err)),
+                },
+                None => skipped.push(pack_mod.name.as_str()),
+            }
+        }
+
+        let mut description = match &pack.minecraft {
+            Some(minecraft) => {
+                format!("Imported from packwiz, currently on Minecraft {minecraft}.")
+            }
+            None => "Imported from packwiz.".to_string(),
+        };
+        if !skipped.is_empty() {
+            description.push_str(&format!(
+                " Skipped {} mods without a Modrinth source: {}",
+                skipped.len(),
+                skipped.join(", ")
+            ));
+        }
+        if !unresolved.is_empty() {
+            description.push_str(&format!(
+                " Couldn't find {} mods on Modrinth: {}",
+                unresolved.len(),
+                unresolved.join(", ")
+            ));
+        }
+
+        let collection = Collection {
+            id: id.clone(),
+            user: UserID(pack.author.unwrap_or_default()),
+            name: pack.name,
+            description,
+            projects,
+        };
+
+        let mut collections = self.collections.write().await;
+        collections.insert(id.clone(), collection);
+        self.persist(&collections).await;
+
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{
+        join, parse_pack, read_pack, PackwizErr, PackwizImports, PackwizMod, PackwizPack,
+        MAX_TOML_SIZE,
+    };
+    use crate::{
+        app::coverage::resolve_collection,
+        test_setup::{fake_api, temp_path},
+    };
+
+    fn files(files: &[(&str, &str)]) -> HashMap<String, String> {
+        files
+            .iter()
+            .map(|(path, contents)| (path.to_string(), contents.to_string()))
+            .collect()
+    }
+
+    const PACK: &str = r#"
+name = "Test Pack"
+author = "goose"
+pack-format = "packwiz:1.1.0"
+
+[index]
+file = "index.toml"
+hash-format = "sha256"
+hash = "abc"
+
+[versions]
+fabric = "0.15.11"
+minecraft = "1.20.1"
+"#;
+
+    const INDEX: &str = r#"
+hash-format = "sha256"
+
+[[files]]
+file = "mods/sodium.pw.toml"
+hash = "abc"
+metafile = true
+
+[[files]]
+file = "config/sodium-options.json"
+hash = "abc"
+
+[[files]]
+file = "mods/local.pw.toml"
+hash = "abc"
+metafile = true
+"#;
+
+    const SODIUM: &str = r#"
+name = "Sodium"
+filename = "sodium-fabric-0.5.8+mc1.20.1.jar"
+side = "client"
+
+[download]
+url = "https://cdn.modrinth.com/data/AANobbMI/versions/OihdIimA/sodium-fabric-0.5.8%2Bmc1.20.1.jar"
+hash-format = "sha1"
+hash = "abc"
+
+[update]
+[update.modrinth]
+mod-id = "AANobbMI"
+version = "OihdIimA"
+"#;
+
+    const LOCAL: &str = r#"
+name = "Some Curseforge Mod"
+filename = "local.jar"
+side = "both"
+
+[download]
+url = "https://example.com/local.jar"
+hash-format = "sha1"
+hash = "abc"
+"#;
+
+    #[test]
+    fn join_relative() {
+        assert_eq!(join("pack", "mods/../index.toml"), "pack/index.toml");
+        assert_eq!(join("", "./pack.toml"), "pack.toml");
+        assert_eq!(join("a/b", "../../../c"), "c");
+    }
+
+    #[test]
+    fn parse_simple() {
+        let pack = parse_pack(&files(&[
+            ("pack.toml", PACK),
+            ("index.toml", INDEX),
+            ("mods/sodium.pw.toml", SODIUM),
+            ("mods/local.pw.toml", LOCAL),
+        ]))
+        .unwrap();
+
+        assert_eq!(pack.name, "Test Pack");
+        assert_eq!(pack.author.as_deref(), Some("goose"));
+        assert_eq!(pack.minecraft.as_deref(), Some("1.20.1"));
+        assert_eq!(
+            pack.mods,
+            vec![
+                PackwizMod {
+                    name: "Sodium".to_string(),
+                    modrinth: Some(("AANobbMI".to_string(), "OihdIimA".to_string())),
+                },
+                PackwizMod {
+                    name: "Some Curseforge Mod".to_string(),
+                    modrinth: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_nested_root() {
+        let pack = parse_pack(&files(&[
+            ("my-pack-main/pack.toml", PACK),
+            ("my-pack-main/index.toml", INDEX),
+            ("my-pack-main/mods/sodium.pw.toml", SODIUM),
+            ("my-pack-main/mods/local.pw.toml", LOCAL),
+        ]))
+        .unwrap();
+
+        assert_eq!(pack.mods.len(), 2);
+    }
+
+    #[test]
+    fn parse_missing_metafile() {
+        assert!(parse_pack(&files(&[
+            ("pack.toml", PACK),
+            ("index.toml", INDEX),
+            ("mods/sodium.pw.toml", SODIUM),
+        ]))
+        .is_err());
+    }
+
+    async fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        use async_zip::{base::write::ZipFileWriter, Compression, ZipEntryBuilder};
+
+        let mut zip = ZipFileWriter::new(Vec::new());
+        for (path, contents) in files {
+            let builder = ZipEntryBuilder::new(path.to_string().into(), Compression::Deflate);
+            zip.write_entry_whole(builder, contents).await.unwrap();
+        }
+        zip.close().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn read_rejects_big_files() {
+        let archive = zip(&[
+            ("pack.toml", PACK.as_bytes()),
+            ("index.toml", INDEX.as_bytes()),
+            ("mods/sodium.pw.toml", SODIUM.as_bytes()),
+            ("mods/local.pw.toml", LOCAL.as_bytes()),
+        ])
+        .await;
+        assert_eq!(read_pack(archive).await.unwrap().mods.len(), 2);
+
+        let bomb = vec![b' '; 2 * MAX_TOML_SIZE as usize];
+        let archive = zip(&[("pack.toml", PACK.as_bytes()), ("bomb.toml", &bomb)]).await;
+        assert!(archive.len() < 64 * 1024);
+        assert!(matches!(
+            read_pack(archive).await,
+            Err(PackwizErr::FileTooBig(path)) if path == "bomb.toml"
+        ));
+    }
+
+    #[tokio::test]
+    async fn import_survives_restart() {
+        let api = fake_api().await;
+
+        let path = temp_path("imports/imports.json");
+        let _ = std::fs::remove_file(&path);
+
+        let pack = PackwizPack {
+            name: "Test Pack".to_string(),
+            author: None,
+            minecraft: Some("1.20.1".to_string()),
+            mods: vec![
+                PackwizMod {
+                    name: "Sodium".to_string(),
+                    modrinth: Some(("AANobbMI".to_string(), "OihdIimA".to_string())),
+                },
+                PackwizMod {
+                    name: "Deleted Mod".to_string(),
+                    modrinth: Some(("deleted0".to_string(), "deleted0".to_string())),
+                },
+            ],
+        };
+
+        let imports = PackwizImports::open(&path).await.unwrap();
+        let id = imports.import(&api, pack).await.unwrap();
+        // the same contents always get the same id
+        assert_eq!(id, "packwiz-de27542d90150ab5");
+
+        let collection = imports.get(&id).await.unwrap();
+        assert_eq!(collection.projects.len(), 1);
+        assert!(collection
+            .description
+            .ends_with("Couldn't find 1 mods on Modrinth: Deleted Mod"));
+
+        let restarted = PackwizImports::open(&path).await.unwrap();
+        assert_eq!(
+            resolve_collection(&api, &restarted, &id)
+                .await
+                .unwrap()
+                .projects
+                .len(),
+            1
+        );
+
+        let unsaved = PackwizImports::default();
+        let err = resolve_collection(&api, &unsaved, &id).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "this packwiz import has expired, upload the pack again"
+        );
+    }
+}
@@ -0,0 +1,719 @@
+//! Checking a pack that was already exported for updates.
+//!
+//! Every archive has a manifest of what was shipped, see `PackManifest`. Uploaded again, the
+//! pack is resolved the same way it was exported, for the same game version, loader and
+//! channel policy, and whatever resolves to another version is listed with the changelogs
+//! in between. Building the updated pack is just the same export run again.
+//!
+//! Two exports can be compared the same way, like for the release notes of a pack that was
+//! bumped. Either comparison can be downloaded as one Markdown changelog, grouped by mod.
+
+use leptos::*;
+use serde::{Deserialize, Serialize};
+use server_fn::codec::{MultipartData, MultipartFormData};
+use wasm_bindgen::JsCast;
+use web_sys::{FormData, HtmlFormElement};
+
+use super::{
+    export::{ExclusionReason, ManifestMod, PackManifest},
+    DownloadButton, SemanticVersion,
+};
+
+/// The changelog of a version released since the one that was shipped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Changelog {
+    pub version_id: String,
+    pub version_number: String,
+    /// `YYYY-MM-DD`
+    pub published: String,
+    pub changelog: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModUpdate {
+    pub project_id: String,
+    pub title: String,
+    /// The version number that was shipped
+    pub from: String,
+    /// The version number the updated pack would have
+    pub to: String,
+    /// Every version after the shipped one up to the new one, newest first
+    pub changelogs: Vec<Changelog>,
+}
+
+/// A shipped mod that the updated pack would leave out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemovedMod {
+    pub project_id: String,
+    pub title: String,
+    /// Why it's left out, `None` when it was a dependency nothing requires anymore
+    pub reason: Option<ExclusionReason>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateReport {
+    /// The pack that was shipped
+    pub from: PackManifest,
+    /// What it's compared with, another export or what the pack resolves to now
+    pub to: PackManifest,
+    /// Whether `to` is what the pack resolves to now, which can be exported again
+    pub latest: bool,
+    pub updates: Vec<ModUpdate>,
+    /// Mods the updated pack would have that weren't shipped, like new dependencies
+    pub added: Vec<ManifestMod>,
+    pub removed: Vec<RemovedMod>,
+    /// How many mods are on the same version in both packs
+    pub unchanged: usize,
+}
+
+impl UpdateReport {
+    pub fn is_empty(&self) -> bool {
+        self.updates.is_empty() && self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Release notes going from one pack to the other, with the changelogs of every mod that
+/// changed, grouped by mod.
+pub fn changelog_markdown(report: &UpdateReport) -> String {
+    use std::fmt::Write as _;
+
+    let UpdateReport { from, to, .. } = report;
+    let mut markdown = format!("# {}\n\n", to.collection_name);
+    if from.game_version == to.game_version {
+        let _ = writeln!(
+            markdown,
+            "Minecraft {}, {}",
+            to.game_version, to.settings.loader
+        );
+    } else {
+        let _ = writeln!(
+            markdown,
+            "Minecraft {} → {}, {}",
+            from.game_version, to.game_version, to.settings.loader
+        );
+    }
+
+    for update in &report.updates {
+        let _ = write!(
+            markdown,
+            "\n## {} {} → {}\n",
+            update.title, update.from, update.to
+        );
+        for changelog in &update.changelogs {
+            let _ = write!(
+                markdown,
+                "\n### {} ({})\n\n{}\n",
+                changelog.version_number,
+                changelog.published,
+                changelog.changelog.trim()
+            );
+        }
+    }
+
+    if !report.added.is_empty() {
+        markdown.push_str("\n## Added\n\n");
+        for added in &report.added {
+            let _ = writeln!(markdown, "- {} {}", added.title, added.version_number);
+        }
+    }
+
+    if !report.removed.is_empty() {
+        markdown.push_str("\n## Removed\n\n");
+        for removed in &report.removed {
+            match &removed.reason {
+                Some(reason) => {
+                    let _ = writeln!(markdown, "- {}: {reason}", removed.title);
+                }
+                None => {
+                    let _ = writeln!(markdown, "- {}", removed.title);
+                }
+            }
+        }
+    }
+
+    markdown
+}
+
+/// A `data:` url of some markdown, so it can be downloaded without another request.
+fn markdown_url(markdown: &str) -> String {
+    let mut url = "data:text/markdown;charset=utf-8,".to_string();
+    for byte in markdown.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            url.push(byte as char);
+        } else {
+            url.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    url
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum UpdateErr {
+    #[error("zip error: {0}")]
+    Zip(async_zip::error::ZipError),
+    #[error("couldn't read the manifest: {0}")]
+    Json(serde_json::Error),
+    #[error(
+        "no {} found in the archive, was it exported by mr-modpack?",
+        PackManifest::FILENAME
+    )]
+    MissingManifest,
+    #[error("{} is bigger than {} KiB", PackManifest::FILENAME, MAX_MANIFEST_SIZE / 1024)]
+    ManifestTooBig,
+    #[error("{0} isn't a game version")]
+    GameVersion(String),
+    #[error("{0}")]
+    Export(super::export::ExportErr),
+    #[error("{0}")]
+    Api(super::modrinth::ApiErr),
+}
+
+#[cfg(feature = "ssr")]
+pub(crate) type UpdateResult<T> = Result<T, UpdateErr>;
+
+/// The most the manifest of an uploaded archive can be once unzipped.
+#[cfg(feature = "ssr")]
+const MAX_MANIFEST_SIZE: u64 = 1024 * 1024;
+
+/// Takes the manifest out of an exported archive, or reads it as is if that's what was uploaded.
+#[cfg(feature = "ssr")]
+pub(crate) async fn read_manifest(upload: Vec<u8>) -> UpdateResult<PackManifest> {
+    use async_zip::base::read::mem::ZipFileReader;
+    use futures::AsyncReadExt as _;
+
+    if upload.trim_ascii_start().starts_with(b"{") {
+        return serde_json::from_slice(&upload).map_err(UpdateErr::Json);
+    }
+
+    let zip = ZipFileReader::new(upload).await.map_err(UpdateErr::Zip)?;
+    let (idx, entry) = zip
+        .file()
+        .entries()
+        .iter()
+        .enumerate()
+        .find(|(_, entry)| {
+            entry
+                .filename()
+                .as_str()
+                .is_ok_and(|name| name == PackManifest::FILENAME)
+        })
+        .ok_or(UpdateErr::MissingManifest)?;
+    if entry.uncompressed_size() > MAX_MANIFEST_SIZE {
+        return Err(UpdateErr::ManifestTooBig);
+    }
+
+    // the size in the archive can't be trusted, so it's cut off while reading too
+    let mut contents = String::new();
+    let reader = zip.reader_with_entry(idx).await.map_err(UpdateErr::Zip)?;
+    reader
+        .take(MAX_MANIFEST_SIZE + 1)
+        .read_to_string(&mut contents)
+        .await
+        .map_err(|err| UpdateErr::Zip(err.into()))?;
+    if contents.len() as u64 > MAX_MANIFEST_SIZE {
+        return Err(UpdateErr::ManifestTooBig);
+    }
+
+    serde_json::from_str(&contents).map_err(UpdateErr::Json)
+}
+
+/// When the version with `id` was published, from `versions` if it's there.
+#[cfg(feature = "ssr")]
+async fn published(
+    api: &super::modrinth::ModrinthClient,
+    versions: &[ferinth::structures::version::Version],
+    id: &str,
+) -> super::modrinth::ApiResult<Option<ferinth::structures::UtcTime>> {
+    use super::modrinth::ApiErr;
+
+    if let Some(version) = versions.iter().find(|v| v.id == id) {
+        return Ok(Some(version.date_published));
+    }
+
+    // like when the packs are for different game versions
+    match api.get_version(id).await {
+        Ok(version) => Ok(Some(version.date_published)),
+        Err(ApiErr::NotFound) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// The changelogs of the versions released after `shipped` up to `current`, for the game
+/// version, loader and channel policy of `to`, newest first.
+#[cfg(feature = "ssr")]
+async fn changelogs(
+    api: &super::modrinth::ModrinthClient,
+    to: &PackManifest,
+    shipped: &ManifestMod,
+    current: &ManifestMod,
+) -> super::modrinth::ApiResult<Vec<Changelog>> {
+    let mut versions = api
+        .get_project_versions(
+            &current.project_id,
+            &[to.settings.loader.as_str()],
+            &[to.game_version.as_str()],
+        )
+        .await?;
+    versions.sort_by(|a, b| b.date_published.cmp(&a.date_published));
+
+    // without the shipped version, there's no telling where to start
+    let shipped = published(api, &versions, &shipped.version_id).await?;
+    let current_published = published(api, &versions, &current.version_id).await?;
+
+    Ok(versions
+        .into_iter()
+        .filter(|v| {
+            v.id == current.version_id
+                || (to.settings.channel.allows(&v.version_type)
+                    && current_published.is_some_and(|current| v.date_published <= current)
+                    && shipped.is_some_and(|shipped| v.date_published > shipped))
+        })
+        .map(|v| Changelog {
+            version_id: v.id,
+            version_number: v.version_number,
+            published: v.date_published.format("%Y-%m-%d").to_string(),
+            changelog: v.changelog.unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// Compares the mods of two packs, with the changelogs of every version in between.
+/// `excluded` says why mods that are missing from `to` were left out, if it's known.
+#[cfg(feature = "ssr")]
+pub(crate) async fn compare_packs(
+    api: &super::modrinth::ModrinthClient,
+    from: PackManifest,
+    to: PackManifest,
+    excluded: &[super::export::ExcludedMod],
+) -> super::modrinth::ApiResult<UpdateReport> {
+    use std::collections::{HashMap, HashSet};
+
+    let shipped = from
+        .mods
+        .iter()
+        .map(|m| (m.project_id.as_str(), m))
+        .collect::<HashMap<_, _>>();
+
+    let mut updates = Vec::new();
+    let mut added = Vec::new();
+    let mut unchanged = 0;
+    for current in &to.mods {
+        match shipped.get(current.project_id.as_str()) {
+            Some(shipped) if shipped.version_id == current.version_id => unchanged += 1,
+            Some(shipped) => updates.push(ModUpdate {
+                project_id: current.project_id.clone(),
+                title: current.title.clone(),
+                from: shipped.version_number.clone(),
+                to: current.version_number.clone(),
+                changelogs: changelogs(api, &to, shipped, current).await?,
+            }),
+            None => added.push(current.clone()),
+        }
+    }
+
+    let current = to
+        .mods
+        .iter()
+        .map(|m| m.project_id.as_str())
+        .collect::<HashSet<_>>();
+    let removed = from
+        .mods
+        .iter()
+        .filter(|m| !current.contains(m.project_id.as_str()))
+        .map(|m| RemovedMod {
+            project_id: m.project_id.clone(),
+            title: m.title.clone(),
+            reason: excluded
+                .iter()
+                .find(|e| e.project_id == m.project_id)
+                .map(|e| e.reason.clone()),
+        })
+        .collect();
+
+    updates.sort_by(|a, b| a.title.cmp(&b.title));
+    added.sort_by(|a, b| a.title.cmp(&b.title));
+
+    Ok(UpdateReport {
+        from,
+        to,
+        latest: false,
+        updates,
+        added,
+        removed,
+        unchanged,
+    })
+}
+
+/// Resolves the pack of `manifest` again and compares it with what was shipped.
+#[cfg(feature = "ssr")]
+pub(crate) async fn check_updates(
+    api: &super::modrinth::ModrinthClient,
+    exporter: &super::export::Exporter,
+    manifest: PackManifest,
+) -> UpdateResult<UpdateReport> {
+    use super::export::ExportRequest;
+
+    let request = ExportRequest::from_manifest(&manifest)
+        .ok_or_else(|| UpdateErr::GameVersion(manifest.game_version.clone()))?;
+    let resolution = exporter
+        .resolve(&request)
+        .await
+        .map_err(UpdateErr::Export)?;
+    let latest = request.manifest(&resolution);
+
+    let report = compare_packs(api, manifest, latest, &resolution.excluded)
+        .await
+        .map_err(UpdateErr::Api)?;
+
+    Ok(UpdateReport {
+        latest: true,
+        ..report
+    })
+}
+
+/// Reads the manifest out of an uploaded archive and checks it for updates, or compares it
+/// with a newer export if one was uploaded too.
+#[server(input = MultipartFormData)]
+async fn check_pack_updates(data: MultipartData) -> Result<UpdateReport, ServerFnError> {
+    use std::sync::Arc;
+
+    let api: Arc<super::modrinth::ModrinthClient> = use_context().unwrap();
+    let jobs: Arc<super::export::ExportJobs> = use_context().unwrap();
+
+    let mut data = data.into_inner().unwrap();
+
+    let mut pack = Vec::new();
+    let mut newer = Vec::new();
+    while let Some(mut field) = data.next_field().await? {
+        let upload = match field.name() {
+            Some("pack") => &mut pack,
+            Some("newer") => &mut newer,
+            _ => continue,
+        };
+        while let Some(chunk) = field.chunk().await? {
+            if upload.len() + chunk.len() > super::MAX_UPLOAD {
+                return Err(ServerFnError::new(format!(
+                    "packs can't be bigger than {} MB",
+                    super::MAX_UPLOAD / (1024 * 1024)
+                )));
+            }
+            upload.extend_from_slice(&chunk);
+        }
+    }
+
+    if pack.is_empty() {
+        return Err(ServerFnError::new("no pack was uploaded"));
+    }
+
+    let manifest = read_manifest(pack).await.map_err(ServerFnError::new)?;
+    if newer.is_empty() {
+        return check_updates(&api, jobs.exporter(), manifest)
+            .await
+            .map_err(ServerFnError::new);
+    }
+
+    let newer = read_manifest(newer).await.map_err(ServerFnError::new)?;
+    compare_packs(&api, manifest, newer, &[])
+        .await
+        .map_err(|err| ServerFnError::new(format!("couldn't compare the packs: {err}")))
+}
+
+/// Uploads an exported pack, lists what has updates since, and builds the updated pack.
+/// With a newer export, lists what changed between the two instead.
+#[component]
+pub fn UpdateChecker() -> impl IntoView {
+    let check = create_action(|data: &FormData| check_pack_updates(data.clone().into()));
+
+    view! {
+        <form class="update-checker" on:submit=move |ev| {
+            ev.prevent_default();
+
+            let form = ev.target().unwrap().unchecked_into::<HtmlFormElement>();
+            let data = FormData::new_with_form(&form).unwrap();
+
+            check.dispatch(data);
+        }>
+            <label>
+                "Check an exported pack for updates: "
+                <input type="file" name="pack" accept=".zip,.mrpack,.json"/>
+            </label>
+            <label>
+                " or compare it with a newer export: "
+                <input type="file" name="newer" accept=".zip,.mrpack,.json"/>
+            </label>
+            <button class="margin-all" type="submit">
+                {move || if check.pending().get() { "Checking..." } else { "Check" }}
+            </button>
+            {move || check.value().get().map(|report| match report {
+                Err(err) => view! { <p class="error">{err.to_string()}</p> }.into_view(),
+                Ok(report) => view! { <UpdateList report/> }.into_view(),
+            })}
+        </form>
+    }
+}
+
+#[component]
+fn UpdateList(report: UpdateReport) -> impl IntoView {
+    let changed = !report.is_empty();
+    let changelog = changed.then(|| {
+        view! {
+            <a
+                class="download"
+                href=markdown_url(&changelog_markdown(&report))
+                download="CHANGELOG.md"
+            >
+                "Download changelog"
+            </a>
+        }
+    });
+
+    let to = report.to;
+    let download = to
+        .game_version
+        .parse::<SemanticVersion>()
+        .ok()
+        .filter(|_| report.latest && changed)
+        .map(|version| {
+            let settings = to.settings.clone();
+            view! {
+                <DownloadButton
+                    collection_id=to.collection.clone()
+                    version
+                    settings=Signal::derive(move || settings.clone())
+                    projects=to.projects.iter().cloned().collect()
+                    format=to.format
+                    label="Download updated pack"
+                />
+            }
+        });
+
+    view! {
+        <h3>{format!(
+            "{} ({}, {}): {} updates, {} unchanged",
+            to.collection_name,
+            to.game_version,
+            to.settings.loader,
+            report.updates.len(),
+            report.unchanged,
+        )}</h3>
+        <ul>
+            {report.updates.into_iter().map(|update| view! {
+                <li>
+                    <details>
+                        <summary>{format!("{}: {} → {}", update.title, update.from, update.to)}</summary>
+                        {update.changelogs.into_iter().map(|changelog| view! {
+                            <h4>{format!("{} ({})", changelog.version_number, changelog.published)}</h4>
+                            <p class="changelog">{changelog.changelog}</p>
+                        }).collect_view()}
+                    </details>
+                </li>
+            }).collect_view()}
+            {report.added.into_iter().map(|added| view! {
+                <li>{format!("{}: added {}", added.title, added.version_number)}</li>
+            }).collect_view()}
+            {report.removed.into_iter().map(|removed| view! {
+                <li>{match removed.reason {
+                    Some(reason) => format!("{}: removed, {reason}", removed.title),
+                    None => format!("{}: removed", removed.title),
+                }}</li>
+            }).collect_view()}
+        </ul>
+        {download}
+        {changelog}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        changelog_markdown, check_updates, compare_packs, read_manifest, Changelog, RemovedMod,
+        UpdateErr, MAX_MANIFEST_SIZE,
+    };
+    use crate::{
+        app::{
+            export::{
+                test_setup::{request, setup},
+                ExclusionReason, ExportFormat, ManifestMod, PackManifest,
+            },
+            modrinth::ProjectKey,
+            workspace::CollectionSettings,
+        },
+        test_setup::temp_path,
+    };
+
+    fn shipped(
+        project_id: &str,
+        title: &str,
+        version_id: &str,
+        version_number: &str,
+    ) -> ManifestMod {
+        ManifestMod {
+            project_id: project_id.to_string(),
+            title: title.to_string(),
+            version_id: version_id.to_string(),
+            version_number: version_number.to_string(),
+            filename: format!("{version_id}.jar"),
+            sha1: String::new(),
+            sha512: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn updates_since_export() {
+        let (api, exporter, _) = setup("fixture1").await;
+
+        let manifest = PackManifest {
+            collection: "fixture1".to_string(),
+            collection_name: "Fixture Pack".to_string(),
+            game_version: "1.20.4".to_string(),
+            format: ExportFormat::ClientPack,
+            settings: CollectionSettings::default(),
+            projects: ["sodium", "iris", "modmenu"]
+                .map(|key| ProjectKey(key.to_string()))
+                .to_vec(),
+            mods: vec![
+                shipped("AANobbMI", "Sodium", "sodi1204", "0.5.8"),
+                shipped("YL57xq9U", "Iris Shaders", "iris1201", "1.7.0"),
+                shipped("mOgUt4GM", "Mod Menu", "mmen8000", "8.0.0"),
+            ],
+        };
+
+        // it's read from a plain json upload as well as from inside an archive
+        let upload = serde_json::to_vec(&manifest).unwrap();
+        let manifest = read_manifest(upload).await.unwrap();
+
+        let report = check_updates(&api, &exporter, manifest).await.unwrap();
+        assert_eq!(report.unchanged, 1);
+
+        // mod menu skipped a version, and needs fabric api now
+        assert_eq!(report.updates.len(), 1);
+        let update = &report.updates[0];
+        assert_eq!(
+            (update.from.as_str(), update.to.as_str()),
+            ("8.0.0", "9.0.0")
+        );
+        assert_eq!(
+            update.changelogs,
+            [
+                Changelog {
+                    version_id: "mmen1204".to_string(),
+                    version_number: "9.0.0".to_string(),
+                    published: "2024-01-01".to_string(),
+                    changelog: "Changes in 9.0.0".to_string(),
+                },
+                Changelog {
+                    version_id: "mmen8001".to_string(),
+                    version_number: "8.0.1".to_string(),
+                    published: "2023-12-10".to_string(),
+                    changelog: "Changes in 8.0.1".to_string(),
+                },
+            ]
+        );
+        assert_eq!(report.added.len(), 1);
+        assert_eq!(report.added[0].version_id, "fapi1204");
+
+        assert_eq!(
+            report.removed,
+            [RemovedMod {
+                project_id: "YL57xq9U".to_string(),
+                title: "Iris Shaders".to_string(),
+                reason: Some(ExclusionReason::NoVersion("1.20.4".to_string())),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn nothing_right_after_export() {
+        let (api, exporter, projects) = setup("fixture1").await;
+
+        let path = temp_path("updates.zip");
+        exporter
+            .write_archive(&request(projects, ExportFormat::ClientPack), &path)
+            .await
+            .unwrap();
+
+        let manifest = read_manifest(std::fs::read(&path).unwrap()).await.unwrap();
+        assert_eq!(manifest.mods.len(), 4);
+
+        let report = check_updates(&api, &exporter, manifest).await.unwrap();
+        assert!(report.latest && report.is_empty());
+        assert_eq!(report.unchanged, 4);
+
+        // not an archive at all
+        assert!(read_manifest(b"not a pack".to_vec()).await.is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn manifest_too_big() {
+        use async_zip::{base::write::ZipFileWriter, Compression, ZipEntryBuilder};
+
+        let mut zip = ZipFileWriter::new(Vec::new());
+        let builder = ZipEntryBuilder::new(PackManifest::FILENAME.into(), Compression::Deflate);
+        zip.write_entry_whole(builder, &vec![b' '; 2 * MAX_MANIFEST_SIZE as usize])
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            read_manifest(zip.close().await.unwrap()).await,
+            Err(UpdateErr::ManifestTooBig)
+        ));
+    }
+
+    #[tokio::test]
+    async fn changelog_between_exports() {
+        let (api, _, _) = setup("fixture1").await;
+
+        let pack = |game_version: &str, mods: Vec<ManifestMod>| PackManifest {
+            collection: "fixture1".to_string(),
+            collection_name: "Fixture Pack".to_string(),
+            game_version: game_version.to_string(),
+            format: ExportFormat::Mrpack,
+            settings: CollectionSettings::default(),
+            projects: vec![ProjectKey("modmenu".to_string())],
+            mods,
+        };
+        let from = pack(
+            "1.20.1",
+            vec![
+                shipped("mOgUt4GM", "Mod Menu", "mmen1201", "7.2.2"),
+                shipped("YL57xq9U", "Iris Shaders", "iris1201", "1.7.0"),
+            ],
+        );
+        let to = pack(
+            "1.20.4",
+            vec![shipped("mOgUt4GM", "Mod Menu", "mmen1204", "9.0.0")],
+        );
+
+        // every 1.20.4 version since the one shipped for 1.20.1
+        let report = compare_packs(&api, from, to, &[]).await.unwrap();
+        assert!(!report.latest);
+        assert_eq!(
+            changelog_markdown(&report),
+            "# Fixture Pack
+
+Minecraft 1.20.1 → 1.20.4, fabric
+
+## Mod Menu 7.2.2 → 9.0.0
+
+### 9.0.0 (2024-01-01)
+
+Changes in 9.0.0
+
+### 8.0.1 (2023-12-10)
+
+Changes in 8.0.1
+
+### 8.0.0 (2023-11-20)
+
+Changes in 8.0.0
+
+## Removed
+
+- Iris Shaders
+"
+        );
+    }
+}
@@ -0,0 +1,390 @@
+//! Comparing two game versions of a collection, to see what's blocking an upgrade.
+//!
+//! Mods are lost when they support the current game version but not the target, and gained
+//! the other way around. Mods that make it across can still break if something they require
+//! doesn't, which is listed as the chain of required dependencies leading to it.
+
+#[cfg(feature = "ssr")]
+use ferinth::structures::project::Project;
+use leptos::*;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "ssr")]
+use super::modrinth::ProjectKey;
+use super::SemanticVersion;
+
+/// A mod that's only available on the game version being upgraded from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MissingMod {
+    pub project_id: String,
+    pub title: String,
+    /// The newest game version the mod's newest version for the loader supports
+    pub latest_game_version: Option<String>,
+    /// When the mod last released a version for the loader, as `YYYY-MM-DD`
+    pub last_release: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GainedMod {
+    pub project_id: String,
+    pub title: String,
+}
+
+/// Required dependencies from a mod of the collection down to one that doesn't support
+/// the target game version, by title.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BrokenChain {
+    pub chain: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpgradeReport {
+    pub from: SemanticVersion,
+    pub to: SemanticVersion,
+    pub lost: Vec<MissingMod>,
+    pub gained: Vec<GainedMod>,
+    pub broken_chains: Vec<BrokenChain>,
+}
+
+#[cfg(feature = "ssr")]
+fn supports(project: &Project, loader: &str, game_version: SemanticVersion) -> bool {
+    project.loaders.iter().any(|l| l == loader)
+        && project
+            .game_versions
+            .iter()
+            .any(|v| v.parse::<SemanticVersion>() == Ok(game_version))
+}
+
+#[cfg(feature = "ssr")]
+fn latest_game_version(version: &ferinth::structures::version::Version) -> Option<String> {
+    version
+        .game_versions
+        .iter()
+        .filter_map(|v| Some((v.parse::<SemanticVersion>().ok()?, v)))
+        .max_by_key(|(version, _)| *version)
+        .map(|(_, v)| v.clone())
+}
+
+/// The newest version of a project for `loader`, on `game_version` if one is given.
+#[cfg(feature = "ssr")]
+async fn newest_version(
+    api: &super::modrinth::ModrinthClient,
+    project_id: &str,
+    loader: &str,
+    game_version: Option<SemanticVersion>,
+) -> super::modrinth::ApiResult<Option<ferinth::structures::version::Version>> {
+    let game_version = game_version.map(|v| v.to_game_version());
+    let game_version = game_version.as_deref();
+    let versions = api
+        .get_project_versions(project_id, &[loader], game_version.as_slice())
+        .await?;
+
+    Ok(versions
+        .into_iter()
+        .max_by(|a, b| a.date_published.cmp(&b.date_published)))
+}
+
+/// Compares what `projects` support on `from` and `to` for `loader`.
+#[cfg(feature = "ssr")]
+pub(crate) async fn upgrade_report(
+    api: &super::modrinth::ModrinthClient,
+    projects: &[(ProjectKey, Project)],
+    loader: &str,
+    from: SemanticVersion,
+    to: SemanticVersion,
+) -> super::modrinth::ApiResult<UpgradeReport> {
+    use std::collections::HashSet;
+
+    use ferinth::structures::version::DependencyType;
+
+    use super::modrinth::ApiErr;
+
+    let mut lost = Vec::new();
+    let mut gained = Vec::new();
+    let mut broken_chains = Vec::new();
+
+    for (_, project) in projects {
+        match (
+            supports(project, loader, from),
+            supports(project, loader, to),
+        ) {
+            (true, false) => {
+                // the project lists game versions for every loader, its newest version only
+                // those for this one
+                let newest = newest_version(api, &project.id, loader, None).await?;
+
+                lost.push(MissingMod {
+                    project_id: project.id.clone(),
+                    title: project.title.clone(),
+                    latest_game_version: newest.as_ref().and_then(latest_game_version),
+                    last_release: newest
+                        .map(|version| version.date_published.format("%Y-%m-%d").to_string()),
+                });
+            }
+            (false, true) => gained.push(GainedMod {
+                project_id: project.id.clone(),
+                title: project.title.clone(),
+            }),
+            (true, true) => {
+                // follows what the target versions require, depth first
+                let mut seen = HashSet::from([project.id.clone()]);
+                let mut stack = vec![(vec![project.title.clone()], project.id.clone())];
+
+                while let Some((chain, project_id)) = stack.pop() {
+                    let Some(version) = newest_version(api, &project_id, loader, Some(to)).await?
+                    else {
+                        continue;
+                    };
+
+                    for dependency in &version.dependencies {
+                        let Some(dependency_id) = &dependency.project_id else {
+                            continue;
+                        };
+                        if dependency.dependency_type != DependencyType::Required
+                            || !seen.insert(dependency_id.clone())
+                        {
+                            continue;
+                        }
+
+                        let dependency = match api.project(&ProjectKey(dependency_id.clone())).await
+                        {
+                            Ok(dependency) => dependency,
+                            // a deleted project was already broken before the upgrade
+                            Err(ApiErr::NotFound) => continue,
+                            Err(err) => return Err(err),
+                        };
+
+                        let mut chain = chain.clone();
+                        chain.push(dependency.title.clone());
+                        if supports(&dependency, loader, to) {
+                            stack.push((chain, dependency.id));
+                        } else if supports(&dependency, loader, from) {
+                            broken_chains.push(BrokenChain { chain });
+                        }
+                    }
+                }
+            }
+            (false, false) => {}
+        }
+    }
+
+    Ok(UpgradeReport {
+        from,
+        to,
+        lost,
+        gained,
+        broken_chains,
+    })
+}
+
+#[server]
+async fn get_upgrade_report(
+    collection_id: String,
+    loader: String,
+    from: SemanticVersion,
+    to: SemanticVersion,
+) -> Result<UpgradeReport, ServerFnError> {
+    use std::sync::Arc;
+
+    let api: Arc<super::modrinth::ModrinthClient> = use_context().unwrap();
+    let imports: Arc<super::packwiz::PackwizImports> = use_context().unwrap();
+
+    if !super::SUPPORTED_LOADERS.contains(&loader.as_str()) {
+        return Err(ServerFnError::new(format!(
+            "{loader} isn't a supported loader"
+        )));
+    }
+
+    let collection = super::coverage::resolve_collection(&api, &imports, &collection_id)
+        .await
+        .map_err(ServerFnError::new)?;
+    let projects = super::coverage::lookup_projects(&api, &collection.projects)
+        .await
+        .map_err(|err| ServerFnError::new(format!("couldn't look up the projects: {err}")))?;
+
+    upgrade_report(&api, &projects, &loader, from, to)
+        .await
+        .map_err(|err| ServerFnError::new(format!("couldn't compare {from} and {to}: {err}")))
+}
+
+/// Picks two game versions and lists what's lost, gained and broken going from one to the other.
+///
+/// `versions` are suggested, but any game version can be typed in, like one no mod supports yet.
+#[component]
+pub fn UpgradePanel(
+    collection_id: String,
+    loader: String,
+    versions: Vec<SemanticVersion>,
+    /// The game version upgraded from, at first
+    from: Option<SemanticVersion>,
+) -> impl IntoView {
+    let from = create_rw_signal(from);
+    let to = create_rw_signal(
+        versions
+            .iter()
+            .copied()
+            .filter(|version| Some(*version) > from.get_untracked())
+            .max(),
+    );
+
+    let report = create_local_resource(
+        move || (from.get(), to.get()),
+        move |(from, to)| {
+            let collection_id = collection_id.clone();
+            let loader = loader.clone();
+            async move {
+                match (from, to) {
+                    (Some(from), Some(to)) => {
+                        Some(get_upgrade_report(collection_id, loader, from, to).await)
+                    }
+                    _ => None,
+                }
+            }
+        },
+    );
+
+    let version_input = move |label: &'static str, version: RwSignal<Option<SemanticVersion>>| {
+        view! {
+            <label>
+                {label}
+                <input
+                    list="upgrade-versions"
+                    value=version.get_untracked().map(|v| v.to_game_version())
+                    on:change=move |ev| version.set(event_target_value(&ev).parse().ok())
+                />
+            </label>
+        }
+    };
+
+    view! {
+        <div class="upgrade-panel">
+            <datalist id="upgrade-versions">
+                {versions
+                    .iter()
+                    .map(|version| view! { <option value=version.to_game_version()/> })
+                    .collect_view()}
+            </datalist>
+            {version_input("From ", from)}
+            {version_input("To ", to)}
+
+            <Suspense fallback=|| view! { <p>"Loading..."</p> }>
+                {move || report.get().map(|report| match report {
+                    None => view! { <p>"Pick two game versions to compare"</p> }.into_view(),
+                    Some(Err(err)) => view! { <p class="error">{err.to_string()}</p> }.into_view(),
+                    Some(Ok(report)) => view! {
+                        <h3>{format!(
+                            "Lost going from {} to {}",
+                            report.from.to_game_version(),
+                            report.to.to_game_version(),
+                        )}</h3>
+                        {if report.lost.is_empty() {
+                            view! { <p>"Nothing"</p> }.into_view()
+                        } else {
+                            view! {
+                                <table>
+                                    <thead>
+                                        <tr>
+                                            <th>"Mod"</th>
+                                            <th>"Newest game version"</th>
+                                            <th>"Last release"</th>
+                                        </tr>
+                                    </thead>
+                                    <tbody>
+                                        {report.lost.into_iter().map(|missing| view! {
+                                            <tr>
+                                                <td>{missing.title}</td>
+                                                <td>{missing.latest_game_version.unwrap_or_default()}</td>
+                                                <td>{missing.last_release.unwrap_or_else(|| "never".to_string())}</td>
+                                            </tr>
+                                        }).collect_view()}
+                                    </tbody>
+                                </table>
+                            }.into_view()
+                        }}
+
+                        <h3>"Gained"</h3>
+                        {if report.gained.is_empty() {
+                            view! { <p>"Nothing"</p> }.into_view()
+                        } else {
+                            view! {
+                                <ul>
+                                    {report.gained.into_iter().map(|gained| view! {
+                                        <li>{gained.title}</li>
+                                    }).collect_view()}
+                                </ul>
+                            }.into_view()
+                        }}
+
+                        <h3>"Dependencies that break"</h3>
+                        {if report.broken_chains.is_empty() {
+                            view! { <p>"Nothing"</p> }.into_view()
+                        } else {
+                            view! {
+                                <ul>
+                                    {report.broken_chains.into_iter().map(|broken| view! {
+                                        <li>{broken.chain.join(" → ")}</li>
+                                    }).collect_view()}
+                                </ul>
+                            }.into_view()
+                        }}
+                    }.into_view(),
+                })}
+            </Suspense>
+        </div>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{upgrade_report, BrokenChain, GainedMod, MissingMod};
+    use crate::{
+        app::{coverage::lookup_projects, modrinth::ProjectKey, SemanticVersion},
+        test_setup::fake_api,
+    };
+
+    #[tokio::test]
+    async fn lost_gained_and_broken() {
+        let api = fake_api().await;
+
+        let keys = ["sodium", "iris", "lithium", "modmenu", "euphoria-patches"]
+            .map(|key| ProjectKey(key.to_string()));
+        let projects = lookup_projects(&api, &keys).await.unwrap();
+
+        let from = "1.20.1".parse::<SemanticVersion>().unwrap();
+        let to = "1.20.4".parse::<SemanticVersion>().unwrap();
+        let report = upgrade_report(&api, &projects, "fabric", from, to)
+            .await
+            .unwrap();
+        assert_eq!(
+            report.lost,
+            [MissingMod {
+                project_id: "YL57xq9U".to_string(),
+                title: "Iris Shaders".to_string(),
+                latest_game_version: Some("1.20.1".to_string()),
+                last_release: Some("2024-02-10".to_string()),
+            }]
+        );
+        assert!(report.gained.is_empty());
+        // modmenu still has fabric api, but euphoria patches needs iris
+        assert_eq!(
+            report.broken_chains,
+            [BrokenChain {
+                chain: vec!["Euphoria Patches".to_string(), "Iris Shaders".to_string()],
+            }]
+        );
+
+        // and the other way around
+        let report = upgrade_report(&api, &projects, "fabric", to, from)
+            .await
+            .unwrap();
+        assert!(report.lost.is_empty());
+        assert_eq!(
+            report.gained,
+            [GainedMod {
+                project_id: "YL57xq9U".to_string(),
+                title: "Iris Shaders".to_string(),
+            }]
+        );
+        assert!(report.broken_chains.is_empty());
+    }
+}
@@ -0,0 +1,319 @@
+//! The version history of a single project, shown when drilling down into the matrix.
+//!
+//! Only versions for one loader and game version are listed, newest first, and any of them
+//! can be pinned so exports of the collection use it instead of the latest one.
+
+use ferinth::structures::version::{DependencyType, VersionType};
+use leptos::*;
+use serde::{Deserialize, Serialize};
+
+use super::{modrinth::ProjectKey, SemanticVersion};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DependencySummary {
+    pub project_id: String,
+    /// The project's title, or its id if it couldn't be looked up
+    pub title: String,
+    pub dependency_type: DependencyType,
+}
+
+/// What the drill-down panel shows about a version.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionSummary {
+    pub id: String,
+    pub version_number: String,
+    pub version_type: VersionType,
+    /// `YYYY-MM-DD`
+    pub published: String,
+    pub dependencies: Vec<DependencySummary>,
+    /// The size of the primary file, in bytes
+    pub size: u64,
+}
+
+fn version_type_name(version_type: &VersionType) -> &'static str {
+    match version_type {
+        VersionType::Release => "release",
+        VersionType::Beta => "beta",
+        VersionType::Alpha => "alpha",
+    }
+}
+
+fn dependency_type_name(dependency_type: &DependencyType) -> &'static str {
+    match dependency_type {
+        DependencyType::Required => "required",
+        DependencyType::Optional => "optional",
+        DependencyType::Incompatible => "incompatible",
+        DependencyType::Embedded => "embedded",
+    }
+}
+
+/// Sizes the way people read them, like `1.5 MiB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+/// Every version of `key` for `loader` and `game_version`, newest first.
+#[cfg(feature = "ssr")]
+pub(crate) async fn version_history(
+    api: &super::modrinth::ModrinthClient,
+    key: &ProjectKey,
+    loader: &str,
+    game_version: &str,
+) -> super::modrinth::ApiResult<Vec<VersionSummary>> {
+    use std::collections::HashMap;
+
+    use itertools::Itertools;
+
+    let project = api.project(key).await?;
+    let mut versions = api
+        .get_project_versions(&project.id, &[loader], &[game_version])
+        .await?;
+    versions.sort_by(|a, b| b.date_published.cmp(&a.date_published));
+
+    // versions mostly share their dependencies, so each is looked up once
+    let dependency_keys = versions
+        .iter()
+        .flat_map(|version| &version.dependencies)
+        .filter_map(|dependency| dependency.project_id.clone())
+        .unique()
+        .map(ProjectKey)
+        .collect::<Vec<_>>();
+    let titles: HashMap<String, String> =
+        match super::coverage::lookup_projects(api, &dependency_keys).await {
+            Ok(projects) => projects
+                .into_iter()
+                .map(|(key, project)| (key.0, project.title))
+                .collect(),
+            // one of them is gone from modrinth, which shouldn't hide the titles of the rest
+            Err(_) => {
+                let mut titles = HashMap::new();
+                for key in dependency_keys {
+                    if let Ok(project) = api.project(&key).await {
+                        titles.insert(key.0, project.title);
+                    }
+                }
+                titles
+            }
+        };
+
+    let mut summaries = Vec::with_capacity(versions.len());
+    for version in versions {
+        let dependencies = version
+            .dependencies
+            .iter()
+            .filter_map(|dependency| {
+                let project_id = dependency.project_id.clone()?;
+                Some(DependencySummary {
+                    title: titles.get(&project_id).unwrap_or(&project_id).clone(),
+                    project_id,
+                    dependency_type: dependency.dependency_type.clone(),
+                })
+            })
+            .collect();
+
+        let primary_file = version
+            .files
+            .iter()
+            .find(|f| f.primary)
+            .or(version.files.first());
+
+        summaries.push(VersionSummary {
+            id: version.id,
+            version_number: version.version_number,
+            version_type: version.version_type,
+            published: version.date_published.format("%Y-%m-%d").to_string(),
+            dependencies,
+            size: primary_file.map_or(0, |file| file.size as u64),
+        });
+    }
+
+    Ok(summaries)
+}
+
+#[server]
+async fn get_version_history(
+    project: ProjectKey,
+    loader: String,
+    game_version: String,
+) -> Result<Vec<VersionSummary>, ServerFnError> {
+    use std::sync::Arc;
+
+    let api: Arc<super::modrinth::ModrinthClient> = use_context().unwrap();
+
+    if !super::SUPPORTED_LOADERS.contains(&loader.as_str()) {
+        return Err(ServerFnError::new(format!(
+            "{loader} isn't a supported loader"
+        )));
+    }
+    let game_version = game_version
+        .parse::<SemanticVersion>()
+        .map_err(|_| ServerFnError::new(format!("{game_version} isn't a game version")))?
+        .to_game_version();
+
+    version_history(&api, &project, &loader, &game_version)
+        .await
+        .map_err(|err| ServerFnError::new(format!("couldn't get the versions of {project}: {err}")))
+}
+
+/// Lists the versions of a project, and lets one be pinned if `on_pin` is given.
+#[component]
+pub fn VersionPanel(
+    project: ProjectKey,
+    title: String,
+    loader: String,
+    game_version: SemanticVersion,
+    /// The id of the version that's pinned, if any
+    #[prop(into)]
+    pinned: Signal<Option<String>>,
+    /// Pins a version, or unpins it with `None`
+    #[prop(default = None)]
+    on_pin: Option<Callback<Option<String>>>,
+    on_close: Callback<()>,
+) -> impl IntoView {
+    let versions = create_local_resource(
+        || (),
+        move |_| {
+            get_version_history(
+                project.clone(),
+                loader.clone(),
+                game_version.to_game_version(),
+            )
+        },
+    );
+
+    view! {
+        <div class="version-panel">
+            <h3>
+                {format!("{title} on {}", game_version.to_game_version())}
+                <button class="margin-all" on:click=move |_| on_close(())>"Close"</button>
+            </h3>
+            <Suspense fallback=|| view! { <p>"Loading..."</p> }>
+                {move || versions.get().map(|versions| match versions {
+                    Err(err) => view! { <p class="error">{err.to_string()}</p> }.into_view(),
+                    Ok(versions) if versions.is_empty() => view! {
+                        <p>"No versions for this game version and loader"</p>
+                    }.into_view(),
+                    Ok(versions) => view! {
+                        <table>
+                            <thead>
+                                <tr>
+                                    <th>"Version"</th>
+                                    <th>"Channel"</th>
+                                    <th>"Published"</th>
+                                    <th>"Dependencies"</th>
+                                    <th>"Size"</th>
+                                    {on_pin.map(|_| view! { <th></th> })}
+                                </tr>
+                            </thead>
+                            <tbody>
+                                {versions.into_iter().map(|version| {
+                                    let id = version.id.clone();
+                                    let is_pinned = {
+                                        let id = id.clone();
+                                        move || pinned.with(|pinned| pinned.as_ref() == Some(&id))
+                                    };
+
+                                    view! {
+                                        <tr class:pinned=is_pinned.clone()>
+                                            <td>{version.version_number}</td>
+                                            <td>{version_type_name(&version.version_type)}</td>
+                                            <td>{version.published}</td>
+                                            <td>
+                                                <ul>
+                                                    {version.dependencies.into_iter().map(|dependency| view! {
+                                                        <li>
+                                                            {dependency.title}
+                                                            " ("
+                                                            {dependency_type_name(&dependency.dependency_type)}
+                                                            ")"
+                                                        </li>
+                                                    }).collect_view()}
+                                                </ul>
+                                            </td>
+                                            <td>{format_size(version.size)}</td>
+                                            {on_pin.map(|on_pin| {
+                                                let label = is_pinned.clone();
+                                                view! {
+                                                    <td>
+                                                        <button on:click=move |_| {
+                                                            if is_pinned() {
+                                                                on_pin(None);
+                                                            } else {
+                                                                on_pin(Some(id.clone()));
+                                                            }
+                                                        }>
+                                                            {move || if label() { "Unpin" } else { "Pin" }}
+                                                        </button>
+                                                    </td>
+                                                }
+                                            })}
+                                        </tr>
+                                    }
+                                }).collect_view()}
+                            </tbody>
+                        </table>
+                    }.into_view(),
+                })}
+            </Suspense>
+        </div>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ferinth::structures::version::{DependencyType, VersionType};
+
+    use super::{format_size, version_history};
+    use crate::{app::modrinth::ProjectKey, test_setup::fake_api};
+
+    #[test]
+    fn sizes() {
+        assert_eq!(format_size(38), "38 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MiB");
+    }
+
+    #[tokio::test]
+    async fn history_with_dependencies() {
+        let api = fake_api().await;
+
+        let versions =
+            version_history(&api, &ProjectKey("modmenu".to_string()), "fabric", "1.20.1")
+                .await
+                .unwrap();
+        assert_eq!(versions.len(), 1);
+
+        let version = &versions[0];
+        assert_eq!(version.id, "mmen1201");
+        assert_eq!(version.version_number, "7.2.2");
+        assert_eq!(version.version_type, VersionType::Release);
+        assert_eq!(version.published, "2023-09-01");
+        assert_eq!(version.size, 32);
+
+        let dependencies = version
+            .dependencies
+            .iter()
+            .map(|d| (d.title.as_str(), d.dependency_type.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            dependencies,
+            [
+                ("Fabric API", DependencyType::Required),
+                ("Iris Shaders", DependencyType::Optional)
+            ]
+        );
+    }
+}
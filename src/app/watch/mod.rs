@@ -0,0 +1,423 @@
+//! Watching collections for changes in coverage, so nobody has to keep reloading the page.
+//!
+//! A watch names the game versions a collection is aiming for and the mods it can't do
+//! without. The server polls watched collections on a schedule, adding to their coverage
+//! history. When a target version reaches 100%, or a must-have mod starts supporting one,
+//! a notification goes to the in-app feed and to the webhook, if one is set.
+
+#[cfg(feature = "ssr")]
+mod store;
+
+#[cfg(feature = "ssr")]
+pub use store::*;
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+
+use ferinth::structures::UtcTime;
+use leptos::*;
+use serde::{Deserialize, Serialize};
+use server_fn::codec::Json;
+
+use super::{history::CoverageSnapshot, modrinth::ProjectKey, SemanticVersion};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Watch {
+    pub collection: String,
+    pub loader: String,
+    /// The game versions the collection is aiming for
+    pub targets: Vec<SemanticVersion>,
+    /// Mods that are worth a notification as soon as they support a target
+    #[serde(default)]
+    pub must_have: Vec<ProjectKey>,
+}
+
+impl Watch {
+    pub const MAX_TARGETS: usize = 16;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum NotificationKind {
+    /// Every mod of the collection supports a target version
+    FullCoverage { game_version: String },
+    /// A must-have mod started supporting a target version
+    ModSupported {
+        project_id: String,
+        title: String,
+        game_version: String,
+    },
+}
+
+/// Something that changed in a watched collection, shown in the feed and sent to the webhook.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notification {
+    pub id: u64,
+    /// Seconds since the unix epoch
+    pub time: u64,
+    pub collection: String,
+    pub collection_name: String,
+    #[serde(flatten)]
+    pub kind: NotificationKind,
+}
+
+impl Display for Notification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            NotificationKind::FullCoverage { game_version } => write!(
+                f,
+                "{}: every mod supports {game_version}",
+                self.collection_name
+            ),
+            NotificationKind::ModSupported {
+                title,
+                game_version,
+                ..
+            } => write!(
+                f,
+                "{}: {title} now supports {game_version}",
+                self.collection_name
+            ),
+        }
+    }
+}
+
+/// What changed from `previous` to `current` that's worth telling someone about.
+pub fn changes(
+    watch: &Watch,
+    previous: &CoverageSnapshot,
+    current: &CoverageSnapshot,
+    titles: &HashMap<ProjectKey, String>,
+) -> Vec<NotificationKind> {
+    let mut changes = Vec::new();
+
+    for target in &watch.targets {
+        let game_version = target.to_game_version();
+
+        if current.is_complete(*target) && !previous.is_complete(*target) {
+            changes.push(NotificationKind::FullCoverage {
+                game_version: game_version.clone(),
+            });
+        }
+
+        let before = previous.supported(*target);
+        let now = current.supported(*target);
+        for key in &watch.must_have {
+            let gained = now.is_some_and(|now| now.contains(key))
+                && !before.is_some_and(|before| before.contains(key));
+            if gained {
+                changes.push(NotificationKind::ModSupported {
+                    project_id: key.0.clone(),
+                    title: titles.get(key).cloned().unwrap_or_else(|| key.0.clone()),
+                    game_version: game_version.clone(),
+                });
+            }
+        }
+    }
+
+    changes
+}
+
+/// Formats seconds since the unix epoch like `2024-01-31 12:00 UTC`.
+pub fn format_time(time: u64) -> String {
+    UtcTime::from_timestamp(time as i64, 0)
+        .map(|time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_default()
+}
+
+/// A watch and when its collection was last polled.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchStatus {
+    pub watch: Watch,
+    pub last_polled: Option<u64>,
+}
+
+#[server]
+pub async fn watching_enabled() -> Result<bool, ServerFnError> {
+    Ok(use_context::<std::sync::Arc<WatchStore>>().is_some())
+}
+
+#[cfg(feature = "ssr")]
+fn watch_store() -> Result<std::sync::Arc<WatchStore>, ServerFnError> {
+    use_context().ok_or_else(|| ServerFnError::new("watching collections is disabled"))
+}
+
+#[server]
+pub async fn get_watch(collection: String) -> Result<Option<WatchStatus>, ServerFnError> {
+    Ok(watch_store()?.get(&collection).await)
+}
+
+/// Starts watching a collection, or changes what's watched. The collection is polled
+/// right away, so there's something to compare the next poll with.
+#[server(input = Json)]
+pub async fn set_watch(watch: Watch) -> Result<(), ServerFnError> {
+    let store = watch_store()?;
+
+    // polled by id, which a slug is only turned into once it's saved
+    let collection = store.set(watch).await.map_err(ServerFnError::new)?;
+    tokio::spawn(async move { store.poll(&collection).await });
+
+    Ok(())
+}
+
+#[server]
+pub async fn remove_watch(collection: String) -> Result<(), ServerFnError> {
+    watch_store()?
+        .remove(&collection)
+        .await
+        .map_err(ServerFnError::new)
+}
+
+/// The newest notifications first.
+#[server]
+pub async fn get_notifications() -> Result<Vec<Notification>, ServerFnError> {
+    Ok(watch_store()?.notifications().await)
+}
+
+/// Starts or stops watching a collection, with the targets and must-have mods to watch for.
+#[component]
+pub fn WatchPanel(
+    collection_id: String,
+    loader: String,
+    /// The collection's projects and their titles
+    projects: Vec<(ProjectKey, String)>,
+    /// The game versions suggested as targets
+    versions: Vec<SemanticVersion>,
+) -> impl IntoView {
+    let enabled = create_local_resource(|| (), |_| watching_enabled());
+
+    let stored_id = store_value(collection_id);
+    let status = create_local_resource(|| (), move |_| get_watch(stored_id.get_value()));
+
+    let targets = create_rw_signal(String::new());
+    let invalid_target = create_rw_signal(None::<String>);
+    let must_have = create_rw_signal(HashSet::<ProjectKey>::new());
+    create_effect(move |_| {
+        if let Some(Ok(Some(status))) = status.get() {
+            targets.set(
+                status
+                    .watch
+                    .targets
+                    .iter()
+                    .map(|target| target.to_game_version())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            );
+            must_have.set(status.watch.must_have.into_iter().collect());
+        }
+    });
+
+    let save = create_action(move |watch: &Watch| {
+        let watch = watch.clone();
+        async move {
+            set_watch(watch).await?;
+            status.refetch();
+            Ok::<_, ServerFnError>(())
+        }
+    });
+    let stop = create_action(move |_: &()| async move {
+        remove_watch(stored_id.get_value()).await?;
+        status.refetch();
+        Ok::<_, ServerFnError>(())
+    });
+
+    let on_save = move |_| {
+        let parsed = targets
+            .get_untracked()
+            .split(',')
+            .map(str::trim)
+            .filter(|target| !target.is_empty())
+            .map(|target| {
+                target
+                    .parse::<SemanticVersion>()
+                    .map_err(|_| target.to_string())
+            })
+            .collect::<Result<Vec<_>, _>>();
+
+        match parsed {
+            Ok(parsed) => {
+                invalid_target.set(None);
+                save.dispatch(Watch {
+                    collection: stored_id.get_value(),
+                    loader: loader.clone(),
+                    targets: parsed,
+                    must_have: must_have.get_untracked().into_iter().collect(),
+                });
+            }
+            Err(target) => invalid_target.set(Some(target)),
+        }
+    };
+
+    let placeholder = versions
+        .iter()
+        .take(2)
+        .map(|version| version.to_game_version())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    view! {
+        {move || matches!(enabled.get(), Some(Ok(true))).then(|| {
+            let projects = projects.clone();
+            let placeholder = placeholder.clone();
+            let on_save = on_save.clone();
+
+            view! {
+                <details class="watch-panel">
+                    <summary>
+                        {move || match status.get() {
+                            Some(Ok(Some(status))) => match status.last_polled {
+                                Some(time) => format!("Watching, last checked {}", format_time(time)),
+                                None => "Watching".to_string(),
+                            },
+                            _ => "Watch for changes".to_string(),
+                        }}
+                    </summary>
+                    <label>
+                        "Target game versions "
+                        <input
+                            type="text"
+                            placeholder=placeholder
+                            prop:value=move || targets.get()
+                            on:input=move |ev| targets.set(event_target_value(&ev))
+                        />
+                    </label>
+                    <p>"Must-have mods"</p>
+                    <ul>
+                        {projects.into_iter().map(|(key, title)| {
+                            let checked = {
+                                let key = key.clone();
+                                move || must_have.with(|must_have| must_have.contains(&key))
+                            };
+                            view! {
+                                <li>
+                                    <label>
+                                        <input
+                                            type="checkbox"
+                                            prop:checked=checked
+                                            on:change=move |ev| {
+                                                let key = key.clone();
+                                                if event_target_checked(&ev) {
+                                                    must_have.update(|must_have| { must_have.insert(key); });
+                                                } else {
+                                                    must_have.update(|must_have| { must_have.remove(&key); });
+                                                }
+                                            }
+                                        />
+                                        {title}
+                                    </label>
+                                </li>
+                            }
+                        }).collect_view()}
+                    </ul>
+                    <button class="margin-all" on:click=on_save>"Save"</button>
+                    {move || matches!(status.get(), Some(Ok(Some(_)))).then(|| view! {
+                        <button class="margin-all" on:click=move |_| stop.dispatch(())>
+                            "Stop watching"
+                        </button>
+                    })}
+                    {move || invalid_target.get().map(|target| view! {
+                        <p class="error">{format!("{target} isn't a game version")}</p>
+                    })}
+                    {move || match (save.value().get(), stop.value().get()) {
+                        (Some(Err(err)), _) | (_, Some(Err(err))) => {
+                            Some(view! { <p class="error">{err.to_string()}</p> })
+                        }
+                        _ => None,
+                    }}
+                </details>
+            }
+        })}
+    }
+}
+
+/// The notifications of every watched collection, newest first.
+#[component]
+pub fn NotificationFeed() -> impl IntoView {
+    let notifications = create_local_resource(|| (), |_| get_notifications());
+
+    view! {
+        {move || match notifications.get() {
+            Some(Ok(notifications)) if !notifications.is_empty() => Some(view! {
+                <details class="notification-feed">
+                    <summary>{format!("Notifications ({})", notifications.len())}</summary>
+                    <ul>
+                        {notifications.into_iter().map(|notification| view! {
+                            <li>
+                                <span class="time">{format_time(notification.time)}</span>
+                                " "
+                                {notification.to_string()}
+                            </li>
+                        }).collect_view()}
+                    </ul>
+                </details>
+            }),
+            _ => None,
+        }}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::{changes, format_time, NotificationKind, Watch};
+    use crate::app::{history::CoverageSnapshot, modrinth::ProjectKey, SemanticVersion};
+
+    fn key(id: &str) -> ProjectKey {
+        ProjectKey(id.to_string())
+    }
+
+    fn snapshot(projects: usize, versions: &[(&str, &[&str])]) -> CoverageSnapshot {
+        CoverageSnapshot {
+            time: 0,
+            projects,
+            versions: versions
+                .iter()
+                .map(|(version, ids)| {
+                    (
+                        version.parse().unwrap(),
+                        ids.iter().map(|id| key(id)).collect::<HashSet<_>>(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn notable_changes() {
+        let watch = Watch {
+            collection: "fixture1".to_string(),
+            loader: "fabric".to_string(),
+            targets: vec!["1.20.4".parse::<SemanticVersion>().unwrap()],
+            must_have: vec![key("iris")],
+        };
+        let titles = HashMap::from([(key("iris"), "Iris Shaders".to_string())]);
+
+        let before = snapshot(2, &[("1.20.4", &["sodium"])]);
+        let after = snapshot(2, &[("1.20.4", &["sodium", "iris"])]);
+        assert_eq!(
+            changes(&watch, &before, &after, &titles),
+            [
+                NotificationKind::FullCoverage {
+                    game_version: "1.20.4".to_string()
+                },
+                NotificationKind::ModSupported {
+                    project_id: "iris".to_string(),
+                    title: "Iris Shaders".to_string(),
+                    game_version: "1.20.4".to_string(),
+                },
+            ]
+        );
+
+        // nothing new, and losing support isn't announced
+        assert!(changes(&watch, &after, &after, &titles).is_empty());
+        assert!(changes(&watch, &after, &before, &titles).is_empty());
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(format_time(1_706_702_400), "2024-01-31 12:00 UTC");
+    }
+}
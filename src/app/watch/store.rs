@@ -0,0 +1,517 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use leptos::logging;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use super::{changes, Notification, Watch, WatchStatus};
+use crate::app::{
+    coverage::{self, CollectionData},
+    history::{CoverageSnapshot, HistoryStore},
+    modrinth::{ApiErr, ModrinthClient, ProjectKey},
+    packwiz::PackwizImports,
+    SUPPORTED_LOADERS,
+};
+
+/// How many collections can be watched at once.
+const MAX_WATCHES: usize = 256;
+/// How many notifications the feed keeps.
+const MAX_FEED: usize = 256;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum WatchErr {
+    #[error("{0}")]
+    Api(ApiErr),
+    #[error("{0}")]
+    Invalid(String),
+    #[error("can't watch more than {MAX_WATCHES} collections")]
+    TooMany,
+    #[error("this collection isn't watched")]
+    NotFound,
+}
+
+pub(crate) type WatchResult<T> = Result<T, WatchErr>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Watched {
+    watch: Watch,
+    /// What the last poll found, to compare the next one with
+    #[serde(default)]
+    last: Option<CoverageSnapshot>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct WatchFile {
+    #[serde(default)]
+    watches: HashMap<String, Watched>,
+    /// Oldest first
+    #[serde(default)]
+    feed: Vec<Notification>,
+    #[serde(default)]
+    next_id: u64,
+}
+
+/// Watched collections and their notifications, kept in a single json file.
+/// Their coverage goes into the `HistoryStore` too.
+#[derive(Debug)]
+pub struct WatchStore {
+    path: PathBuf,
+    api: Arc<ModrinthClient>,
+    imports: Arc<PackwizImports>,
+    history: Arc<HistoryStore>,
+    /// Notifications are posted here as json, along with a `text` summary
+    webhook: Option<reqwest::Url>,
+    http: reqwest::Client,
+    state: RwLock<WatchFile>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl WatchStore {
+    /// Loads the store from `path`, starting empty if the file doesn't exist yet.
+    pub async fn open(
+        path: impl Into<PathBuf>,
+        api: Arc<ModrinthClient>,
+        imports: Arc<PackwizImports>,
+        history: Arc<HistoryStore>,
+    ) -> io::Result<Self> {
+        let path = path.into();
+
+        let state = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => WatchFile::default(),
+            Err(err) => return Err(err),
+        };
+        for watched in state.watches.values() {
+            history
+                .set_watched(&watched.watch.collection, &watched.watch.loader, true)
+                .await;
+        }
+
+        Ok(Self {
+            path,
+            api,
+            imports,
+            history,
+            webhook: None,
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap(),
+            state: RwLock::new(state),
+        })
+    }
+
+    pub fn with_webhook(mut self, url: reqwest::Url) -> Self {
+        self.webhook = Some(url);
+        self
+    }
+
+    /// Writes everything back to disk, in one go so a crash can't leave the file half written.
+    async fn persist(&self, state: &WatchFile) {
+        let result = async {
+            if let Some(parent) = self.path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+
+            let contents = serde_json::to_vec(state)?;
+            let tmp = self.path.with_extension("tmp");
+            tokio::fs::write(&tmp, contents).await?;
+            tokio::fs::rename(&tmp, &self.path).await
+        };
+        if let Err(err) = result.await {
+            logging::error!("couldn't save watches to {}: {err}", self.path.display());
+        }
+    }
+
+    pub(crate) async fn get(&self, collection: &str) -> Option<WatchStatus> {
+        self.state
+            .read()
+            .await
+            .watches
+            .get(collection)
+            .map(|watched| WatchStatus {
+                watch: watched.watch.clone(),
+                last_polled: watched.last.as_ref().map(|snapshot| snapshot.time),
+            })
+    }
+
+    /// Watches a collection, keeping what the last poll found unless the loader changed.
+    /// Must-have mods and the collection can be given by slug, they're saved by id, and the
+    /// id of the collection is returned.
+    pub(crate) async fn set(&self, mut watch: Watch) -> WatchResult<String> {
+        if !SUPPORTED_LOADERS.contains(&watch.loader.as_str()) {
+            return Err(WatchErr::Invalid(format!(
+                "{} isn't a supported loader",
+                watch.loader
+            )));
+        }
+        if watch.targets.is_empty() || watch.targets.len() > Watch::MAX_TARGETS {
+            return Err(WatchErr::Invalid(format!(
+                "there should be between 1 and {} target game versions",
+                Watch::MAX_TARGETS
+            )));
+        }
+
+        let collection = coverage::resolve_collection(&self.api, &self.imports, &watch.collection)
+            .await
+            .map_err(WatchErr::Api)?;
+        watch.collection = collection.id;
+
+        let mut must_have = Vec::with_capacity(watch.must_have.len());
+        for key in &watch.must_have {
+            let project = self.api.project(key).await.map_err(WatchErr::Api)?;
+            must_have.push(ProjectKey(project.id));
+        }
+        must_have.sort();
+        must_have.dedup();
+        watch.must_have = must_have;
+        watch.targets.sort();
+        watch.targets.dedup();
+
+        let id = watch.collection.clone();
+        let mut state = self.state.write().await;
+        let watching = state.watches.len();
+        match state.watches.get_mut(&watch.collection) {
+            Some(watched) => {
+                if watched.watch.loader != watch.loader {
+                    watched.last = None;
+                    self.history
+                        .set_watched(&id, &watched.watch.loader, false)
+                        .await;
+                }
+                self.history.set_watched(&id, &watch.loader, true).await;
+                watched.watch = watch;
+            }
+            None if watching >= MAX_WATCHES => return Err(WatchErr::TooMany),
+            None => {
+                self.history.set_watched(&id, &watch.loader, true).await;
+                state
+                    .watches
+                    .insert(watch.collection.clone(), Watched { watch, last: None });
+            }
+        }
+        self.persist(&state).await;
+
+        Ok(id)
+    }
+
+    pub(crate) async fn remove(&self, collection: &str) -> WatchResult<()> {
+        let mut state = self.state.write().await;
+        let watched = state.watches.remove(collection).ok_or(WatchErr::NotFound)?;
+        self.history
+            .set_watched(collection, &watched.watch.loader, false)
+            .await;
+        self.persist(&state).await;
+
+        Ok(())
+    }
+
+    /// The newest notifications first.
+    pub(crate) async fn notifications(&self) -> Vec<Notification> {
+        self.state.read().await.feed.iter().rev().cloned().collect()
+    }
+
+    /// Looks up the collection and its projects again, rather than using cached ones.
+    async fn snapshot(&self, watch: &Watch) -> Result<(CollectionData, CoverageSnapshot), ApiErr> {
+        let collection =
+            coverage::resolve_collection(&self.api, &self.imports, &watch.collection).await?;
+        // a modrinth collection's projects were just fetched along with it, an import's weren't
+        if PackwizImports::is_import(&collection.id) {
+            for key in &collection.projects {
+                self.api.get_project(&key.0).await?;
+            }
+        }
+        let projects = coverage::lookup_projects(&self.api, &collection.projects).await?;
+
+        let data = CollectionData::new(collection, projects, &watch.loader);
+        let snapshot = CoverageSnapshot::new(&data, now());
+
+        Ok((data, snapshot))
+    }
+
+    /// Re-evaluates a watched collection, recording its coverage and sending out notifications
+    /// for what changed since the last time.
+    pub async fn poll(&self, collection: &str) {
+        let Some(watch) = self
+            .state
+            .read()
+            .await
+            .watches
+            .get(collection)
+            .map(|watched| watched.watch.clone())
+        else {
+            return;
+        };
+
+        let (data, snapshot) = match self.snapshot(&watch).await {
+            Ok(polled) => polled,
+            Err(err) => {
+                logging::warn!("couldn't poll watched collection {collection}: {err}");
+                return;
+            }
+        };
+        let titles = data
+            .projects
+            .iter()
+            .map(|(key, project)| (key.clone(), project.title.clone()))
+            .collect();
+
+        let mut notifications = Vec::new();
+        {
+            let mut state = self.state.write().await;
+            let WatchFile {
+                watches,
+                feed,
+                next_id,
+            } = &mut *state;
+            // it may have been removed or changed meanwhile
+            let Some(watched) = watches
+                .get_mut(collection)
+                .filter(|watched| watched.watch == watch)
+            else {
+                return;
+            };
+
+            // the first poll is what later ones are compared to
+            if let Some(previous) = &watched.last {
+                for kind in changes(&watch, previous, &snapshot, &titles) {
+                    let notification = Notification {
+                        id: *next_id,
+                        time: snapshot.time,
+                        collection: collection.to_string(),
+                        collection_name: data.collection.name.clone(),
+                        kind,
+                    };
+                    *next_id += 1;
+                    notifications.push(notification.clone());
+                    feed.push(notification);
+                }
+            }
+
+            watched.last = Some(snapshot.clone());
+            if feed.len() > MAX_FEED {
+                let extra = feed.len() - MAX_FEED;
+                feed.drain(..extra);
+            }
+
+            self.persist(&state).await;
+        }
+
+        self.history
+            .record(collection, &watch.loader, snapshot)
+            .await;
+        for notification in &notifications {
+            self.send_webhook(notification).await;
+        }
+    }
+
+    /// Polls every watched collection, one after the other.
+    pub async fn poll_all(&self) {
+        let collections = self
+            .state
+            .read()
+            .await
+            .watches
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for collection in collections {
+            self.poll(&collection).await;
+        }
+    }
+
+    async fn send_webhook(&self, notification: &Notification) {
+        let Some(url) = &self.webhook else {
+            return;
+        };
+
+        #[derive(Serialize)]
+        struct Payload<'a> {
+            text: String,
+            #[serde(flatten)]
+            notification: &'a Notification,
+        }
+
+        let result = self
+            .http
+            .post(url.clone())
+            .json(&Payload {
+                text: notification.to_string(),
+                notification,
+            })
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(err) = result {
+            logging::warn!(
+                "couldn't send notification {} to the webhook: {err}",
+                notification.id
+            );
+        }
+    }
+
+    /// Polls every watched collection now, and then every `interval`.
+    pub fn spawn_poller(self: Arc<Self>, interval: Duration) {
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                self.poll_all().await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use axum::{extract::State, routing::post, Json, Router};
+    use serde_json::Value;
+
+    use super::WatchStore;
+    use crate::{
+        app::{
+            history::HistoryStore,
+            modrinth::ProjectKey,
+            packwiz::PackwizImports,
+            watch::{NotificationKind, Watch},
+            SemanticVersion,
+        },
+        test_setup::{fake_api, temp_path},
+    };
+
+    /// A webhook that remembers what was posted to it.
+    async fn webhook() -> (reqwest::Url, Arc<Mutex<Vec<Value>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let app =
+            Router::new()
+                .route(
+                    "/hook",
+                    post(
+                        |State(received): State<Arc<Mutex<Vec<Value>>>>,
+                         Json(body): Json<Value>| async move {
+                            received.lock().unwrap().push(body);
+                        },
+                    ),
+                )
+                .with_state(received.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        (url.parse().unwrap(), received)
+    }
+
+    #[tokio::test]
+    async fn notifies_on_changes() {
+        let api = Arc::new(fake_api().await);
+        let imports = Arc::new(PackwizImports::default());
+        let (url, received) = webhook().await;
+
+        let path = temp_path("watches/watches.json");
+        let _ = std::fs::remove_file(&path);
+
+        let history = Arc::new(HistoryStore::new(Duration::from_secs(60 * 60)));
+        let store = WatchStore::open(&path, api.clone(), imports.clone(), history.clone())
+            .await
+            .unwrap()
+            .with_webhook(url);
+        let target = "1.20.1".parse::<SemanticVersion>().unwrap();
+        let id = store
+            .set(Watch {
+                collection: "fixture1".to_string(),
+                loader: "fabric".to_string(),
+                targets: vec![target],
+                must_have: vec![ProjectKey("modmenu".to_string())],
+            })
+            .await
+            .unwrap();
+        assert_eq!(id, "fixture1");
+
+        // the first poll has nothing to compare to
+        store.poll(&id).await;
+        assert!(store.notifications().await.is_empty());
+
+        // as if modmenu didn't support 1.20.1 the last time
+        {
+            let mut state = store.state.write().await;
+            let snapshot = state
+                .watches
+                .get_mut("fixture1")
+                .unwrap()
+                .last
+                .as_mut()
+                .unwrap();
+            let supported = &mut snapshot
+                .versions
+                .iter_mut()
+                .find(|(version, _)| *version == target)
+                .unwrap()
+                .1;
+            supported.remove(&ProjectKey("mOgUt4GM".to_string()));
+        }
+
+        store.poll("fixture1").await;
+        let kinds = store
+            .notifications()
+            .await
+            .into_iter()
+            .map(|notification| notification.kind)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                NotificationKind::ModSupported {
+                    project_id: "mOgUt4GM".to_string(),
+                    title: "Mod Menu".to_string(),
+                    game_version: "1.20.1".to_string(),
+                },
+                NotificationKind::FullCoverage {
+                    game_version: "1.20.1".to_string()
+                },
+            ]
+        );
+
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0]["kind"], "full_coverage");
+        assert_eq!(
+            received[0]["text"],
+            "Fixture Pack: every mod supports 1.20.1"
+        );
+        assert_eq!(received[1]["kind"], "mod_supported");
+
+        // polls go into the coverage history too
+        assert_eq!(history.history("fixture1", "fabric").await.len(), 1);
+
+        // it all survives a restart
+        let reopened = WatchStore::open(&path, api, imports, history)
+            .await
+            .unwrap();
+        let status = reopened.get("fixture1").await.unwrap();
+        assert_eq!(status.watch.must_have, [ProjectKey("mOgUt4GM".to_string())]);
+        assert!(status.last_polled.is_some());
+        assert_eq!(reopened.notifications().await.len(), 2);
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -0,0 +1,347 @@
+//! The list of collections someone is looking at, along with their settings.
+//!
+//! It always lives in cookies, `modrinth_collections` for the ids and
+//! `modrinth_collection_settings` for settings that were changed, and can optionally be saved on
+//! the server under an anonymous workspace token so it survives across devices.
+
+#[cfg(feature = "ssr")]
+mod store;
+
+#[cfg(feature = "ssr")]
+pub use store::*;
+
+use std::collections::HashMap;
+
+use leptos::*;
+use serde::{Deserialize, Serialize};
+use server_fn::codec::Json;
+
+use super::{modrinth::ChannelPolicy, DEFAULT_LOADER};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollectionSettings {
+    pub loader: String,
+    #[serde(default)]
+    pub channel: ChannelPolicy,
+    /// Versions to always export, keyed by project id
+    #[serde(default)]
+    pub pinned: HashMap<String, String>,
+}
+
+impl Default for CollectionSettings {
+    fn default() -> Self {
+        Self {
+            loader: DEFAULT_LOADER.to_string(),
+            channel: ChannelPolicy::default(),
+            pinned: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkspaceCollection {
+    pub id: String,
+    #[serde(default)]
+    pub settings: CollectionSettings,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Workspace {
+    /// In the order they're shown, newest first
+    pub collections: Vec<WorkspaceCollection>,
+}
+
+impl Workspace {
+    pub const MAX_COLLECTIONS: usize = 256;
+    /// How long collection ids, and the ids and loaders in their settings, can be
+    pub const MAX_ID_LEN: usize = 64;
+    /// How many versions a single collection can pin
+    pub const MAX_PINNED: usize = 256;
+
+    /// The cookie lists collection ids oldest first, and settings are kept in another one.
+    pub fn from_cookie(
+        ids: Vec<String>,
+        mut settings: HashMap<String, CollectionSettings>,
+    ) -> Self {
+        let mut workspace = Workspace::default();
+        for id in ids {
+            workspace.add(id);
+        }
+        for collection in &mut workspace.collections {
+            if let Some(settings) = settings.remove(&collection.id) {
+                collection.settings = settings;
+            }
+        }
+        workspace
+    }
+
+    pub fn to_cookie(&self) -> Vec<String> {
+        self.collections
+            .iter()
+            .rev()
+            .map(|c| c.id.clone())
+            .collect()
+    }
+
+    /// Only settings that were changed go in the cookie, to keep it small.
+    pub fn settings_cookie(&self) -> HashMap<String, CollectionSettings> {
+        self.collections
+            .iter()
+            .filter(|c| c.settings != CollectionSettings::default())
+            .map(|c| (c.id.clone(), c.settings.clone()))
+            .collect()
+    }
+
+    /// Adds a collection to the top of the list, unless it's already there.
+    pub fn add(&mut self, id: String) {
+        if self.collections.iter().all(|c| c.id != id) {
+            self.collections.insert(
+                0,
+                WorkspaceCollection {
+                    id,
+                    settings: Default::default(),
+                },
+            );
+        }
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        self.collections.retain(|c| c.id != id);
+    }
+
+    /// Moves a collection up (negative) or down (positive) the list.
+    pub fn move_by(&mut self, id: &str, offset: isize) {
+        let Some(from) = self.collections.iter().position(|c| c.id == id) else {
+            return;
+        };
+        let to = from
+            .saturating_add_signed(offset)
+            .min(self.collections.len() - 1);
+
+        let collection = self.collections.remove(from);
+        self.collections.insert(to, collection);
+    }
+
+    pub fn settings(&self, id: &str) -> CollectionSettings {
+        self.collections
+            .iter()
+            .find(|c| c.id == id)
+            .map(|c| c.settings.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn settings_mut(&mut self, id: &str) -> Option<&mut CollectionSettings> {
+        self.collections
+            .iter_mut()
+            .find(|c| c.id == id)
+            .map(|c| &mut c.settings)
+    }
+
+    /// Reads an exported workspace, or failing that a plain list of collection ids
+    /// separated by whitespace or commas (in the order they should be shown).
+    pub fn import(text: &str) -> Option<Self> {
+        if let Ok(workspace) = serde_json::from_str::<Workspace>(text) {
+            return Some(workspace);
+        }
+
+        let ids = text
+            .split(|ch: char| ch.is_whitespace() || ch == ',')
+            .filter(|id| !id.is_empty())
+            .map(|id| id.to_string())
+            .rev()
+            .collect::<Vec<_>>();
+
+        if ids.is_empty() {
+            None
+        } else {
+            Some(Workspace::from_cookie(ids, HashMap::new()))
+        }
+    }
+}
+
+#[server]
+pub async fn workspace_storage_enabled() -> Result<bool, ServerFnError> {
+    Ok(use_context::<std::sync::Arc<WorkspaceStore>>().is_some())
+}
+
+#[cfg(feature = "ssr")]
+fn workspace_store() -> Result<std::sync::Arc<WorkspaceStore>, ServerFnError> {
+    use_context().ok_or_else(|| ServerFnError::new("server-side storage is disabled"))
+}
+
+/// Saves a workspace on the server, returning the token it can be loaded with.
+#[server(input = Json)]
+pub async fn create_workspace(workspace: Workspace) -> Result<String, ServerFnError> {
+    workspace_store()?
+        .create(workspace)
+        .await
+        .map_err(ServerFnError::new)
+}
+
+#[server]
+pub async fn load_workspace(token: String) -> Result<Workspace, ServerFnError> {
+    workspace_store()?
+        .get(&token)
+        .await
+        .map_err(ServerFnError::new)
+}
+
+#[server(input = Json)]
+pub async fn save_workspace(token: String, workspace: Workspace) -> Result<(), ServerFnError> {
+    workspace_store()?
+        .save(&token, workspace)
+        .await
+        .map_err(ServerFnError::new)
+}
+
+/// Controls for keeping the workspace on the server and moving it between devices.
+#[component]
+pub fn WorkspacePanel(
+    workspace: RwSignal<Workspace>,
+    token: Signal<Option<String>>,
+    set_token: WriteSignal<Option<String>>,
+) -> impl IntoView {
+    let enabled = create_local_resource(|| (), |_| workspace_storage_enabled());
+
+    let create = create_action(move |workspace: &Workspace| {
+        let workspace = workspace.clone();
+        async move {
+            let token = create_workspace(workspace).await?;
+            set_token.set(Some(token));
+            Ok::<_, ServerFnError>(())
+        }
+    });
+
+    let show_export = create_rw_signal(false);
+    let token_text = create_rw_signal(String::new());
+    let import_text = create_rw_signal(String::new());
+    let import_error = create_rw_signal(false);
+
+    view! {
+        <details class="workspace-panel">
+            <summary>"Workspace"</summary>
+
+            {move || match (enabled.get(), token.get()) {
+                (Some(Ok(true)), Some(current)) => view! {
+                    <p>
+                        "Saved on the server. Open it on another device with the token "
+                        <code>{current}</code>
+                    </p>
+                    <button class="margin-all" on:click=move |_| set_token.set(None)>
+                        "Stop syncing"
+                    </button>
+                }.into_view(),
+                (Some(Ok(true)), None) => view! {
+                    <button class="margin-all" on:click=move |_| create.dispatch(workspace.get_untracked())>
+                        "Save to server"
+                    </button>
+                    <form on:submit=move |ev| {
+                        ev.prevent_default();
+                        let val = token_text.get_untracked().trim().to_string();
+                        if !val.is_empty() {
+                            set_token.set(Some(val));
+                            token_text.set(String::new());
+                        }
+                    }>
+                        <input
+                            type="text"
+                            placeholder="Or open a workspace token"
+                            prop:value=move || token_text.get()
+                            on:input=move |ev| token_text.set(event_target_value(&ev))
+                        />
+                    </form>
+                }.into_view(),
+                _ => view! { <p>"Collections are only saved in this browser."</p> }.into_view(),
+            }}
+
+            <button class="margin-all" on:click=move |_| show_export.update(|s| *s = !*s)>
+                {move || if show_export.get() { "Hide export" } else { "Export" }}
+            </button>
+            <Show when=move || show_export.get()>
+                <textarea readonly rows="8">
+                    {move || serde_json::to_string_pretty(&workspace.get()).unwrap_or_default()}
+                </textarea>
+            </Show>
+
+            <form on:submit=move |ev| {
+                ev.prevent_default();
+                match Workspace::import(&import_text.get_untracked()) {
+                    Some(imported) => {
+                        workspace.set(imported);
+                        import_text.set(String::new());
+                        import_error.set(false);
+                    }
+                    None => import_error.set(true),
+                }
+            }>
+                <textarea
+                    rows="3"
+                    placeholder="Paste an exported workspace or a list of collection ids"
+                    prop:value=move || import_text.get()
+                    on:input=move |ev| import_text.set(event_target_value(&ev))
+                />
+                <button class="margin-all" type="submit">"Import"</button>
+            </form>
+            <Show when=move || import_error.get()>
+                <p class="error">"That isn't a workspace or a list of collection ids"</p>
+            </Show>
+        </details>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::Workspace;
+
+    fn ids(workspace: &Workspace) -> Vec<&str> {
+        workspace
+            .collections
+            .iter()
+            .map(|c| c.id.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn cookie_round_trip() {
+        let cookie = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let mut workspace = Workspace::from_cookie(cookie.clone(), HashMap::new());
+
+        assert_eq!(ids(&workspace), ["c", "b", "a"]);
+        assert_eq!(workspace.to_cookie(), cookie);
+
+        let settings = workspace.settings_mut("b").unwrap();
+        settings.loader = "quilt".to_string();
+        settings
+            .pinned
+            .insert("AANobbMI".to_string(), "OihdIimA".to_string());
+
+        let settings = workspace.settings_cookie();
+        assert_eq!(settings.keys().collect::<Vec<_>>(), ["b"]);
+        assert_eq!(Workspace::from_cookie(cookie, settings), workspace);
+    }
+
+    #[test]
+    fn move_clamps() {
+        let mut workspace = Workspace::import("a b c").unwrap();
+
+        workspace.move_by("a", -5);
+        assert_eq!(ids(&workspace), ["a", "b", "c"]);
+
+        workspace.move_by("a", 1);
+        assert_eq!(ids(&workspace), ["b", "a", "c"]);
+
+        workspace.move_by("b", 10);
+        assert_eq!(ids(&workspace), ["a", "c", "b"]);
+    }
+
+    #[test]
+    fn import_json() {
+        let mut workspace = Workspace::import("a,b").unwrap();
+        workspace.settings_mut("b").unwrap().loader = "quilt".to_string();
+
+        let exported = serde_json::to_string(&workspace).unwrap();
+        assert_eq!(Workspace::import(&exported), Some(workspace));
+    }
+}
@@ -0,0 +1,244 @@
+//! Saving workspaces on the server, so they can be opened on another device with their token.
+
+use std::{collections::HashMap, io, path::PathBuf};
+
+use tokio::sync::RwLock;
+
+use super::Workspace;
+
+#[derive(Debug, thiserror::Error)]
+pub enum WorkspaceErr {
+    #[error("io error: {0}")]
+    Io(io::Error),
+    #[error("json error: {0}")]
+    Json(serde_json::Error),
+    #[error("invalid workspace token")]
+    InvalidToken,
+    #[error("workspace not found")]
+    NotFound,
+    #[error(
+        "workspaces can't have more than {} collections",
+        Workspace::MAX_COLLECTIONS
+    )]
+    TooLarge,
+    #[error(
+        "ids in a workspace can't be longer than {} characters",
+        Workspace::MAX_ID_LEN
+    )]
+    IdTooLong,
+    #[error("a collection can't pin more than {} versions", Workspace::MAX_PINNED)]
+    TooManyPinned,
+    #[error("no more workspaces can be saved on this server")]
+    Full,
+}
+
+pub type WorkspaceResult<T> = Result<T, WorkspaceErr>;
+
+/// Workspaces keyed by their token, kept in a single json file.
+#[derive(Debug)]
+pub struct WorkspaceStore {
+    path: PathBuf,
+    workspaces: RwLock<HashMap<String, Workspace>>,
+    max_workspaces: usize,
+}
+
+impl WorkspaceStore {
+    /// Anyone can create a workspace, so there's a limit to how many the file holds.
+    pub const MAX_WORKSPACES: usize = 10_000;
+
+    /// Loads the store from `path`, starting empty if the file doesn't exist yet.
+    pub async fn open(path: impl Into<PathBuf>) -> WorkspaceResult<Self> {
+        let path = path.into();
+
+        let workspaces = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => serde_json::from_str(&contents).map_err(WorkspaceErr::Json)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(WorkspaceErr::Io(err)),
+        };
+
+        Ok(Self {
+            path,
+            workspaces: RwLock::new(workspaces),
+            max_workspaces: Self::MAX_WORKSPACES,
+        })
+    }
+
+    /// How many workspaces can be created, [`WorkspaceStore::MAX_WORKSPACES`] by default.
+    pub fn with_max_workspaces(mut self, max_workspaces: usize) -> Self {
+        self.max_workspaces = max_workspaces;
+        self
+    }
+
+    /// Writes every workspace back to disk.
+    /// The file is replaced in one go so a crash can't leave it half written.
+    async fn persist(&self, workspaces: &HashMap<String, Workspace>) -> WorkspaceResult<()> {
+        let contents = serde_json::to_vec(workspaces).map_err(WorkspaceErr::Json)?;
+
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(WorkspaceErr::Io)?;
+        }
+
+        let tmp = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp, contents)
+            .await
+            .map_err(WorkspaceErr::Io)?;
+        tokio::fs::rename(&tmp, &self.path)
+            .await
+            .map_err(WorkspaceErr::Io)
+    }
+
+    fn validate_token(token: &str) -> WorkspaceResult<()> {
+        uuid::Uuid::parse_str(token)
+            .map(|_| ())
+            .map_err(|_| WorkspaceErr::InvalidToken)
+    }
+
+    /// Workspaces come from anyone, so they're kept small enough to store.
+    fn validate_workspace(workspace: &Workspace) -> WorkspaceResult<()> {
+        if workspace.collections.len() > Workspace::MAX_COLLECTIONS {
+            return Err(WorkspaceErr::TooLarge);
+        }
+
+        for collection in &workspace.collections {
+            let settings = &collection.settings;
+            if settings.pinned.len() > Workspace::MAX_PINNED {
+                return Err(WorkspaceErr::TooManyPinned);
+            }
+
+            let ids = settings
+                .pinned
+                .iter()
+                .flat_map(|(project, version)| [project, version])
+                .chain([&collection.id, &settings.loader]);
+            if ids.into_iter().any(|id| id.len() > Workspace::MAX_ID_LEN) {
+                return Err(WorkspaceErr::IdTooLong);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn create(&self, workspace: Workspace) -> WorkspaceResult<String> {
+        Self::validate_workspace(&workspace)?;
+
+        let token = uuid::Uuid::new_v4().to_string();
+
+        let mut workspaces = self.workspaces.write().await;
+        if workspaces.len() >= self.max_workspaces {
+            return Err(WorkspaceErr::Full);
+        }
+        workspaces.insert(token.clone(), workspace);
+        self.persist(&workspaces).await?;
+
+        Ok(token)
+    }
+
+    pub(crate) async fn get(&self, token: &str) -> WorkspaceResult<Workspace> {
+        Self::validate_token(token)?;
+
+        self.workspaces
+            .read()
+            .await
+            .get(token)
+            .cloned()
+            .ok_or(WorkspaceErr::NotFound)
+    }
+
+    pub(crate) async fn save(&self, token: &str, workspace: Workspace) -> WorkspaceResult<()> {
+        Self::validate_token(token)?;
+        Self::validate_workspace(&workspace)?;
+
+        let mut workspaces = self.workspaces.write().await;
+        let existing = workspaces.get_mut(token).ok_or(WorkspaceErr::NotFound)?;
+
+        if *existing == workspace {
+            return Ok(());
+        }
+        *existing = workspace;
+
+        self.persist(&workspaces).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WorkspaceErr, WorkspaceStore};
+    use crate::{app::workspace::Workspace, test_setup::temp_path};
+
+    #[tokio::test]
+    async fn saved_across_restarts() {
+        let path = temp_path("workspaces/workspaces.json");
+        let _ = std::fs::remove_file(&path);
+
+        let store = WorkspaceStore::open(&path).await.unwrap();
+        let mut workspace = Workspace::import("a b").unwrap();
+        let token = store.create(workspace.clone()).await.unwrap();
+
+        workspace.settings_mut("a").unwrap().loader = "quilt".to_string();
+        store.save(&token, workspace.clone()).await.unwrap();
+
+        let restarted = WorkspaceStore::open(&path).await.unwrap();
+        assert_eq!(restarted.get(&token).await.unwrap(), workspace);
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_workspaces() {
+        let path = temp_path("workspaces/rejected.json");
+        let _ = std::fs::remove_file(&path);
+        let store = WorkspaceStore::open(&path).await.unwrap();
+
+        assert!(matches!(
+            store.get("../../etc/passwd").await,
+            Err(WorkspaceErr::InvalidToken)
+        ));
+        assert!(matches!(
+            store.get(&uuid::Uuid::new_v4().to_string()).await,
+            Err(WorkspaceErr::NotFound)
+        ));
+
+        let ids = (0..=Workspace::MAX_COLLECTIONS)
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert!(matches!(
+            store.create(Workspace::import(&ids).unwrap()).await,
+            Err(WorkspaceErr::TooLarge)
+        ));
+
+        let long_id = "a".repeat(Workspace::MAX_ID_LEN + 1);
+        assert!(matches!(
+            store.create(Workspace::import(&long_id).unwrap()).await,
+            Err(WorkspaceErr::IdTooLong)
+        ));
+
+        let mut workspace = Workspace::import("a").unwrap();
+        workspace.settings_mut("a").unwrap().pinned = (0..=Workspace::MAX_PINNED)
+            .map(|id| (id.to_string(), id.to_string()))
+            .collect();
+        assert!(matches!(
+            store.create(workspace).await,
+            Err(WorkspaceErr::TooManyPinned)
+        ));
+    }
+
+    #[tokio::test]
+    async fn limits_workspaces() {
+        let path = temp_path("workspaces/limited.json");
+        let _ = std::fs::remove_file(&path);
+        let store = WorkspaceStore::open(&path)
+            .await
+            .unwrap()
+            .with_max_workspaces(1);
+
+        let workspace = Workspace::import("a").unwrap();
+        let token = store.create(workspace.clone()).await.unwrap();
+        assert!(matches!(
+            store.create(workspace.clone()).await,
+            Err(WorkspaceErr::Full)
+        ));
+        // the ones that are there can still be saved
+        store.save(&token, Workspace::default()).await.unwrap();
+    }
+}
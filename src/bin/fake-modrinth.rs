@@ -0,0 +1,34 @@
+//! Runs the fake modrinth api on its own, for the end2end tests or trying the app offline.
+//!
+//! ```text
+//! fake-modrinth [fixture dir] [--port 4010]
+//! ```
+
+#[tokio::main]
+async fn main() {
+    let mut dir = "fixtures/modrinth".to_string();
+    let mut port = 4010;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => {
+                port = args
+                    .next()
+                    .and_then(|p| p.parse().ok())
+                    .expect("`--port` to be an u16")
+            }
+            _ => dir = arg,
+        }
+    }
+
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
+        .await
+        .unwrap();
+    let base = mr_modpack::fake_modrinth::serve(listener, &dir)
+        .await
+        .expect("the fixtures to be valid");
+    println!("serving {dir} as the modrinth api at {base}");
+
+    std::future::pending::<()>().await;
+}
@@ -0,0 +1,285 @@
+//! Running exports from the command line, without starting the web server.
+//!
+//! ```text
+//! mr-modpack resolve <collection> --loader fabric --version 1.20.1 --format mrpack -o pack.mrpack
+//! ```
+
+use std::{path::PathBuf, process::ExitCode, sync::Arc};
+
+use crate::{
+    app::{
+        coverage::{self, CollectionData},
+        export::{ExportFormat, ExportRequest, Exporter, JarCache},
+        loader_meta,
+        modrinth::{ChannelPolicy, ModrinthClient},
+        workspace::CollectionSettings,
+        SemanticVersion, DEFAULT_LOADER, SUPPORTED_LOADERS,
+    },
+    config::Config,
+};
+
+const USAGE: &str = "\
+usage: mr-modpack resolve <collection> [options]
+
+Resolves a Modrinth collection for a game version and writes it as a pack.
+
+options:
+    --loader <loader>      fabric, quilt, forge or neoforge (default: fabric)
+    --version <version>    the Minecraft version (default: the best supported one)
+    --format <format>      client, server, prism or mrpack (default: mrpack)
+    --channel <channel>    release, beta or alpha (default: release)
+    -o, --output <path>    where to write the pack (default: <collection>.<format extension>)
+
+Running without arguments starts the web server.";
+
+#[derive(Debug, Clone, PartialEq)]
+struct ResolveArgs {
+    collection: String,
+    loader: String,
+    version: Option<SemanticVersion>,
+    format: ExportFormat,
+    channel: ChannelPolicy,
+    output: Option<PathBuf>,
+}
+
+impl ResolveArgs {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut collection = None;
+        let mut loader = DEFAULT_LOADER.to_string();
+        let mut version = None;
+        let mut format = ExportFormat::Mrpack;
+        let mut channel = ChannelPolicy::default();
+        let mut output = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // both `--flag value` and `--flag=value` are accepted
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if arg.starts_with('-') => (flag.to_string(), Some(value)),
+                _ => (arg.clone(), None),
+            };
+            let mut value = || {
+                inline
+                    .map(str::to_string)
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{flag} needs a value"))
+            };
+
+            match flag.as_str() {
+                "--loader" => loader = value()?,
+                "--version" => {
+                    let value = value()?;
+                    version = Some(
+                        value
+                            .parse::<SemanticVersion>()
+                            .map_err(|_| format!("{value} isn't a game version"))?,
+                    );
+                }
+                "--format" => format = value()?.parse()?,
+                "--channel" => {
+                    let value = value()?;
+                    channel = value
+                        .parse()
+                        .map_err(|_| format!("unknown channel {value}"))?;
+                }
+                "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+                flag if flag.starts_with('-') => return Err(format!("unknown option {flag}")),
+                _ if collection.is_none() => collection = Some(arg),
+                _ => return Err(format!("unexpected argument {arg}")),
+            }
+        }
+
+        if !SUPPORTED_LOADERS.contains(&loader.as_str()) {
+            return Err(format!(
+                "loader must be one of {}",
+                SUPPORTED_LOADERS.join(", ")
+            ));
+        }
+
+        Ok(Self {
+            collection: collection.ok_or("missing the collection to resolve")?,
+            loader,
+            version,
+            format,
+            channel,
+            output,
+        })
+    }
+}
+
+/// Runs the command in `args` (without the binary name).
+pub async fn run(args: Vec<String>) -> ExitCode {
+    let mut args = args.into_iter();
+
+    let result = match args.next().as_deref() {
+        Some("resolve") => match ResolveArgs::parse(args) {
+            Ok(args) => resolve(args).await,
+            Err(err) => Err(format!("{err}\n\n{USAGE}")),
+        },
+        Some("-h" | "--help" | "help") => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Some(other) => Err(format!("unknown command {other}\n\n{USAGE}")),
+        None => Err(USAGE.to_string()),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn resolve(args: ResolveArgs) -> Result<(), String> {
+    let config = Config::load().map_err(|err| format!("invalid config: {err}"))?;
+    let api = Arc::new(ModrinthClient::from_config(&config.modrinth));
+    let loader_meta =
+        loader_meta::from_config(&config.loader_meta).map_err(|err| err.to_string())?;
+
+    let mut exporter = Exporter::new(api.clone(), loader_meta);
+    exporter
+        .check_loader(&args.loader, args.format)
+        .map_err(|err| err.to_string())?;
+    if let Some(dir) = &config.cache.dir {
+        let cache = JarCache::open(dir, config.cache.max_size())
+            .await
+            .map_err(|err| format!("couldn't open the jar cache: {err}"))?;
+        exporter = exporter.with_cache(Arc::new(cache));
+    }
+
+    let collection = api
+        .get_collection(&args.collection)
+        .await
+        .map_err(|err| format!("couldn't get collection {}: {err}", args.collection))?;
+    let projects = coverage::lookup_projects(&api, &collection.projects)
+        .await
+        .map_err(|err| format!("couldn't look up the projects: {err}"))?;
+    let data = CollectionData::new(collection, projects, &args.loader);
+
+    let (release_version, supported) = match args.version {
+        Some(version) => {
+            let supported = data
+                .available_versions
+                .iter()
+                .find(|(v, _)| *v == version)
+                .map(|(_, projects)| projects.len())
+                .unwrap_or(0);
+            (version, supported)
+        }
+        None => {
+            let (version, projects) = data
+                .best_version()
+                .ok_or_else(|| format!("no mod in the collection supports {}", args.loader))?;
+            (*version, projects.len())
+        }
+    };
+    println!(
+        "{}: {supported} of {} mods support Minecraft {} with {}",
+        data.collection.name,
+        data.collection.projects.len(),
+        release_version.to_game_version(),
+        args.loader,
+    );
+
+    let output = args.output.unwrap_or_else(|| {
+        PathBuf::from(format!(
+            "{}.{}",
+            data.collection.id,
+            args.format.extension()
+        ))
+    });
+
+    let request = ExportRequest {
+        collection_id: data.collection.id.clone(),
+        collection_name: data.collection.name.clone(),
+        release_version,
+        settings: CollectionSettings {
+            loader: args.loader,
+            channel: args.channel,
+            ..Default::default()
+        },
+        projects: data.collection.projects.iter().cloned().collect(),
+        format: args.format,
+    };
+
+    let resolution = exporter
+        .write_archive(&request, &output)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    for m in &resolution.mods {
+        println!(
+            "  + {} {} ({})",
+            m.project.title, m.version.version_number, m.file.filename
+        );
+    }
+    for excluded in &resolution.excluded {
+        println!("  - {}: {}", excluded.title, excluded.reason);
+    }
+    if resolution.retries > 0 {
+        println!("{} requests had to be retried", resolution.retries);
+    }
+    println!("wrote {}", output.display());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::ResolveArgs;
+    use crate::app::{export::ExportFormat, modrinth::ChannelPolicy};
+
+    fn parse(args: &[&str]) -> Result<ResolveArgs, String> {
+        ResolveArgs::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn parse_full() {
+        let args = parse(&[
+            "my-collection",
+            "--loader",
+            "quilt",
+            "--version=1.20.1",
+            "--format",
+            "prism",
+            "--channel",
+            "beta",
+            "-o",
+            "pack.zip",
+        ])
+        .unwrap();
+
+        assert_eq!(args.collection, "my-collection");
+        assert_eq!(args.loader, "quilt");
+        assert_eq!(args.version.unwrap().to_game_version(), "1.20.1");
+        assert_eq!(args.format, ExportFormat::Prism);
+        assert_eq!(args.channel, ChannelPolicy::Beta);
+        assert_eq!(args.output, Some(PathBuf::from("pack.zip")));
+    }
+
+    #[test]
+    fn parse_defaults() {
+        let args = parse(&["my-collection"]).unwrap();
+
+        assert_eq!(args.loader, "fabric");
+        assert_eq!(args.version, None);
+        assert_eq!(args.format, ExportFormat::Mrpack);
+        assert_eq!(args.channel, ChannelPolicy::Release);
+        assert_eq!(args.output, None);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["a", "b"]).is_err());
+        assert!(parse(&["a", "--version"]).is_err());
+        assert!(parse(&["a", "--loader", "rift"]).is_err());
+        assert!(parse(&["a", "--format", "curseforge"]).is_err());
+        assert!(parse(&["a", "--verbose"]).is_err());
+    }
+}
@@ -0,0 +1,564 @@
+//! Settings for the server and the command line, read from a toml file with environment
+//! variables on top.
+//!
+//! The file is `mr-modpack.toml` in the working directory, or whatever `MR_MODPACK_CONFIG`
+//! points at. Every setting has a default, so the file is optional.
+//! See `mr-modpack.example.toml` for everything that can be set.
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::app::{loader_meta::FABRIC_META_ENDPOINT, modrinth::MODRINTH_ENDPOINT};
+
+pub const CONFIG_FILE: &str = "mr-modpack.toml";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigErr {
+    #[error("couldn't read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("couldn't parse {0}: {1}")]
+    Toml(PathBuf, toml::de::Error),
+    #[error("`{var}` is set to {value:?}, but {reason}")]
+    Env {
+        var: &'static str,
+        value: String,
+        reason: String,
+    },
+    #[error("`{field}` {reason}")]
+    Invalid { field: &'static str, reason: String },
+}
+
+pub type ConfigResult<T> = Result<T, ConfigErr>;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub modrinth: ModrinthConfig,
+    pub loader_meta: LoaderMetaConfig,
+    pub exports: ExportConfig,
+    pub cache: CacheConfig,
+    pub storage: StorageConfig,
+    pub watch: WatchConfig,
+    pub history: HistoryConfig,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Listen on every interface on this port, instead of leptos' `site-addr`
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModrinthConfig {
+    /// The api root, which has `v2/` and `v3/` under it
+    pub endpoint: String,
+    /// Put in the user agent so modrinth knows who to reach out to
+    pub contact: Option<String>,
+    /// The average rate, bursts of up to this many are sent at once
+    pub requests_per_minute: u32,
+    /// How many times a request is sent before giving up, when it keeps failing with
+    /// connection errors, 5xx or 429 responses
+    pub retry_attempts: u32,
+    /// The wait before the first retry, doubled (with some jitter) for each one after it
+    pub retry_delay_ms: u64,
+}
+
+impl Default for ModrinthConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: MODRINTH_ENDPOINT.to_string(),
+            contact: Some("notaflyinggoose@gmail.com".to_string()),
+            // modrinth allows 300, leave some room for other clients on the same ip
+            requests_per_minute: 250,
+            retry_attempts: 4,
+            retry_delay_ms: 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoaderMetaConfig {
+    /// Fixed loader versions to use instead of asking a meta server
+    pub fixture: Option<PathBuf>,
+    pub fabric_endpoint: String,
+}
+
+impl Default for LoaderMetaConfig {
+    fn default() -> Self {
+        Self {
+            fixture: None,
+            fabric_endpoint: FABRIC_META_ENDPOINT.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExportConfig {
+    /// Where finished archives are kept until they're downloaded, a folder in the system's
+    /// temp dir by default
+    pub dir: Option<PathBuf>,
+    /// How long a finished archive can be downloaded for
+    pub cleanup_delay_secs: u64,
+    /// How many archives can be built at the same time, other exports wait in a queue
+    pub concurrency: usize,
+    /// The oldest archives are removed early once they take up more than this together
+    pub max_size_mb: u64,
+    /// How often expired archives are looked for
+    pub janitor_interval_secs: u64,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            cleanup_delay_secs: 2 * 60,
+            concurrency: 4,
+            max_size_mb: 4096,
+            janitor_interval_secs: 60,
+        }
+    }
+}
+
+impl ExportConfig {
+    pub fn cleanup_delay(&self) -> Duration {
+        Duration::from_secs(self.cleanup_delay_secs)
+    }
+
+    /// The size limit in bytes
+    pub fn max_size(&self) -> u64 {
+        self.max_size_mb.saturating_mul(1024 * 1024)
+    }
+
+    pub fn janitor_interval(&self) -> Duration {
+        Duration::from_secs(self.janitor_interval_secs)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Where downloaded jars are kept between exports, nothing is cached if unset
+    pub dir: Option<PathBuf>,
+    /// The least recently used jars are removed once the cache grows past this
+    pub max_size_mb: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_size_mb: 1024,
+        }
+    }
+}
+
+impl CacheConfig {
+    /// The size limit in bytes
+    pub fn max_size(&self) -> u64 {
+        self.max_size_mb.saturating_mul(1024 * 1024)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// The json file workspaces are saved in, they only live in a cookie if unset
+    pub workspaces: Option<PathBuf>,
+    /// The json file watched collections are saved in, along with their notifications.
+    /// Nothing can be watched if unset
+    pub watches: Option<PathBuf>,
+    /// The json file coverage history is saved in, it's only kept in memory if unset
+    pub history: Option<PathBuf>,
+    /// The json file uploaded packwiz packs are saved in.
+    /// They're only kept in memory if unset, so links to them stop working after a restart
+    pub imports: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// A collection's coverage is recorded whenever it changes, and at most this often if not
+    pub snapshot_interval_secs: u64,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            snapshot_interval_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+impl HistoryConfig {
+    pub fn snapshot_interval(&self) -> Duration {
+        Duration::from_secs(self.snapshot_interval_secs)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatchConfig {
+    /// How often watched collections are polled
+    pub interval_secs: u64,
+    /// Where notifications are posted as json, they're only shown in the app if unset
+    pub webhook_url: Option<String>,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 6 * 60 * 60,
+            webhook_url: None,
+        }
+    }
+}
+
+impl WatchConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    /// The webhook url, which [`Config::validate`] already made sure parses.
+    pub fn webhook(&self) -> Option<reqwest::Url> {
+        self.webhook_url.as_deref().and_then(|url| url.parse().ok())
+    }
+}
+
+/// Parses an environment variable, explaining what was expected if it's invalid.
+fn parse_env<T: std::str::FromStr>(
+    var: &'static str,
+    value: String,
+    expected: &str,
+) -> ConfigResult<T> {
+    value.parse().map_err(|_| ConfigErr::Env {
+        var,
+        value,
+        reason: format!("it should be {expected}"),
+    })
+}
+
+fn check_url(field: &'static str, url: &str) -> ConfigResult<()> {
+    match reqwest::Url::parse(url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(()),
+        _ => Err(ConfigErr::Invalid {
+            field,
+            reason: format!("should be an http(s) url, not {url:?}"),
+        }),
+    }
+}
+
+impl Config {
+    /// Reads the config file (if there is one), applies the environment, and validates it.
+    pub fn load() -> ConfigResult<Self> {
+        let mut config = match std::env::var("MR_MODPACK_CONFIG") {
+            Ok(path) => Self::from_file(path)?,
+            _ if Path::new(CONFIG_FILE).exists() => Self::from_file(CONFIG_FILE)?,
+            _ => Self::default(),
+        };
+
+        config.apply_env(|var| std::env::var(var).ok())?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> ConfigResult<Self> {
+        let path = path.as_ref();
+        let contents =
+            std::fs::read_to_string(path).map_err(|err| ConfigErr::Io(path.to_path_buf(), err))?;
+
+        toml::from_str(&contents).map_err(|err| ConfigErr::Toml(path.to_path_buf(), err))
+    }
+
+    /// Overrides settings with the environment variables that are set.
+    pub fn apply_env(&mut self, env: impl Fn(&str) -> Option<String>) -> ConfigResult<()> {
+        if let Some(port) = env("PORT") {
+            self.server.port = Some(parse_env("PORT", port, "a port number")?);
+        }
+
+        if let Some(endpoint) = env("MODRINTH_URL") {
+            self.modrinth.endpoint = endpoint;
+        }
+        if let Some(contact) = env("MODRINTH_CONTACT") {
+            self.modrinth.contact = Some(contact).filter(|c| !c.is_empty());
+        }
+        if let Some(rate) = env("MODRINTH_REQUESTS_PER_MINUTE") {
+            self.modrinth.requests_per_minute =
+                parse_env("MODRINTH_REQUESTS_PER_MINUTE", rate, "a whole number")?;
+        }
+        if let Some(attempts) = env("MODRINTH_RETRY_ATTEMPTS") {
+            self.modrinth.retry_attempts =
+                parse_env("MODRINTH_RETRY_ATTEMPTS", attempts, "a whole number")?;
+        }
+        if let Some(delay) = env("MODRINTH_RETRY_DELAY_MS") {
+            self.modrinth.retry_delay_ms =
+                parse_env("MODRINTH_RETRY_DELAY_MS", delay, "a number of milliseconds")?;
+        }
+
+        if let Some(fixture) = env("LOADER_META_FIXTURE") {
+            self.loader_meta.fixture = Some(fixture.into());
+        }
+        if let Some(endpoint) = env("FABRIC_META_URL") {
+            self.loader_meta.fabric_endpoint = endpoint;
+        }
+
+        if let Some(dir) = env("EXPORT_DIR") {
+            self.exports.dir = Some(dir.into());
+        }
+        if let Some(delay) = env("EXPORT_CLEANUP_DELAY_SECS") {
+            self.exports.cleanup_delay_secs =
+                parse_env("EXPORT_CLEANUP_DELAY_SECS", delay, "a number of seconds")?;
+        }
+        if let Some(concurrency) = env("EXPORT_CONCURRENCY") {
+            self.exports.concurrency =
+                parse_env("EXPORT_CONCURRENCY", concurrency, "a whole number")?;
+        }
+        if let Some(size) = env("EXPORT_MAX_SIZE_MB") {
+            self.exports.max_size_mb = parse_env("EXPORT_MAX_SIZE_MB", size, "a whole number")?;
+        }
+        if let Some(interval) = env("EXPORT_JANITOR_INTERVAL_SECS") {
+            self.exports.janitor_interval_secs = parse_env(
+                "EXPORT_JANITOR_INTERVAL_SECS",
+                interval,
+                "a number of seconds",
+            )?;
+        }
+
+        if let Some(dir) = env("CACHE_DIR") {
+            self.cache.dir = Some(dir.into());
+        }
+        if let Some(size) = env("CACHE_MAX_SIZE_MB") {
+            self.cache.max_size_mb = parse_env("CACHE_MAX_SIZE_MB", size, "a whole number")?;
+        }
+
+        if let Some(path) = env("WORKSPACE_STORE") {
+            self.storage.workspaces = Some(path.into());
+        }
+        if let Some(path) = env("WATCH_STORE") {
+            self.storage.watches = Some(path.into());
+        }
+        if let Some(path) = env("HISTORY_STORE") {
+            self.storage.history = Some(path.into());
+        }
+        if let Some(path) = env("IMPORT_STORE") {
+            self.storage.imports = Some(path.into());
+        }
+
+        if let Some(interval) = env("WATCH_INTERVAL_SECS") {
+            self.watch.interval_secs =
+                parse_env("WATCH_INTERVAL_SECS", interval, "a number of seconds")?;
+        }
+        if let Some(url) = env("WATCH_WEBHOOK_URL") {
+            self.watch.webhook_url = Some(url).filter(|url| !url.is_empty());
+        }
+
+        if let Some(interval) = env("HISTORY_SNAPSHOT_INTERVAL_SECS") {
+            self.history.snapshot_interval_secs = parse_env(
+                "HISTORY_SNAPSHOT_INTERVAL_SECS",
+                interval,
+                "a number of seconds",
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn validate(&self) -> ConfigResult<()> {
+        check_url("modrinth.endpoint", &self.modrinth.endpoint)?;
+        if self.modrinth.requests_per_minute == 0 {
+            return Err(ConfigErr::Invalid {
+                field: "modrinth.requests_per_minute",
+                reason: "has to be at least 1".to_string(),
+            });
+        }
+        if self.modrinth.retry_attempts == 0 {
+            return Err(ConfigErr::Invalid {
+                field: "modrinth.retry_attempts",
+                reason: "has to be at least 1, which means requests aren't retried".to_string(),
+            });
+        }
+
+        match &self.loader_meta.fixture {
+            Some(fixture) if !fixture.is_file() => {
+                return Err(ConfigErr::Invalid {
+                    field: "loader_meta.fixture",
+                    reason: format!("points at {}, which isn't a file", fixture.display()),
+                })
+            }
+            Some(_) => {}
+            None => check_url(
+                "loader_meta.fabric_endpoint",
+                &self.loader_meta.fabric_endpoint,
+            )?,
+        }
+
+        if self.exports.concurrency == 0 {
+            return Err(ConfigErr::Invalid {
+                field: "exports.concurrency",
+                reason: "has to be at least 1".to_string(),
+            });
+        }
+        if self.exports.cleanup_delay_secs == 0 {
+            return Err(ConfigErr::Invalid {
+                field: "exports.cleanup_delay_secs",
+                reason: "has to be at least 1, or archives are gone before they're downloaded"
+                    .to_string(),
+            });
+        }
+        if self.exports.max_size_mb == 0 {
+            return Err(ConfigErr::Invalid {
+                field: "exports.max_size_mb",
+                reason: "has to be at least 1, or archives are gone before they're downloaded"
+                    .to_string(),
+            });
+        }
+        if self.exports.janitor_interval_secs == 0 {
+            return Err(ConfigErr::Invalid {
+                field: "exports.janitor_interval_secs",
+                reason: "has to be at least 1".to_string(),
+            });
+        }
+
+        if self.cache.dir.is_some() && self.cache.max_size_mb == 0 {
+            return Err(ConfigErr::Invalid {
+                field: "cache.max_size_mb",
+                reason: "has to be at least 1 when `cache.dir` is set".to_string(),
+            });
+        }
+
+        if self.watch.interval_secs == 0 {
+            return Err(ConfigErr::Invalid {
+                field: "watch.interval_secs",
+                reason: "has to be at least 1".to_string(),
+            });
+        }
+        if let Some(url) = &self.watch.webhook_url {
+            check_url("watch.webhook_url", url)?;
+        }
+        if self.history.snapshot_interval_secs == 0 {
+            return Err(ConfigErr::Invalid {
+                field: "history.snapshot_interval_secs",
+                reason: "has to be at least 1".to_string(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::PathBuf};
+
+    use super::{Config, ConfigErr};
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |var| vars.get(var).cloned()
+    }
+
+    #[test]
+    fn example_is_valid() {
+        let config = Config::from_file("mr-modpack.example.toml").unwrap();
+        config.validate().unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn parse_partial() {
+        let config: Config = toml::from_str(
+            r#"
+[server]
+port = 8080
+
+[exports]
+concurrency = 2
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.server.port, Some(8080));
+        assert_eq!(config.exports.concurrency, 2);
+        assert_eq!(config.exports.cleanup_delay_secs, 120);
+        assert_eq!(config.modrinth.requests_per_minute, 250);
+    }
+
+    #[test]
+    fn unknown_fields() {
+        assert!(toml::from_str::<Config>("[exports]\nconcurency = 2").is_err());
+    }
+
+    #[test]
+    fn env_overrides() {
+        let mut config = Config::default();
+        config
+            .apply_env(env(&[
+                ("PORT", "3005"),
+                ("MODRINTH_URL", "http://127.0.0.1:4010/"),
+                ("EXPORT_CLEANUP_DELAY_SECS", "30"),
+                ("EXPORT_MAX_SIZE_MB", "512"),
+                ("WORKSPACE_STORE", "workspaces.json"),
+                ("WATCH_WEBHOOK_URL", "http://127.0.0.1:9000/hook"),
+            ]))
+            .unwrap();
+
+        assert_eq!(config.server.port, Some(3005));
+        assert_eq!(config.modrinth.endpoint, "http://127.0.0.1:4010/");
+        assert_eq!(config.exports.cleanup_delay_secs, 30);
+        assert_eq!(config.exports.max_size(), 512 * 1024 * 1024);
+        assert_eq!(
+            config.storage.workspaces,
+            Some(PathBuf::from("workspaces.json"))
+        );
+        assert_eq!(
+            config.watch.webhook_url.as_deref(),
+            Some("http://127.0.0.1:9000/hook")
+        );
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn invalid() {
+        let mut config = Config::default();
+        assert!(matches!(
+            config.apply_env(env(&[("PORT", "eighty")])),
+            Err(ConfigErr::Env { var: "PORT", .. })
+        ));
+
+        let mut config = Config::default();
+        config.modrinth.endpoint = "api.modrinth.com".to_string();
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigErr::Invalid {
+                field: "modrinth.endpoint",
+                ..
+            })
+        ));
+
+        let mut config = Config::default();
+        config.exports.concurrency = 0;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigErr::Invalid {
+                field: "exports.concurrency",
+                ..
+            })
+        ));
+
+        let mut config = Config::default();
+        config.loader_meta.fixture = Some("does/not/exist.json".into());
+        assert!(config.validate().is_err());
+    }
+}
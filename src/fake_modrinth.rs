@@ -0,0 +1,187 @@
+//! A stand-in for the modrinth api that serves fixture files, so tests can run offline.
+//!
+//! The fixture folder is laid out like `fixtures/modrinth`:
+//! `collections/*.json` (v3 collections), `projects/*.json` and `versions/*.json`
+//! (v2 projects and versions), and `files/` with the jars versions link to.
+//! `{{base}}` in any json file is replaced with the url the server is running on.
+
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{Path as UrlPath, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde_json::Value;
+
+#[derive(Debug)]
+pub struct Fixtures {
+    collections: HashMap<String, Value>,
+    projects: Vec<Value>,
+    versions: Vec<Value>,
+    files: PathBuf,
+}
+
+fn read_dir(dir: &Path, base: &str) -> io::Result<Vec<Value>> {
+    let mut values = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+
+        let contents = std::fs::read_to_string(&path)?.replace("{{base}}", base);
+        let value = serde_json::from_str(&contents).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {err}", path.display()),
+            )
+        })?;
+        values.push(value);
+    }
+
+    Ok(values)
+}
+
+impl Fixtures {
+    /// Reads every fixture in `dir`, with links pointing at `base`.
+    pub fn load(dir: impl AsRef<Path>, base: &str) -> io::Result<Self> {
+        let dir = dir.as_ref();
+
+        let collections = read_dir(&dir.join("collections"), base)?
+            .into_iter()
+            .map(|c| (c["id"].as_str().unwrap_or_default().to_string(), c))
+            .collect();
+
+        Ok(Self {
+            collections,
+            projects: read_dir(&dir.join("projects"), base)?,
+            versions: read_dir(&dir.join("versions"), base)?,
+            files: dir.join("files"),
+        })
+    }
+
+    /// Finds a project by its id or slug, like modrinth does.
+    fn project(&self, id: &str) -> Option<&Value> {
+        self.projects
+            .iter()
+            .find(|p| p["id"] == id || p["slug"] == id)
+    }
+}
+
+fn not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({ "error": "not_found" })),
+    )
+        .into_response()
+}
+
+/// Whether a version matches a `["a","b"]` filter from the query string.
+fn matches_filter(version: &Value, field: &str, filter: Option<&String>) -> bool {
+    let Some(filter) = filter else {
+        return true;
+    };
+    let Ok(wanted) = serde_json::from_str::<Vec<String>>(filter) else {
+        return false;
+    };
+
+    version[field]
+        .as_array()
+        .is_some_and(|values| values.iter().any(|v| wanted.iter().any(|w| v == w)))
+}
+
+async fn collection(
+    State(fixtures): State<Arc<Fixtures>>,
+    UrlPath(id): UrlPath<String>,
+) -> Response {
+    match fixtures.collections.get(&id) {
+        Some(collection) => Json(collection.clone()).into_response(),
+        None => not_found(),
+    }
+}
+
+async fn project(State(fixtures): State<Arc<Fixtures>>, UrlPath(id): UrlPath<String>) -> Response {
+    match fixtures.project(&id) {
+        Some(project) => Json(project.clone()).into_response(),
+        None => not_found(),
+    }
+}
+
+async fn project_versions(
+    State(fixtures): State<Arc<Fixtures>>,
+    UrlPath(id): UrlPath<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let Some(project) = fixtures.project(&id) else {
+        return not_found();
+    };
+
+    let mut versions = fixtures
+        .versions
+        .iter()
+        .filter(|v| v["project_id"] == project["id"])
+        .filter(|v| matches_filter(v, "loaders", query.get("loaders")))
+        .filter(|v| matches_filter(v, "game_versions", query.get("game_versions")))
+        .cloned()
+        .collect::<Vec<_>>();
+    // newest first
+    versions.sort_by(|a, b| {
+        b["date_published"]
+            .as_str()
+            .cmp(&a["date_published"].as_str())
+    });
+
+    Json(versions).into_response()
+}
+
+async fn version(State(fixtures): State<Arc<Fixtures>>, UrlPath(id): UrlPath<String>) -> Response {
+    match fixtures.versions.iter().find(|v| v["id"] == id) {
+        Some(version) => Json(version.clone()).into_response(),
+        None => not_found(),
+    }
+}
+
+async fn file(State(fixtures): State<Arc<Fixtures>>, UrlPath(name): UrlPath<String>) -> Response {
+    if name.contains(['/', '\\']) || name.starts_with('.') {
+        return not_found();
+    }
+
+    match tokio::fs::read(fixtures.files.join(name)).await {
+        Ok(contents) => contents.into_response(),
+        Err(_) => not_found(),
+    }
+}
+
+pub fn router(fixtures: Fixtures) -> Router {
+    Router::new()
+        .route("/v3/collection/:id", get(collection))
+        .route("/v2/project/:id", get(project))
+        .route("/v2/project/:id/version", get(project_versions))
+        .route("/v2/version/:id", get(version))
+        .route("/files/:name", get(file))
+        .with_state(Arc::new(fixtures))
+}
+
+/// Serves the fixtures in `dir` from `listener`, returning the url of the api root.
+pub async fn serve(listener: tokio::net::TcpListener, dir: impl AsRef<Path>) -> io::Result<String> {
+    let base = format!("http://{}/", listener.local_addr()?);
+    let app = router(Fixtures::load(dir, &base)?);
+
+    tokio::task::spawn(async move { axum::serve(listener, app).await });
+
+    Ok(base)
+}
+
+/// Starts a server on a free local port, for tests.
+pub async fn spawn(dir: impl AsRef<Path>) -> io::Result<String> {
+    serve(tokio::net::TcpListener::bind("127.0.0.1:0").await?, dir).await
+}
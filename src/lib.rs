@@ -1,9 +1,19 @@
 #![feature(pattern)]
 
 pub mod app;
+#[cfg(feature = "ssr")]
+pub mod cli;
+#[cfg(feature = "ssr")]
+pub mod config;
 pub mod error_template;
+#[cfg(all(feature = "ssr", any(test, feature = "fake-modrinth")))]
+pub mod fake_modrinth;
 #[cfg(feature = "ssr")]
 pub mod fileserv;
+#[cfg(feature = "ssr")]
+pub mod rest;
+#[cfg(all(test, feature = "ssr"))]
+pub(crate) mod test_setup;
 
 #[cfg(feature = "hydrate")]
 #[wasm_bindgen::prelude::wasm_bindgen]
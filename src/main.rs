@@ -1,17 +1,38 @@
 #[cfg(feature = "ssr")]
 #[tokio::main]
-async fn main() {
+async fn main() -> std::process::ExitCode {
+    // any arguments mean a command line run, which doesn't start the server
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if !args.is_empty() {
+        return mr_modpack::cli::run(args).await;
+    }
+
     use std::net::{SocketAddr, SocketAddrV4};
-    use std::str::FromStr;
     use std::sync::Arc;
 
     use axum::Router;
     use leptos::leptos_config::Env;
     use leptos::*;
     use leptos_axum::{generate_route_list, LeptosRoutes};
+    use mr_modpack::app::export::{self, ExportJobs, Exporter, JarCache};
+    use mr_modpack::app::history::HistoryStore;
+    use mr_modpack::app::loader_meta;
     use mr_modpack::app::modrinth::*;
+    use mr_modpack::app::packwiz::PackwizImports;
+    use mr_modpack::app::watch::WatchStore;
+    use mr_modpack::app::workspace::WorkspaceStore;
     use mr_modpack::app::*;
+    use mr_modpack::config::Config;
     use mr_modpack::fileserv::file_and_error_handler;
+    use mr_modpack::rest::{self, RestState};
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("invalid config: {err}");
+            return std::process::ExitCode::FAILURE;
+        }
+    };
 
     // Setting get_configuration(None) means we'll be using cargo-leptos's env values
     // For deployment these variables are:
@@ -26,25 +47,134 @@ async fn main() {
         leptos_options.hash_file = "/app/target/release/hash.txt".to_string();
     }
 
-    let addr = match std::env::var("PORT") {
-        Ok(port) => SocketAddr::V4(SocketAddrV4::new(
-            "0.0.0.0".parse().unwrap(),
-            port.parse().expect("`PORT` to be an u16"),
-        )),
-        _ => leptos_options.site_addr,
+    let addr = match config.server.port {
+        Some(port) => SocketAddr::V4(SocketAddrV4::new("0.0.0.0".parse().unwrap(), port)),
+        None => leptos_options.site_addr,
     };
     let cloned_leptos_options = leptos_options.clone();
     let routes = generate_route_list(App);
 
-    let modrinth = Arc::new(ModrinthClient::default());
+    let modrinth = Arc::new(ModrinthClient::from_config(&config.modrinth));
+    // uploaded packs are looked up by id later on, saving them is opt-in
+    let packwiz_imports = Arc::new(match &config.storage.imports {
+        Some(path) => match PackwizImports::open(path.clone()).await {
+            Ok(imports) => imports,
+            Err(err) => return startup_err("storage.imports", err),
+        },
+        None => PackwizImports::default(),
+    });
+
+    // loader versions for instance exports can come from a fixture file (for offline use),
+    // or from a fabric meta server, which can be a local mirror
+    let loader_meta = match loader_meta::from_config(&config.loader_meta) {
+        Ok(loader_meta) => loader_meta,
+        Err(err) => return startup_err("loader_meta.fixture", err),
+    };
+
+    // archives are kept out of the site root, and only handed out by export id
+    let export_dir = config
+        .exports
+        .dir
+        .clone()
+        .unwrap_or_else(|| std::env::temp_dir().join("mr-modpack-exports"));
+    let mut exporter = Exporter::new(modrinth.clone(), loader_meta.clone())
+        .with_output(&export_dir, "/downloads")
+        .with_cleanup_delay(config.exports.cleanup_delay())
+        .with_max_size(config.exports.max_size());
+    if let Some(dir) = &config.cache.dir {
+        let cache = match JarCache::open(dir, config.cache.max_size()).await {
+            Ok(cache) => cache,
+            Err(err) => return startup_err("cache.dir", err),
+        };
+        exporter = exporter.with_cache(Arc::new(cache));
+    }
+    let exporter = Arc::new(exporter);
+    // exports wait in a queue for a free worker, and are saved so finished ones outlive a restart
+    let export_jobs = match ExportJobs::open(
+        exporter.clone(),
+        config.exports.concurrency,
+        export_dir.join("jobs.json"),
+    )
+    .await
+    {
+        Ok(jobs) => Arc::new(jobs),
+        Err(err) => return startup_err("exports.dir", err),
+    };
+    // only once finished archives from before a restart are known, or they'd be swept up
+    exporter
+        .clone()
+        .spawn_janitor(config.exports.janitor_interval());
+
+    // saving workspaces on the server is opt-in, without it they only live in a cookie
+    let workspaces = match &config.storage.workspaces {
+        Some(path) => match WorkspaceStore::open(path.clone()).await {
+            Ok(store) => Some(Arc::new(store)),
+            Err(err) => return startup_err("storage.workspaces", err),
+        },
+        None => None,
+    };
+
+    // coverage is recorded whenever a matrix is worked out, saving it is opt-in
+    let history = Arc::new(match &config.storage.history {
+        Some(path) => {
+            match HistoryStore::open(path.clone(), config.history.snapshot_interval()).await {
+                Ok(history) => history,
+                Err(err) => return startup_err("storage.history", err),
+            }
+        }
+        None => HistoryStore::new(config.history.snapshot_interval()),
+    });
+    history.clone().spawn_saver();
+
+    // watching collections needs somewhere to keep them, so it's opt-in too
+    let watches = match &config.storage.watches {
+        Some(path) => {
+            let mut store = match WatchStore::open(
+                path.clone(),
+                modrinth.clone(),
+                packwiz_imports.clone(),
+                history.clone(),
+            )
+            .await
+            {
+                Ok(store) => store,
+                Err(err) => return startup_err("storage.watches", err),
+            };
+            if let Some(url) = config.watch.webhook() {
+                store = store.with_webhook(url);
+            }
+            let store = Arc::new(store);
+            store.clone().spawn_poller(config.watch.interval());
+            Some(store)
+        }
+        None => None,
+    };
+
+    let rest_state = RestState {
+        modrinth: modrinth.clone(),
+        imports: packwiz_imports.clone(),
+        jobs: export_jobs.clone(),
+    };
 
     // build our application with a route
     let app = Router::new()
+        .nest("/api/v1", rest::router(rest_state))
+        .nest("/downloads", export::download::router(exporter.clone()))
         .leptos_routes_with_context(
             &leptos_options,
             routes,
             move || {
                 provide_context(modrinth.clone());
+                provide_context(packwiz_imports.clone());
+                provide_context(loader_meta.clone());
+                provide_context(export_jobs.clone());
+                provide_context(history.clone());
+                if let Some(workspaces) = &workspaces {
+                    provide_context(workspaces.clone());
+                }
+                if let Some(watches) = &watches {
+                    provide_context(watches.clone());
+                }
                 provide_context(cloned_leptos_options.clone());
             },
             App,
@@ -57,6 +187,15 @@ async fn main() {
     axum::serve(listener, app.into_make_service())
         .await
         .unwrap();
+
+    std::process::ExitCode::SUCCESS
+}
+
+/// Reports a setting that couldn't be used at startup, the way an invalid config is.
+#[cfg(feature = "ssr")]
+fn startup_err(setting: &str, err: impl std::fmt::Display) -> std::process::ExitCode {
+    eprintln!("error: {setting}: {err}");
+    std::process::ExitCode::FAILURE
 }
 
 #[cfg(not(feature = "ssr"))]
@@ -0,0 +1,589 @@
+//! A versioned JSON api for scripts, mounted under `/api/v1`.
+//!
+//! Unlike the server functions the app uses, these routes and their bodies are stable.
+//! Projects are always referred to by their modrinth id or slug here.
+//! The schemas are documented in `openapi.json`, which is served at `/api/v1/openapi.json`.
+
+use std::{collections::HashSet, sync::Arc};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use ferinth::structures::project::{Project, ProjectSupportRange};
+use serde::{Deserialize, Serialize};
+
+use crate::app::{
+    coverage::{self, CollectionData},
+    export::{ExportFormat, ExportJob, ExportJobState, ExportJobs, ExportRequest},
+    modrinth::{ApiErr, ModrinthClient, ProjectKey},
+    packwiz::PackwizImports,
+    workspace::CollectionSettings,
+    SemanticVersion, DEFAULT_LOADER, SUPPORTED_LOADERS,
+};
+
+const OPENAPI: &str = include_str!("openapi.json");
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum RestErr {
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    Upstream(ApiErr),
+}
+
+impl From<ApiErr> for RestErr {
+    fn from(err: ApiErr) -> Self {
+        match err {
+            ApiErr::NotFound => RestErr::NotFound("not found on modrinth".to_string()),
+            ApiErr::ImportExpired => RestErr::NotFound(err.to_string()),
+            err => RestErr::Upstream(err),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl IntoResponse for RestErr {
+    fn into_response(self) -> Response {
+        let status = match self {
+            RestErr::NotFound(_) => StatusCode::NOT_FOUND,
+            RestErr::BadRequest(_) => StatusCode::BAD_REQUEST,
+            RestErr::Conflict(_) => StatusCode::CONFLICT,
+            RestErr::Upstream(_) => StatusCode::BAD_GATEWAY,
+        };
+
+        (
+            status,
+            Json(ErrorBody {
+                error: self.to_string(),
+            }),
+        )
+            .into_response()
+    }
+}
+
+type RestResult<T> = Result<Json<T>, RestErr>;
+
+#[derive(Debug, Clone)]
+pub struct RestState {
+    pub modrinth: Arc<ModrinthClient>,
+    pub imports: Arc<PackwizImports>,
+    pub jobs: Arc<ExportJobs>,
+}
+
+pub fn router<S>(state: RestState) -> Router<S> {
+    Router::new()
+        .route("/openapi.json", get(openapi))
+        .route("/collections/:id", get(collection))
+        .route("/collections/:id/matrix", get(matrix))
+        .route("/collections/:id/recommendation", get(recommendation))
+        .route("/exports", post(create_export))
+        .route("/exports/:id", get(export_job).delete(cancel_export))
+        .with_state(state)
+}
+
+#[derive(Debug, Serialize)]
+struct ProjectSummary {
+    id: String,
+    slug: String,
+    title: String,
+    loaders: Vec<String>,
+    game_versions: Vec<String>,
+    client_side: ProjectSupportRange,
+    server_side: ProjectSupportRange,
+}
+
+impl From<&Project> for ProjectSummary {
+    fn from(project: &Project) -> Self {
+        Self {
+            id: project.id.clone(),
+            slug: project.slug.clone(),
+            title: project.title.clone(),
+            loaders: project.loaders.clone(),
+            game_versions: project.game_versions.clone(),
+            client_side: project.client_side.clone(),
+            server_side: project.server_side.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct CollectionBody {
+    id: String,
+    name: String,
+    description: String,
+    user: String,
+    projects: Vec<ProjectSummary>,
+}
+
+#[derive(Debug, Serialize)]
+struct MatrixColumn {
+    game_version: String,
+    coverage: f64,
+    /// Ids of the projects with a version for this game version
+    projects: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct MatrixBody {
+    collection: String,
+    loader: String,
+    /// Most supported first
+    versions: Vec<MatrixColumn>,
+}
+
+#[derive(Debug, Serialize)]
+struct RecommendationBody {
+    collection: String,
+    loader: String,
+    game_version: String,
+    coverage: f64,
+    supported: Vec<String>,
+    missing: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoaderQuery {
+    loader: Option<String>,
+}
+
+impl LoaderQuery {
+    fn loader(self) -> Result<String, RestErr> {
+        let loader = self.loader.unwrap_or(DEFAULT_LOADER.to_string());
+        if !SUPPORTED_LOADERS.contains(&loader.as_str()) {
+            return Err(RestErr::BadRequest(format!(
+                "loader must be one of {}",
+                SUPPORTED_LOADERS.join(", ")
+            )));
+        }
+        Ok(loader)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportBody {
+    collection: String,
+    game_version: String,
+    format: ExportFormat,
+    #[serde(default)]
+    settings: CollectionSettings,
+    /// Ids or slugs of the projects to export, every project of the collection if missing
+    projects: Option<Vec<String>>,
+}
+
+async fn openapi() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/json")], OPENAPI)
+}
+
+async fn load(state: &RestState, id: &str, loader: &str) -> Result<CollectionData, RestErr> {
+    let collection = coverage::resolve_collection(&state.modrinth, &state.imports, id)
+        .await
+        .map_err(|err| match err {
+            ApiErr::NotFound => RestErr::NotFound(format!("collection {id} not found")),
+            err => err.into(),
+        })?;
+    let projects = coverage::lookup_projects(&state.modrinth, &collection.projects)
+        .await
+        .map_err(|err| match err {
+            // the collection lists a project modrinth no longer has
+            ApiErr::NotFound => {
+                RestErr::NotFound(format!("a project of collection {id} wasn't found"))
+            }
+            err => err.into(),
+        })?;
+
+    Ok(CollectionData::new(collection, projects, loader))
+}
+
+fn project_ids(data: &CollectionData, keys: &HashSet<ProjectKey>) -> Vec<String> {
+    data.projects
+        .iter()
+        .filter(|(key, _)| keys.contains(key))
+        .map(|(_, project)| project.id.clone())
+        .collect()
+}
+
+async fn collection(
+    State(state): State<RestState>,
+    Path(id): Path<String>,
+) -> RestResult<CollectionBody> {
+    let data = load(&state, &id, DEFAULT_LOADER).await?;
+
+    Ok(Json(CollectionBody {
+        id: data.collection.id,
+        name: data.collection.name,
+        description: data.collection.description,
+        user: data.collection.user.0,
+        projects: data.projects.iter().map(|(_, p)| p.into()).collect(),
+    }))
+}
+
+async fn matrix(
+    State(state): State<RestState>,
+    Path(id): Path<String>,
+    Query(query): Query<LoaderQuery>,
+) -> RestResult<MatrixBody> {
+    let loader = query.loader()?;
+    let data = load(&state, &id, &loader).await?;
+
+    let versions = data
+        .available_versions
+        .iter()
+        .map(|(version, keys)| MatrixColumn {
+            game_version: version.to_game_version(),
+            coverage: data.coverage(keys),
+            projects: project_ids(&data, keys),
+        })
+        .collect();
+
+    Ok(Json(MatrixBody {
+        collection: data.collection.id,
+        loader,
+        versions,
+    }))
+}
+
+async fn recommendation(
+    State(state): State<RestState>,
+    Path(id): Path<String>,
+    Query(query): Query<LoaderQuery>,
+) -> RestResult<RecommendationBody> {
+    let loader = query.loader()?;
+    let data = load(&state, &id, &loader).await?;
+
+    let Some((version, keys)) = data.best_version() else {
+        return Err(RestErr::NotFound(format!(
+            "no project in the collection supports {loader}"
+        )));
+    };
+
+    let missing = data
+        .projects
+        .iter()
+        .filter(|(key, _)| !keys.contains(key))
+        .map(|(_, project)| project.id.clone())
+        .collect();
+
+    Ok(Json(RecommendationBody {
+        collection: data.collection.id.clone(),
+        game_version: version.to_game_version(),
+        coverage: data.coverage(keys),
+        supported: project_ids(&data, keys),
+        missing,
+        loader,
+    }))
+}
+
+async fn create_export(
+    State(state): State<RestState>,
+    Json(body): Json<ExportBody>,
+) -> Result<(StatusCode, Json<ExportJob>), RestErr> {
+    if !SUPPORTED_LOADERS.contains(&body.settings.loader.as_str()) {
+        return Err(RestErr::BadRequest(format!(
+            "loader must be one of {}",
+            SUPPORTED_LOADERS.join(", ")
+        )));
+    }
+    state
+        .jobs
+        .exporter()
+        .check_loader(&body.settings.loader, body.format)
+        .map_err(|err| RestErr::BadRequest(err.to_string()))?;
+
+    let release_version = body
+        .game_version
+        .parse::<SemanticVersion>()
+        .map_err(|_| RestErr::BadRequest(format!("{} isn't a game version", body.game_version)))?;
+
+    let data = load(&state, &body.collection, &body.settings.loader).await?;
+
+    let projects = match &body.projects {
+        Some(wanted) => {
+            let mut projects = HashSet::with_capacity(wanted.len());
+            for wanted in wanted {
+                let (key, _) = data
+                    .projects
+                    .iter()
+                    .find(|(_, p)| &p.id == wanted || &p.slug == wanted)
+                    .ok_or_else(|| {
+                        RestErr::BadRequest(format!("{wanted} isn't part of the collection"))
+                    })?;
+                projects.insert(key.clone());
+            }
+            projects
+        }
+        None => data.collection.projects.iter().cloned().collect(),
+    };
+
+    let job = state
+        .jobs
+        .start(ExportRequest {
+            collection_id: data.collection.id,
+            collection_name: data.collection.name,
+            release_version,
+            settings: body.settings,
+            projects,
+            format: body.format,
+        })
+        .await;
+
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+async fn export_job(
+    State(state): State<RestState>,
+    Path(id): Path<String>,
+) -> RestResult<ExportJob> {
+    state
+        .jobs
+        .get(&id)
+        .await
+        .map(Json)
+        .ok_or_else(|| RestErr::NotFound("export job not found".to_string()))
+}
+
+async fn cancel_export(
+    State(state): State<RestState>,
+    Path(id): Path<String>,
+) -> RestResult<ExportJob> {
+    let job = state
+        .jobs
+        .cancel(&id)
+        .await
+        .ok_or_else(|| RestErr::NotFound("export job not found".to_string()))?;
+
+    if job.state != ExportJobState::Cancelled {
+        return Err(RestErr::Conflict(
+            "export job has already finished".to_string(),
+        ));
+    }
+    Ok(Json(job))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        Router,
+    };
+    use serde_json::{json, Value};
+    use tower::ServiceExt as _;
+
+    use super::{router, RestState, OPENAPI};
+    use crate::{
+        app::{
+            export::{self, test_setup::setup, ExportJobs},
+            packwiz::PackwizImports,
+        },
+        test_setup::temp_path,
+    };
+
+    async fn app(output: &str) -> Router {
+        let (api, exporter, _) = setup("fixture1").await;
+        let exporter = Arc::new(exporter.with_output(temp_path(output), "/downloads"));
+
+        let state = RestState {
+            modrinth: api,
+            imports: Arc::new(PackwizImports::default()),
+            jobs: Arc::new(ExportJobs::new(exporter.clone(), 1)),
+        };
+        Router::new()
+            .nest("/api/v1", router(state))
+            .nest("/downloads", export::download::router(exporter))
+    }
+
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, Vec<u8>) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, body.to_vec())
+    }
+
+    async fn get(app: &Router, uri: &str) -> (StatusCode, Value) {
+        let (status, body) = send(app, Request::get(uri).body(Body::empty()).unwrap()).await;
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn openapi_covers_routes() {
+        let doc: serde_json::Value = serde_json::from_str(OPENAPI).unwrap();
+        let paths = doc["paths"].as_object().unwrap();
+
+        for path in [
+            "/api/v1/collections/{id}",
+            "/api/v1/collections/{id}/matrix",
+            "/api/v1/collections/{id}/recommendation",
+            "/api/v1/exports",
+            "/api/v1/exports/{id}",
+            "/downloads/{id}",
+        ] {
+            assert!(paths.contains_key(path), "{path} is undocumented");
+        }
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let app = app("rest-errors").await;
+
+        let (status, body) = get(&app, "/api/v1/collections/missing1").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, json!({ "error": "collection missing1 not found" }));
+
+        let (status, body) = get(&app, "/api/v1/collections/fixture1/matrix?loader=rift").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body,
+            json!({ "error": "loader must be one of fabric, quilt, forge, neoforge" })
+        );
+
+        let (status, body) = get(&app, "/api/v1/exports/missing1").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, json!({ "error": "export job not found" }));
+
+        let (status, body) = get(&app, "/api/v1/collections/packwiz-0123456789abcdef").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            body,
+            json!({ "error": "this packwiz import has expired, upload the pack again" })
+        );
+    }
+
+    #[tokio::test]
+    async fn recommendation() {
+        let app = app("rest-recommendation").await;
+
+        let (status, body) = get(&app, "/api/v1/collections/fixture1/recommendation").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["collection"], "fixture1");
+        assert_eq!(body["loader"], "fabric");
+        assert_eq!(body["game_version"], "1.20.1");
+    }
+
+    async fn start_export(app: &Router, body: Value) -> (StatusCode, Value) {
+        let (status, body) = send(
+            app,
+            Request::post("/api/v1/exports")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await;
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn wait_for(app: &Router, id: &str) -> Value {
+        loop {
+            let (status, job) = get(app, &format!("/api/v1/exports/{id}")).await;
+            assert_eq!(status, StatusCode::OK);
+            if job["status"] != "pending" && job["status"] != "running" {
+                break job;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn export_round_trip() {
+        let app = app("rest-export").await;
+
+        let (status, job) = start_export(
+            &app,
+            json!({
+                "collection": "fixture1",
+                "game_version": "1.20.1",
+                "format": "Mrpack",
+                "projects": ["sodium"],
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let id = job["id"].as_str().unwrap();
+
+        let job = wait_for(&app, id).await;
+        assert_eq!(job["status"], "finished", "{job}");
+
+        let url = job["report"]["url"].as_str().unwrap();
+        let (status, archive) = send(&app, Request::get(url).body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        // every zip starts with "PK"
+        assert_eq!(&archive[..2], b"PK");
+
+        let (status, body) = send(
+            &app,
+            Request::delete(format!("/api/v1/exports/{id}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            serde_json::from_slice::<Value>(&body).unwrap(),
+            json!({ "error": "export job has already finished" })
+        );
+    }
+
+    #[tokio::test]
+    async fn non_fabric_exports() {
+        let app = app("rest-non-fabric").await;
+
+        // the fixture has quilt versions
+        let (status, job) = start_export(
+            &app,
+            json!({
+                "collection": "fixture1",
+                "game_version": "1.20.1",
+                "format": "Mrpack",
+                "settings": { "loader": "quilt" },
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let job = wait_for(&app, job["id"].as_str().unwrap()).await;
+        assert_eq!(job["status"], "finished", "{job}");
+
+        // but none for forge, which is refused before it's queued
+        let (status, body) = start_export(
+            &app,
+            json!({
+                "collection": "fixture1",
+                "game_version": "1.20.1",
+                "format": "Prism",
+                "settings": { "loader": "forge" },
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body,
+            json!({ "error": "forge versions can't be looked up, so only client packs can be exported for it" })
+        );
+
+        let (status, _) = start_export(
+            &app,
+            json!({
+                "collection": "fixture1",
+                "game_version": "1.20.1",
+                "format": "ClientPack",
+                "settings": { "loader": "forge" },
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+    }
+}
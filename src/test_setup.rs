@@ -0,0 +1,17 @@
+//! What the tests start from: a client for the fake api serving the fixtures, and somewhere
+//! to write files to.
+
+use std::path::PathBuf;
+
+use crate::{app::modrinth::ModrinthClient, fake_modrinth};
+
+/// Somewhere in the temp folder, which isn't shared with other test runs.
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mr-modpack-{}-{name}", std::process::id()))
+}
+
+/// A client for a fake api of its own, serving `fixtures/modrinth`.
+pub(crate) async fn fake_api() -> ModrinthClient {
+    let endpoint = fake_modrinth::spawn("fixtures/modrinth").await.unwrap();
+    ModrinthClient::default().with_endpoint(&endpoint)
+}